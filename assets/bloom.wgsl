@@ -0,0 +1,38 @@
+// parameters: 0 threshold, 1 intensity, 2 radius in pixels
+
+const SAMPLE_COUNT: u32 = 48u;
+// spacing between spiral samples, spreads them evenly over the disc without a visible pattern
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let threshold = parameter(0u);
+    let intensity = parameter(1u);
+    let radius = parameter(2u);
+
+    let color = sample_input(in.uv);
+
+    var bloom = vec3<f32>(0.0);
+    var total_weight = 0.0;
+
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        // 0 at the center to 1 at the edge of the disc
+        let t = (f32(i) + 0.5) / f32(SAMPLE_COUNT);
+        let angle = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<f32>(cos(angle), sin(angle)) * sqrt(t) * radius * post_process.texel_size;
+
+        let tap = sample_input(in.uv + offset);
+
+        // only the part above the threshold glows, scaled rather than subtracted so the glow keeps its hue
+        let brightness = max(max(tap.r, tap.g), tap.b);
+        let contribution = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+
+        // roughly gaussian falloff
+        let weight = exp(-3.0 * t);
+
+        bloom += tap * contribution * weight;
+        total_weight += weight;
+    }
+
+    return vec4<f32>(color + bloom / total_weight * intensity, 1.0);
+}
@@ -0,0 +1,16 @@
+// parameters: 0 strength i.e how many pixels the red and blue channels are split by at the edge of the screen
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let strength = parameter(0u);
+
+    // grows towards the edges like a real lens
+    let from_center = in.uv - 0.5;
+    let offset = from_center * 2.0 * strength * post_process.texel_size;
+
+    let red = sample_input(in.uv + offset).r;
+    let green = sample_input(in.uv).g;
+    let blue = sample_input(in.uv - offset).b;
+
+    return vec4<f32>(red, green, blue, 1.0);
+}
@@ -0,0 +1,37 @@
+// parameters: 0 intensity, 1 saturation, 2 contrast
+// effect_texture is a lut of size^3 colors laid out as size slices of size x size side by side, blue picks the slice
+
+fn sample_lut(color: vec3<f32>) -> vec3<f32> {
+    let size = f32(textureDimensions(effect_texture).y);
+    let scaled = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)) * (size - 1.0);
+
+    // half a texel in so the filtering doesn't bleed into the neighbouring slice
+    let x = (scaled.r + 0.5) / (size * size);
+    let y = (scaled.g + 0.5) / size;
+
+    // blue isn't filtered by the sampler because the slices aren't next to each other in the texture
+    let slice = floor(scaled.b);
+    let next_slice = min(slice + 1.0, size - 1.0);
+
+    let low = textureSampleLevel(effect_texture, input_sampler, vec2<f32>(x + slice / size, y), 0.0).rgb;
+    let high = textureSampleLevel(effect_texture, input_sampler, vec2<f32>(x + next_slice / size, y), 0.0).rgb;
+
+    return mix(low, high, scaled.b - slice);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let intensity = parameter(0u);
+    let saturation = parameter(1u);
+    let contrast = parameter(2u);
+
+    let color = sample_input(in.uv);
+
+    // luts are authored against gamma encoded colors
+    var graded = to_perceptual(color);
+    graded = mix(vec3<f32>(luminance(graded)), graded, saturation);
+    graded = (graded - 0.5) * contrast + 0.5;
+    graded = from_perceptual(sample_lut(graded));
+
+    return vec4<f32>(mix(color, graded, intensity), 1.0);
+}
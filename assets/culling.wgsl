@@ -0,0 +1,91 @@
+// tests every instance of a model against the camera frustum and packs the visible ones together for an indirect draw
+// workgroup size must match WORKGROUP_SIZE in culling.rs
+
+struct Culling {
+    // left, right, bottom, top, near, far, xyz = normal pointing into the frustum, w = distance from the origin
+    planes: array<vec4<f32>, 6>,
+    // model space bounding sphere, xyz = center, w = radius
+    sphere: vec4<f32>,
+    // xyz = how far and in which direction shadows are cast, w = 1 if there are shadows to keep
+    sweep: vec4<f32>,
+    instance_count: u32,
+    mesh_count: u32,
+}
+
+// same layout as RawInstance
+struct Instance {
+    model: mat4x4<f32>,
+    fade: f32,
+}
+
+// same layout as wgpu::util::DrawIndexedIndirect
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> culling: Culling;
+@group(0) @binding(1)
+var<storage, read> instances: array<Instance>;
+@group(0) @binding(2)
+var<storage, read_write> visible_instances: array<Instance>;
+// one per mesh
+@group(0) @binding(3)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+fn signed_distance(plane: vec4<f32>, point: vec3<f32>) -> f32 {
+    return dot(plane.xyz, point) + plane.w;
+}
+
+// same as Frustum::intersects_sphere, or intersects_swept_sphere when there is a sweep
+fn is_visible(center: vec3<f32>, radius: f32) -> bool {
+    for (var i = 0u; i < 6u; i += 1u) {
+        let plane = culling.planes[i];
+
+        var furthest = signed_distance(plane, center);
+        if culling.sweep.w > 0.0 {
+            furthest = max(furthest, signed_distance(plane, center + culling.sweep.xyz));
+        }
+
+        if furthest < -radius {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+// one thread per instance, mesh 0's instance count is used as the counter for where the next visible instance goes
+@compute @workgroup_size(64)
+fn cull_instances(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if index >= culling.instance_count {
+        return;
+    }
+
+    let instance = instances[index];
+    let transform = instance.model;
+    let center = (transform * vec4<f32>(culling.sphere.xyz, 1.0)).xyz;
+    // keeps the sphere around the model if the instance is scaled
+    let scale = max(length(transform[0].xyz), max(length(transform[1].xyz), length(transform[2].xyz)));
+
+    if is_visible(center, culling.sphere.w * scale) {
+        let slot = atomicAdd(&draws[0].instance_count, 1u);
+        visible_instances[slot] = instance;
+    }
+}
+
+// every mesh of the model draws the same instances, runs after cull_instances has finished counting them
+@compute @workgroup_size(64)
+fn write_draw_arguments(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let mesh = global_id.x;
+    if mesh == 0u || mesh >= culling.mesh_count {
+        return;
+    }
+
+    atomicStore(&draws[mesh].instance_count, atomicLoad(&draws[0].instance_count));
+}
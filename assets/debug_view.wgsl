@@ -0,0 +1,119 @@
+// the debug views in debug_view.rs, each one is a permutation picked with a define
+// NORMALS, TEXTURE_COORDS, DEPTH, OVERDRAW and MESH_COLORS replace the lit scene
+// WIREFRAME and BARYCENTRIC_WIREFRAME are drawn over whatever is already there
+
+#include "mesh.wgsl"
+
+#ifdef BARYCENTRIC_WIREFRAME
+// see WireframeVertex in model.rs, drawn instead of VertexInput
+struct WireframeVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) barycentric: vec3<f32>,
+}
+#endif
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) barycentric: vec3<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+// one slot per mesh, see DebugViews::mesh_offset
+struct Mesh {
+    index: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> mesh: Mesh;
+
+// how quickly the depth view fades to black with distance from the camera
+const DEPTH_FALLOFF: f32 = 0.05;
+// added for every triangle covering a pixel, tone mapping turns the sum from dark red to white
+const OVERDRAW_STEP: vec3<f32> = vec3<f32>(0.08, 0.02, 0.005);
+const WIREFRAME_COLOR: vec3<f32> = vec3<f32>(0.0, 4.0, 0.0);
+// in pixels
+const WIREFRAME_WIDTH: f32 = 1.0;
+
+@vertex
+fn vs_main(
+#ifdef BARYCENTRIC_WIREFRAME
+    model: WireframeVertexInput,
+#else
+    model: VertexInput,
+#endif
+    instance: InstanceInput
+) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_projection * world_position;
+#ifdef BARYCENTRIC_WIREFRAME
+    out.barycentric = model.barycentric;
+#else
+    // instances are only translated and rotated so the model matrix can transform normals directly
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.tex_coords = model.tex_coords;
+#endif
+    return out;
+}
+
+// pcg hash, neighbouring indices get unrelated colors
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn mesh_color(index: u32) -> vec3<f32> {
+    let hashed = hash(index);
+    return vec3<f32>(
+        f32(hashed & 255u),
+        f32((hashed >> 8u) & 255u),
+        f32((hashed >> 16u) & 255u),
+    ) / 255.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // the preprocessor has no #elif so each view overwrites this
+    var color = vec3<f32>(1.0);
+
+#ifdef NORMALS
+    color = normalize(in.world_normal) * 0.5 + 0.5;
+#endif
+#ifdef TEXTURE_COORDS
+    // repeating textures have coordinates outside 0 to 1
+    color = vec3<f32>(fract(in.tex_coords), 0.0);
+#endif
+#ifdef DEPTH
+    let distance = length(in.world_position - camera.position.xyz);
+    color = vec3<f32>(exp(-distance * DEPTH_FALLOFF));
+#endif
+#ifdef OVERDRAW
+    color = OVERDRAW_STEP;
+#endif
+#ifdef MESH_COLORS
+    color = mesh_color(mesh.index);
+#endif
+#ifdef WIREFRAME
+    color = WIREFRAME_COLOR;
+#endif
+#ifdef BARYCENTRIC_WIREFRAME
+    // a barycentric coordinate is 0 along the edge opposite its corner, fwidth keeps the lines the same width on screen
+    let edge_distance = in.barycentric / fwidth(in.barycentric);
+    if min(edge_distance.x, min(edge_distance.y, edge_distance.z)) > WIREFRAME_WIDTH {
+        discard;
+    }
+    color = WIREFRAME_COLOR;
+#endif
+
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,161 @@
+// fast approximate anti aliasing, based on timothy lottes' fxaa 3.11 quality preset
+// parameters: 0 edge threshold, 1 edge threshold minimum, 2 subpixel blending
+
+const EDGE_SEARCH_STEPS: i32 = 12;
+
+// how far each step along the edge jumps, later steps go further to find long edges quicker
+fn search_step_size(index: i32) -> f32 {
+    if index < 5 {
+        return 1.0;
+    }
+    if index == 5 {
+        return 1.5;
+    }
+    if index < 10 {
+        return 2.0;
+    }
+    if index == 10 {
+        return 4.0;
+    }
+    return 8.0;
+}
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luminance(to_perceptual(sample_input(uv)));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let edge_threshold = parameter(0u);
+    let edge_threshold_min = parameter(1u);
+    let subpixel = parameter(2u);
+
+    let texel = post_process.texel_size;
+    let uv = in.uv;
+
+    let color = sample_input(uv);
+    let luma_center = luminance(to_perceptual(color));
+
+    let luma_up = luma_at(uv + vec2<f32>(0.0, -texel.y));
+    let luma_down = luma_at(uv + vec2<f32>(0.0, texel.y));
+    let luma_left = luma_at(uv + vec2<f32>(-texel.x, 0.0));
+    let luma_right = luma_at(uv + vec2<f32>(texel.x, 0.0));
+
+    let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    let luma_range = luma_max - luma_min;
+
+    // flat areas and dark areas where aliasing isn't noticeable are left alone
+    if luma_range < max(edge_threshold_min, luma_max * edge_threshold) {
+        return vec4<f32>(color, 1.0);
+    }
+
+    let luma_up_left = luma_at(uv + vec2<f32>(-texel.x, -texel.y));
+    let luma_up_right = luma_at(uv + vec2<f32>(texel.x, -texel.y));
+    let luma_down_left = luma_at(uv + vec2<f32>(-texel.x, texel.y));
+    let luma_down_right = luma_at(uv + vec2<f32>(texel.x, texel.y));
+
+    let luma_up_down = luma_up + luma_down;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_up_left + luma_down_left;
+    let luma_right_corners = luma_up_right + luma_down_right;
+    let luma_up_corners = luma_up_left + luma_up_right;
+    let luma_down_corners = luma_down_left + luma_down_right;
+
+    // which way the luma changes the most
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_up_down) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // the pixels on either side of the edge
+    let luma_negative = select(luma_left, luma_up, is_horizontal);
+    let luma_positive = select(luma_right, luma_down, is_horizontal);
+    let gradient_negative = abs(luma_negative - luma_center);
+    let gradient_positive = abs(luma_positive - luma_center);
+    let gradient_scaled = 0.25 * max(gradient_negative, gradient_positive);
+
+    // steps across the edge towards the side it is steepest on
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average: f32;
+    if gradient_negative >= gradient_positive {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_negative + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_positive + luma_center);
+    }
+
+    // halfway between this pixel and its neighbour so bilinear filtering averages both sides of the edge
+    var edge_uv = uv;
+    if is_horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+
+    // walk along the edge in both directions until the luma changes enough to be the end of it
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+    var uv_negative = edge_uv - offset;
+    var uv_positive = edge_uv + offset;
+    var luma_end_negative = luma_at(uv_negative) - luma_local_average;
+    var luma_end_positive = luma_at(uv_positive) - luma_local_average;
+    var reached_negative = abs(luma_end_negative) >= gradient_scaled;
+    var reached_positive = abs(luma_end_positive) >= gradient_scaled;
+
+    if !reached_negative {
+        uv_negative -= offset;
+    }
+    if !reached_positive {
+        uv_positive += offset;
+    }
+
+    for (var i = 1; i < EDGE_SEARCH_STEPS && !(reached_negative && reached_positive); i += 1) {
+        if !reached_negative {
+            luma_end_negative = luma_at(uv_negative) - luma_local_average;
+            reached_negative = abs(luma_end_negative) >= gradient_scaled;
+        }
+        if !reached_positive {
+            luma_end_positive = luma_at(uv_positive) - luma_local_average;
+            reached_positive = abs(luma_end_positive) >= gradient_scaled;
+        }
+
+        if !reached_negative {
+            uv_negative -= offset * search_step_size(i);
+        }
+        if !reached_positive {
+            uv_positive += offset * search_step_size(i);
+        }
+    }
+
+    let distance_negative = select(uv.y - uv_negative.y, uv.x - uv_negative.x, is_horizontal);
+    let distance_positive = select(uv_positive.y - uv.y, uv_positive.x - uv.x, is_horizontal);
+    let is_negative_closer = distance_negative < distance_positive;
+    let edge_length = distance_negative + distance_positive;
+
+    // pixels near the end of the edge are blended the most
+    let pixel_offset = -min(distance_negative, distance_positive) / edge_length + 0.5;
+
+    // only blend if the closest end of the edge goes the same way as this pixel
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let luma_end = select(luma_end_positive, luma_end_negative, is_negative_closer);
+    let correct_variation = (luma_end < 0.0) != is_luma_center_smaller;
+    var final_offset = select(0.0, pixel_offset, correct_variation);
+
+    // thin features smaller than a pixel are blended with their surroundings instead
+    let luma_average = (1.0 / 12.0) * (2.0 * (luma_up_down + luma_left_right) + luma_left_corners + luma_right_corners);
+    let subpixel_offset = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    let subpixel_offset_smooth = (-2.0 * subpixel_offset + 3.0) * subpixel_offset * subpixel_offset;
+    final_offset = max(final_offset, subpixel_offset_smooth * subpixel_offset_smooth * subpixel);
+
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+
+    return vec4<f32>(sample_input(final_uv), 1.0);
+}
@@ -0,0 +1,103 @@
+// must match BIN_COUNT in tone_mapping.rs, also the number of threads in a workgroup
+const BIN_COUNT: u32 = 256u;
+
+struct Histogram {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // how far to move towards this frame's exposure, 0 = not at all, 1 = straight to it
+    adaptation: f32,
+    // exposure compensation as a multiplier
+    compensation: f32,
+    pixel_count: u32,
+}
+
+struct Exposure {
+    average_luminance: f32,
+    // negative before the first frame so that it starts at the right value instead of adapting from nothing
+    exposure: f32,
+}
+
+@group(0) @binding(0)
+var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> histogram_settings: Histogram;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, BIN_COUNT>;
+@group(0) @binding(3)
+var<storage, read_write> exposure: Exposure;
+
+var<workgroup> workgroup_histogram: array<atomic<u32>, BIN_COUNT>;
+
+// bin 0 is reserved for pixels too dark to count, the rest are spread evenly over log luminance
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+
+    if luminance < 0.0001 {
+        return 0u;
+    }
+
+    let log_luminance = clamp((log2(luminance) - histogram_settings.min_log_luminance) / histogram_settings.log_luminance_range, 0.0, 1.0);
+    return u32(log_luminance * 254.0 + 1.0);
+}
+
+// each workgroup counts a 16x16 tile in shared memory then adds it to the global histogram, far fewer global atomics
+@compute @workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    atomicStore(&workgroup_histogram[local_index], 0u);
+    workgroupBarrier();
+
+    let size = vec2<u32>(textureDimensions(hdr_texture));
+    if global_id.x < size.x && global_id.y < size.y {
+        let color = textureLoad(hdr_texture, vec2<i32>(global_id.xy), 0).rgb;
+        atomicAdd(&workgroup_histogram[luminance_bin(color)], 1u);
+    }
+
+    workgroupBarrier();
+    atomicAdd(&histogram[local_index], atomicLoad(&workgroup_histogram[local_index]));
+}
+
+// one workgroup with a thread per bin, sums up the weighted bins and clears the histogram for the next frame
+@compute @workgroup_size(256)
+fn average_histogram(@builtin(local_invocation_index) local_index: u32) {
+    let count = atomicLoad(&histogram[local_index]);
+    atomicStore(&histogram[local_index], 0u);
+
+    atomicStore(&workgroup_histogram[local_index], count * local_index);
+    workgroupBarrier();
+
+    // parallel reduction, halves the number of threads adding each step
+    for (var cutoff = BIN_COUNT >> 1u; cutoff > 0u; cutoff >>= 1u) {
+        if local_index < cutoff {
+            atomicAdd(&workgroup_histogram[local_index], atomicLoad(&workgroup_histogram[local_index + cutoff]));
+        }
+        workgroupBarrier();
+    }
+
+    if local_index == 0u {
+        // count is bin 0 for this thread i.e the pixels that were too dark
+        let lit_pixel_count = histogram_settings.pixel_count - count;
+
+        // nothing to measure, keep the last exposure
+        if lit_pixel_count == 0u {
+            return;
+        }
+
+        // mean bin from 1 to 255, mapped back to log luminance
+        let mean_bin = f32(atomicLoad(&workgroup_histogram[0])) / f32(lit_pixel_count);
+        let log_average = (mean_bin - 1.0) / 254.0 * histogram_settings.log_luminance_range + histogram_settings.min_log_luminance;
+        let average_luminance = exp2(log_average);
+
+        // maps the average luminance to middle grey
+        let target_exposure = 0.18 / average_luminance * histogram_settings.compensation;
+
+        exposure.average_luminance = average_luminance;
+        if exposure.exposure < 0.0 {
+            exposure.exposure = target_exposure;
+        } else {
+            exposure.exposure = mix(exposure.exposure, target_exposure, histogram_settings.adaptation);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// what the shaders that draw meshes share, #include it instead of copying these
+
+// see ModelVertex in model.rs
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>
+}
+
+// see RawInstance in instance.rs
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) fade: f32,
+}
+
+// see CameraUniform in camera.rs, each shader binds it where its own layout puts it
+struct Camera {
+    view_projection: mat4x4<f32>,
+    position: vec4<f32>,
+}
+
+fn instance_model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
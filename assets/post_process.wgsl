@@ -0,0 +1,77 @@
+// shared by every post processing effect, the effect's own shader is appended to this so it only has to define fs_main
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// one triangle big enough to cover the screen, (-1, -1), (3, -1), (-1, 3)
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // clip space y points up but texture v points down
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+struct PostProcess {
+    // the effect's parameters in the order they were registered, read with parameter()
+    parameters: array<vec4<f32>, 2>,
+    // 1 / size of the input in pixels
+    texel_size: vec2<f32>,
+    // the input holds linear colors that the hardware converts to srgb on write
+    output_is_srgb: u32,
+}
+
+@group(0) @binding(0)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var input_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> post_process: PostProcess;
+// extra texture for effects that need one e.g the color grading lut, 1x1 white otherwise
+@group(0) @binding(3)
+var effect_texture: texture_2d<f32>;
+
+fn parameter(index: u32) -> f32 {
+    return post_process.parameters[index / 4u][index % 4u];
+}
+
+// explicit level so it can be used in loops and branches
+fn sample_input(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(input_texture, input_sampler, uv, 0.0).rgb;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+// gamma encoded color, closer to how bright things look than linear values are
+fn to_perceptual(color: vec3<f32>) -> vec3<f32> {
+    if post_process.output_is_srgb != 0u {
+        return linear_to_srgb(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)));
+    }
+    return color;
+}
+
+fn from_perceptual(color: vec3<f32>) -> vec3<f32> {
+    if post_process.output_is_srgb != 0u {
+        return srgb_to_linear(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)));
+    }
+    return color;
+}
@@ -1,48 +1,315 @@
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec2<f32>
-}
-
-struct InstanceInput {
-    @location(3) model_matrix_0: vec4<f32>,
-    @location(4) model_matrix_1: vec4<f32>,
-    @location(5) model_matrix_2: vec4<f32>,
-    @location(6) model_matrix_3: vec4<f32>
-}
+#include "mesh.wgsl"
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+    @location(5) fade: f32,
 }
 
 @group(1) @binding(0)
-var<uniform> view_projection: mat4x4<f32>;
+var<uniform> camera: Camera;
 
 @vertex
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput
 ) -> VertexOutput {
-    let instance_model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
+    let model_matrix = instance_model_matrix(instance);
+
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
 
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = view_projection * instance_model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    // instances are only translated and rotated so the model matrix can transform normals directly
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.world_tangent = (model_matrix * vec4<f32>(model.tangent, 0.0)).xyz;
+    out.world_bitangent = (model_matrix * vec4<f32>(model.bitangent, 0.0)).xyz;
+    out.clip_position = camera.view_projection * world_position;
+    out.fade = instance.fade;
     return out;
 }
 
+struct Material {
+    ambient: vec3<f32>,
+    shininess: f32,
+    diffuse: vec3<f32>,
+    dissolve: f32,
+    specular: vec3<f32>,
+    metallic: f32,
+    emissive: vec3<f32>,
+    roughness: f32,
+}
+
 @group(0) @binding(0)
 var diffuse_texture: texture_2d<f32>;
 @group(0) @binding(1)
 var diffuse_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> material: Material;
+@group(0) @binding(3)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(4)
+var normal_sampler: sampler;
+
+// must match MAX_LIGHTS in light.rs
+const MAX_LIGHTS: u32 = 16u;
+
+struct Light {
+    // w = 0 for directional lights (xyz = direction), w = 1 for point lights (xyz = position)
+    position: vec4<f32>,
+    color: vec3<f32>,
+    intensity: f32,
+    range: f32,
+}
+
+struct Lights {
+    ambient: vec3<f32>,
+    count: u32,
+    lights: array<Light, MAX_LIGHTS>,
+}
+
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+// must match NO_SHADOW_LIGHT in shadow.rs
+const NO_SHADOW_LIGHT: u32 = 0xffffffffu;
+// must match MAX_CASCADES in shadow.rs
+const MAX_CASCADES: u32 = 4u;
+
+// must match CASCADE_DEBUG_COLORS in shadow.rs
+const CASCADE_DEBUG_COLORS = array<vec3<f32>, MAX_CASCADES>(
+    vec3<f32>(1.0, 0.3, 0.3),
+    vec3<f32>(0.3, 1.0, 0.3),
+    vec3<f32>(0.3, 0.3, 1.0),
+    vec3<f32>(1.0, 1.0, 0.3),
+);
+
+struct Cascade {
+    view_projection: mat4x4<f32>,
+    // view depth of the cascade's far end
+    split_depth: f32,
+    texel_world_size: f32,
+}
+
+struct Shadow {
+    cascades: array<Cascade, MAX_CASCADES>,
+    camera_forward: vec4<f32>,
+    comparison_bias: f32,
+    texel_size: f32,
+    pcf_radius: u32,
+    // index into lights of the light casting the shadow
+    light_index: u32,
+    // in texels of the cascade being sampled
+    normal_bias: f32,
+    cascade_count: u32,
+    blend_fraction: f32,
+    debug_cascades: u32,
+}
+
+@group(3) @binding(0)
+var shadow_map: texture_depth_2d_array;
+@group(3) @binding(1)
+var shadow_sampler: sampler_comparison;
+@group(3) @binding(2)
+var<uniform> shadow: Shadow;
+
+// which cascade covers a view depth and how far to blend into the next one, index = cascade_count if none do
+struct CascadeSelection {
+    index: u32,
+    blend: f32,
+}
+
+fn select_cascade(world_position: vec3<f32>) -> CascadeSelection {
+    let depth = dot(world_position - camera.position.xyz, shadow.camera_forward.xyz);
+
+    var selection = CascadeSelection(shadow.cascade_count, 0.0);
+    var near = 0.0;
+
+    for (var i = 0u; i < min(shadow.cascade_count, MAX_CASCADES); i++) {
+        let far = shadow.cascades[i].split_depth;
+
+        if depth < far {
+            let blend_start = far - (far - near) * shadow.blend_fraction;
+            selection.index = i;
+            selection.blend = clamp((depth - blend_start) / (far - blend_start), 0.0, 1.0);
+            break;
+        }
+
+        near = far;
+    }
+
+    return selection;
+}
+
+// 0 = fully in shadow, 1 = fully lit
+fn cascade_shadow_factor(cascade_index: u32, world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    // past the last cascade everything is lit
+    if cascade_index >= min(shadow.cascade_count, MAX_CASCADES) {
+        return 1.0;
+    }
+
+    let cascade = shadow.cascades[cascade_index];
+
+    let offset_position = world_position + world_normal * shadow.normal_bias * cascade.texel_world_size;
+    let light_space_position = cascade.view_projection * vec4<f32>(offset_position, 1.0);
+    let ndc = light_space_position.xyz / light_space_position.w;
+    // ndc y points up, texture v points down
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+
+    // anything the shadow map doesn't cover is lit
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let depth = ndc.z - shadow.comparison_bias;
+    let radius = i32(shadow.pcf_radius);
+
+    // percentage closer filtering, average the comparisons of the surrounding texels
+    var lit = 0.0;
+    for (var x = -radius; x <= radius; x++) {
+        for (var y = -radius; y <= radius; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade_index, depth);
+        }
+    }
+
+    let samples = f32((2 * radius + 1) * (2 * radius + 1));
+    return lit / samples;
+}
+
+fn shadow_factor(world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    if shadow.light_index == NO_SHADOW_LIGHT {
+        return 1.0;
+    }
+
+    let selection = select_cascade(world_position);
+    var lit = cascade_shadow_factor(selection.index, world_position, world_normal);
+
+    // fade into the next cascade so the change in resolution isn't a hard line, the last cascade fades out to lit
+    if selection.blend > 0.0 {
+        let next_lit = cascade_shadow_factor(selection.index + 1u, world_position, world_normal);
+        lit = mix(lit, next_lit, selection.blend);
+    }
+
+    return lit;
+}
+
+fn cascade_debug_color(world_position: vec3<f32>) -> vec3<f32> {
+    let selection = select_cascade(world_position);
+
+    if selection.index >= min(shadow.cascade_count, MAX_CASCADES) {
+        return vec3<f32>(1.0);
+    }
+
+    var colors = CASCADE_DEBUG_COLORS;
+    var next_color = vec3<f32>(1.0);
+    if selection.index + 1u < min(shadow.cascade_count, MAX_CASCADES) {
+        next_color = colors[selection.index + 1u];
+    }
+
+    return mix(colors[selection.index], next_color, selection.blend);
+}
+
+// blinn-phong contribution of a single light
+fn shade(light: Light, base_color: vec3<f32>, normal: vec3<f32>, view_direction: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    var light_direction: vec3<f32>;
+    var attenuation = 1.0;
+
+    if light.position.w == 0.0 {
+        light_direction = normalize(-light.position.xyz);
+    } else {
+        let to_light = light.position.xyz - world_position;
+        let distance = length(to_light);
+        light_direction = to_light / distance;
+
+        // inverse square falloff windowed so that it reaches exactly zero at the light's range
+        let window = clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+        attenuation = window * window / (distance * distance + 1.0);
+    }
+
+    let diffuse_strength = max(dot(normal, light_direction), 0.0);
+
+    let half_direction = normalize(light_direction + view_direction);
+    var specular_strength = pow(max(dot(normal, half_direction), 0.0), max(material.shininess, 1.0));
+    // no highlights on faces pointing away from the light
+    if diffuse_strength <= 0.0 {
+        specular_strength = 0.0;
+    }
+
+    let radiance = light.color * light.intensity * attenuation;
+
+    return (base_color * diffuse_strength + material.specular * specular_strength) * radiance;
+}
+
+// 4x4 ordered dither thresholds, spread out so neighbouring pixels fade at different times
+const DITHER = array<f32, 16>(
+    0.0, 8.0, 2.0, 10.0,
+    12.0, 4.0, 14.0, 6.0,
+    3.0, 11.0, 1.0, 9.0,
+    15.0, 7.0, 13.0, 5.0,
+);
+
+// fade > 0 keeps pixels with a threshold below it, fade < 0 keeps the rest
+// the lod fading out and the one fading in get opposite signs so they never draw the same pixel
+fn is_faded_out(pixel: vec2<f32>, fade: f32) -> bool {
+    let cell = vec2<u32>(pixel) % 4u;
+    var thresholds = DITHER;
+    let threshold = (thresholds[cell.y * 4u + cell.x] + 0.5) / 16.0;
+
+    if fade > 0.0 {
+        return threshold >= fade;
+    }
+    return threshold < -fade;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
-}
\ No newline at end of file
+    let texture_color = textureSample(diffuse_texture, diffuse_sampler, in.tex_coords);
+    let base_color = texture_color.rgb * material.diffuse;
+
+    // normal map is in tangent space, rgb 0 to 1 maps to xyz -1 to 1
+    let tangent_normal = textureSample(normal_texture, normal_sampler, in.tex_coords).xyz * 2.0 - 1.0;
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let normal = normalize(tangent_matrix * tangent_normal);
+    let view_direction = normalize(camera.position.xyz - in.world_position);
+
+    var color = lights.ambient * material.ambient * base_color + material.emissive;
+
+    let shadow_lit = shadow_factor(in.world_position, normalize(in.world_normal));
+
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i++) {
+        var light_color = shade(lights.lights[i], base_color, normal, view_direction, in.world_position);
+
+        if i == shadow.light_index {
+            light_color *= shadow_lit;
+        }
+
+        color += light_color;
+    }
+
+    if shadow.debug_cascades != 0u {
+        color *= cascade_debug_color(in.world_position);
+    }
+
+    // after the texture samples, they have to be in uniform control flow
+    if is_faded_out(in.clip_position.xy, in.fade) {
+        discard;
+    }
+
+    // the scene isn't sorted back to front so it can't be blended, see-through materials keep a dithered share of their pixels instead
+    let alpha = texture_color.a * material.dissolve;
+    if alpha < 1.0 && is_faded_out(in.clip_position.xy, alpha) {
+        discard;
+    }
+
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,13 @@
+#include "mesh.wgsl"
+
+// light view projection of the cascade being rendered
+@group(0) @binding(0)
+var<uniform> light_view_projection: mat4x4<f32>;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput
+) -> @builtin(position) vec4<f32> {
+    return light_view_projection * instance_model_matrix(instance) * vec4<f32>(model.position, 1.0);
+}
@@ -0,0 +1,98 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// one triangle big enough to cover the screen, (-1, -1), (3, -1), (-1, 3)
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+struct ToneMapping {
+    curve: u32,
+    auto_exposure: u32,
+    // linear multiplier used when auto_exposure is off
+    manual_exposure: f32,
+    // 1 = leave as is, the hardware does the srgb conversion for srgb surfaces
+    gamma: f32,
+}
+
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+}
+
+@group(0) @binding(0)
+var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tone_mapping: ToneMapping;
+@group(0) @binding(2)
+var<storage, read> auto_exposure: Exposure;
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// krzysztof narkowicz's fit of the aces reference curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// john hable's uncharted 2 curve
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn filmic(color: vec3<f32>) -> vec3<f32> {
+    // linear white point, anything brighter than this is white
+    let white = 11.2;
+    return hable(color * 2.0) / hable(vec3<f32>(white));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // same size as the target so no filtering is needed
+    let hdr_color = textureLoad(hdr_texture, vec2<i32>(in.clip_position.xy), 0).rgb;
+
+    var exposure = tone_mapping.manual_exposure;
+    // negative until the histogram has measured a frame
+    if tone_mapping.auto_exposure != 0u && auto_exposure.exposure >= 0.0 {
+        exposure = auto_exposure.exposure;
+    }
+
+    let exposed = hdr_color * exposure;
+
+    // must match ToneMappingOperator::index in tone_mapping.rs
+    var mapped: vec3<f32>;
+    switch tone_mapping.curve {
+        // reinhard
+        case 0u: {
+            mapped = reinhard(exposed);
+        }
+        // filmic
+        case 2u: {
+            mapped = filmic(exposed);
+        }
+        // aces
+        default: {
+            mapped = aces(exposed);
+        }
+    }
+
+    return vec4<f32>(pow(mapped, vec3<f32>(1.0 / tone_mapping.gamma)), 1.0);
+}
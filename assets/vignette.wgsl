@@ -0,0 +1,16 @@
+// parameters: 0 intensity, 1 radius, 2 smoothness
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let intensity = parameter(0u);
+    let radius = parameter(1u);
+    let smoothness = max(parameter(2u), 0.001);
+
+    let color = sample_input(in.uv);
+
+    // 0 at the center to 1 in the corners
+    let from_center = length(in.uv - 0.5) * sqrt(2.0);
+    let vignette = 1.0 - smoothstep(radius - smoothness, radius, from_center);
+
+    return vec4<f32>(color * mix(1.0, vignette, intensity), 1.0);
+}
@@ -0,0 +1,125 @@
+// offline version of the lods load_lod_group generates with Model::load_simplified, writes name_lod1.obj, name_lod2.obj... next to the model
+// load_lod_group picks those up instead of simplifying the model every time it is loaded
+// usage: simplify <model.obj> [ratios...], e.g simplify cube.obj 0.5 0.25
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use shooter_game::common::import;
+use shooter_game::common::model::MeshData;
+
+const DEFAULT_RATIOS: [f32; 3] = [0.5, 0.25, 0.125];
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .ok_or_else(|| eyre!("Usage: simplify <model.obj> [ratios...]"))?;
+
+    let ratios = args
+        .map(|ratio| {
+            ratio
+                .parse::<f32>()
+                .map_err(|_| eyre!("Ratio \"{}\" is not a number", ratio))
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    let ratios = if ratios.is_empty() {
+        DEFAULT_RATIOS.to_vec()
+    } else {
+        ratios
+    };
+
+    let obj_file = import::load_obj(&path, &import::ImportSettings::default())?;
+    for warning in obj_file.warnings.iter() {
+        println!("{}", warning);
+    }
+
+    let meshes = obj_file.meshes;
+    // a missing mtl file only loses the usemtl lines
+    let materials = obj_file.materials.unwrap_or_default();
+
+    // the lods share the original's materials
+    let mtllib = std::fs::read_to_string(&path)?
+        .lines()
+        .find(|line| line.starts_with("mtllib "))
+        .map(str::to_string);
+
+    let path = Path::new(&path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    for (i, &ratio) in ratios.iter().enumerate() {
+        let lod_meshes = meshes
+            .iter()
+            .map(|mesh| mesh.simplified(ratio))
+            .collect::<Vec<MeshData>>();
+
+        let lod_path = path.with_file_name(format!("{stem}_lod{}.obj", i + 1));
+        std::fs::write(
+            &lod_path,
+            to_obj(&lod_meshes, mtllib.as_deref(), &materials)?,
+        )?;
+
+        println!(
+            "Wrote \"{}\" with {} triangles from {}",
+            lod_path.display(),
+            triangle_count(&lod_meshes),
+            triangle_count(&meshes)
+        );
+    }
+
+    Ok(())
+}
+
+fn triangle_count(meshes: &[MeshData]) -> usize {
+    meshes.iter().map(|mesh| mesh.indices.len() / 3).sum()
+}
+
+fn to_obj(
+    meshes: &[MeshData],
+    mtllib: Option<&str>,
+    materials: &[tobj::Material],
+) -> Result<String> {
+    let mut obj = String::new();
+
+    if let Some(mtllib) = mtllib {
+        writeln!(obj, "{mtllib}")?;
+    }
+
+    // obj indices start at 1 and carry on across objects
+    let mut first_index = 1;
+
+    for mesh in meshes {
+        writeln!(obj, "o {}", mesh.name)?;
+
+        for vertex in mesh.vertices.iter() {
+            let [x, y, z] = vertex.position;
+            writeln!(obj, "v {x} {y} {z}")?;
+        }
+        for vertex in mesh.vertices.iter() {
+            let [u, v] = vertex.texture_coords;
+            writeln!(obj, "vt {u} {v}")?;
+        }
+        for vertex in mesh.vertices.iter() {
+            let [x, y, z] = vertex.normal;
+            writeln!(obj, "vn {x} {y} {z}")?;
+        }
+
+        if let Some(material) = materials.get(mesh.material_index) {
+            writeln!(obj, "usemtl {}", material.name)?;
+        }
+
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + first_index);
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+
+        first_index += mesh.vertices.len() as u32;
+    }
+
+    Ok(obj)
+}
@@ -1,7 +1,9 @@
 use super::context::RenderingContext;
 use color_eyre::Result;
 
+// what the editor and game add on top of the scene ApplicationHandler draws
 pub trait Application {
     fn new(context: &RenderingContext) -> Self;
-    fn render(&self, render_pass: &mut wgpu::RenderPass) -> Result<()>;
+    // into the main pass after the scene, the hdr and depth textures are bound
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) -> Result<()>;
 }
@@ -1,90 +1,81 @@
+use std::rc::Rc;
+
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::Result;
+use winit::event::*;
+use winit::event_loop::{ControlFlow, EventLoop};
+
+use crate::common::model::DrawModels;
 use crate::common::*;
 
 pub struct ApplicationHandler<App: application::Application> {
     app: App,
     context: context::RenderingContext,
-    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    // None until shader.wgsl loads, the scene isn't drawn and the error is shown in the gui
+    render_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    pipeline_cache: pipeline::PipelineCache,
+    // the shader render_pipeline was last created from, the file is watched so edits show up without restarting
+    shader_handle: asset::Handle<pipeline::Shader>,
+    shader: Option<Rc<pipeline::Shader>>,
     camera: camera::Camera,
-    view_projection_buffer: wgpu::Buffer,
-    view_projection_bind_group: wgpu::BindGroup,
-    texture_bind_group_layout: wgpu::BindGroupLayout,
-    texture_atlas: texture::TextureAtlas,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    material_bind_group_layout: wgpu::BindGroupLayout,
+    asset_server: asset::AssetServer,
+    file_watcher: hot_reload::FileWatcher,
     time: time::Time,
     render_data: render::RenderData,
+    shadow_map: shadow::ShadowMap,
+    // None if the adapter can't run it, instances are culled on the cpu instead
+    gpu_culling: Option<culling::GpuCulling>,
+    tone_mapping: tone_mapping::ToneMapping,
+    // None if it couldn't be made, the tone mapped scene is drawn straight to the surface instead
+    post_process: Option<post_process::PostProcessStack>,
+    debug_views: debug_view::DebugViews,
     gui: gui::Gui,
 }
 
-impl<App> ApplicationHandler<App> {
-    async fn new(window: winit::window::Window) -> Self {
-        let context = context::RenderingContext::new(window).await;
+impl<App: application::Application> ApplicationHandler<App> {
+    // falls back to no msaa if the adapter doesn't support it
+    const DEFAULT_SAMPLE_COUNT: u32 = 4;
 
-        // bind group describes a set of resources and how they can be accessed by the shaders
-        let texture_bind_group_layout =
-            context
-                .wgpu
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                // IDK
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                multisampled: false,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            // WHAT?
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
+    pub async fn new(window: winit::window::Window) -> Self {
+        let mut context = context::RenderingContext::new(window, Self::DEFAULT_SAMPLE_COUNT).await;
+
+        let material_bind_group_layout =
+            pipeline::create_material_bind_group_layout(&context.wgpu.device);
 
-        let view_projection_buffer = context.wgpu.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("view_projection_buffer"),
-            size: std::mem::size_of::<MatrixUniform>() as u64,
+        let camera_buffer = context.wgpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("camera_buffer"),
+            size: std::mem::size_of::<camera::CameraUniform>() as u64,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
-        let view_projection_bind_group_layout =
-            context
-                .wgpu
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: Some("view_projection_bind_group_layout"),
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                });
+        let camera_bind_group_layout =
+            pipeline::create_camera_bind_group_layout(&context.wgpu.device);
 
-        let view_projection_bind_group =
+        let camera_bind_group =
             context
                 .wgpu
                 .device
                 .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some("view_projection_bind_group"),
-                    layout: &view_projection_bind_group_layout,
+                    label: Some("camera_bind_group"),
+                    layout: &camera_bind_group_layout,
                     entries: &[wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: view_projection_buffer.as_entire_binding(),
+                        resource: camera_buffer.as_entire_binding(),
                     }],
                 });
 
+        let lights_bind_group_layout =
+            pipeline::create_lights_bind_group_layout(&context.wgpu.device);
+
+        let shadow_bind_group_layout =
+            pipeline::create_shadow_bind_group_layout(&context.wgpu.device);
+
         let render_pipeline_layout =
             context
                 .wgpu
@@ -92,23 +83,48 @@ impl<App> ApplicationHandler<App> {
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("render_pipeline_layout"),
                     bind_group_layouts: &[
-                        &texture_bind_group_layout,
-                        &view_projection_bind_group_layout,
+                        &material_bind_group_layout,
+                        &camera_bind_group_layout,
+                        &lights_bind_group_layout,
+                        &shadow_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
 
-        let render_pipeline = pipeline::create_pipeline(
+        let mut asset_server = asset::AssetServer::new();
+
+        let shader_handle = asset_server.load_shader(
+            "shader.wgsl",
+            &pipeline::ShaderDefines::new(),
             &context.wgpu.device,
-            &context.wgpu.config,
-            &render_pipeline_layout,
-            // TODO HELP!
-            wgpu::include_wgsl!("../../assets/shader.wgsl"),
-            &[
-                model::ModelVertex::buffer_layout(),
-                instance::RawInstance::buffer_layout(),
-            ],
         );
+        let mut shader_error = match asset_server.load_state(&shader_handle) {
+            asset::LoadState::Loaded => None,
+            asset::LoadState::Failed(error) | asset::LoadState::ReloadFailed(error) => Some(error),
+        };
+
+        // if the file on disk is broken or doesn't fit the pipeline nothing is drawn until it's fixed, it's still watched and reloaded
+        let mut pipeline_cache = pipeline::PipelineCache::new();
+        let (render_pipeline, shader) = match asset_server.get(&shader_handle) {
+            Some(shader) => match Self::create_render_pipeline(
+                &mut pipeline_cache,
+                &context.wgpu,
+                &render_pipeline_layout,
+                &shader.module,
+            ) {
+                Ok(render_pipeline) => (Some(render_pipeline), Some(shader)),
+                Err(error) => {
+                    let error = format!(
+                        "Couldn't create render pipeline from \"{}\": {error}",
+                        shader.path
+                    );
+                    log::error!("{error}");
+                    shader_error = Some(error);
+                    (None, None)
+                }
+            },
+            None => (None, None),
+        };
 
         let camera = camera::Camera {
             position: (0.0, 2.0, 2.0).into(),
@@ -122,50 +138,251 @@ impl<App> ApplicationHandler<App> {
             z_far: 100.0,
         };
 
-        let gui = gui::Gui::new(&context).unwrap();
+        let mut gui = gui::Gui::new(&context).unwrap();
+        if let Some(shader_error) = shader_error {
+            gui.state.shader_errors.insert("scene", shader_error);
+        }
+
+        let map = asset_server.load_model("map.obj", &material_bind_group_layout, &context.wgpu);
 
-        let mut texture_atlas = texture::TextureAtlas::new();
+        let mut render_data =
+            render::RenderData::new(&context.wgpu.device, &lights_bind_group_layout);
 
-        let cube = model::Model::load(
-            "cube.obj".to_string(),
-            &mut texture_atlas,
-            &texture_bind_group_layout,
+        // models that failed to load have already been logged and are left out of the scene
+        render_data.models = [map]
+            .into_iter()
+            .filter_map(|handle| {
+                let mut model = model::InstancedModel::from(asset_server.get(&handle)?);
+                model.handle = Some(handle);
+                Some(model)
+            })
+            .collect();
+
+        // the cubes further back are drawn with lower detail lods, their thresholds can be tuned in the gui
+        match lod::load_lod_group(
+            "cube.obj",
+            lod::LodMetric::ScreenSize,
+            &[0.15, 0.05],
+            &[0.5, 0.25],
+            &mut asset_server,
+            &material_bind_group_layout,
             &context.wgpu,
-        )
-        .unwrap();
+        ) {
+            Ok(cube) => render_data.models.push(cube),
+            Err(error) => log::error!("Couldn't load the cube's LODs: {error}"),
+        }
+
+        gui.state.lod_thresholds = render_data
+            .models
+            .iter()
+            .map(|model| model.lods.iter().map(|lod| lod.threshold).collect())
+            .collect();
+
+        // the binaries are run from the assets directory
+        let file_watcher = hot_reload::FileWatcher::new(".");
+
+        render_data.lights = vec![
+            light::Light::Directional {
+                direction: cg::Vector3::new(-0.4, -1.0, -0.6),
+                color: cg::Vector3::new(1.0, 0.95, 0.85),
+                intensity: 1.0,
+            },
+            light::Light::Point {
+                position: cg::Point3::new(0.0, 4.0, 0.0),
+                color: cg::Vector3::new(1.0, 0.6, 0.3),
+                intensity: 20.0,
+                range: 15.0,
+            },
+        ];
+        render_data.update_lights_buffer(&context.wgpu);
+
+        // the pipeline is made by the first update
+        let shadow_map =
+            shadow::ShadowMap::new(&context.wgpu.device, gui.state.shadow_settings.clone());
 
-        let map = model::Model::load(
-            "map.obj".to_string(),
-            &mut texture_atlas,
-            &texture_bind_group_layout,
+        let gpu_culling = culling::GpuCulling::new(&context.wgpu)
+            .map_err(|error| log::warn!("{error}, culling on the CPU instead"))
+            .ok();
+
+        let tone_mapping = tone_mapping::ToneMapping::new(
             &context.wgpu,
-        )
-        .unwrap();
+            context.wgpu.config.format,
+            gui.state.tone_mapping_settings.clone(),
+        );
 
-        let mut render_data = render::RenderData::new(&context.wgpu.device);
+        let post_process = match post_process::PostProcessStack::new(&mut context.wgpu) {
+            Ok(mut post_process) => {
+                // the effects added before one that failed are still used
+                let effects = post_process.add_default_effects(&context.wgpu);
+                gui.state
+                    .show_pipeline_result("post processing", effects.map(|_| true));
+                gui.state.post_process_settings = post_process.settings().clone();
+                Some(post_process)
+            }
+            Err(error) => {
+                gui.state
+                    .show_pipeline_result("post processing", Err(error));
+                None
+            }
+        };
 
-        let models = vec![map.into(), cube.into()];
-        render_data.models = models;
+        let debug_views = debug_view::DebugViews::new(&context.wgpu);
 
-        let application = App::new(&context);
+        let app = App::new(&context);
 
         Self {
             context,
+            render_pipeline_layout,
             render_pipeline,
+            pipeline_cache,
+            shader_handle,
+            shader,
             camera,
-            view_projection_buffer,
-            view_projection_bind_group,
-            texture_bind_group_layout,
+            camera_buffer,
+            camera_bind_group,
+            material_bind_group_layout,
             time: time::Time {
                 start: instant::Instant::now(),
                 current: instant::Duration::default(),
                 delta: instant::Duration::default(),
                 fps: 0.0,
             },
-            texture_atlas,
+            asset_server,
+            file_watcher,
             render_data,
+            shadow_map,
+            gpu_culling,
+            tone_mapping,
+            post_process,
+            debug_views,
             gui,
-            application,
+            app,
+        }
+    }
+
+    // takes over the thread until the window is closed
+    pub fn run(mut self, event_loop: EventLoop<gui::GuiEvent>) -> !
+    where
+        App: 'static,
+    {
+        event_loop.run(move |event, _, control_flow| {
+            if input::cursor_state() == input::CursorState::Visible {
+                self.gui.handle_event(&event);
+            }
+
+            input::update_input_state(&event, &self.context.window);
+
+            match event {
+                Event::WindowEvent {
+                    ref event,
+                    window_id,
+                } if window_id == self.context.window.id() => {
+                    self.process_window_event(event, control_flow)
+                }
+                Event::RedrawRequested(window_id) if window_id == self.context.window.id() => {
+                    self.time.start_frame();
+
+                    self.update();
+                    match self.render() {
+                        Ok(_) => {}
+                        // reconfigure the surface if lost
+                        Err(err) => {
+                            if err
+                                .downcast_ref::<wgpu::SurfaceError>()
+                                .is_some_and(|err| *err == wgpu::SurfaceError::Lost)
+                            {
+                                self.context.resize(self.context.size)
+                            } else {
+                                log::error!("{:?}", err);
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
+                    }
+
+                    self.time.end_frame();
+                }
+                Event::MainEventsCleared | Event::UserEvent(gui::GuiEvent::RequestRedraw) => {
+                    self.context.window.request_redraw();
+                }
+                _ => {}
+            }
+        })
+    }
+
+    // fails instead of panicking if the shader doesn't fit the pipeline e.g a reloaded one with different bindings
+    fn create_render_pipeline(
+        pipeline_cache: &mut pipeline::PipelineCache,
+        wgpu: &context::WgpuContext,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+    ) -> Result<Rc<wgpu::RenderPipeline>> {
+        pipeline_cache.get(
+            &pipeline::scene_pipeline_builder(wgpu.sample_count),
+            &wgpu.device,
+            layout,
+            shader,
+            "instanced_render_pipeline",
+        )
+    }
+
+    // picks up files that were edited or removed since the last frame, anything that fails to reload is logged and the old one kept
+    fn reload_changed_assets(&mut self) {
+        let changed_paths = self.file_watcher.poll();
+        if changed_paths.is_empty() {
+            return;
+        }
+
+        let reloaded = self.asset_server.reload(
+            &changed_paths,
+            &self.material_bind_group_layout,
+            &self.context.wgpu,
+        );
+
+        self.render_data
+            .reload_models(&self.asset_server, &reloaded.models);
+        self.shadow_map.reload(&reloaded.shaders);
+        self.tone_mapping.reload(&reloaded.shaders);
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.reload(&reloaded.shaders);
+        }
+        self.debug_views.reload(&reloaded.shaders);
+
+        if !reloaded.shaders.contains(&self.shader_handle) {
+            // shown until the file is fixed, the error was already logged by the asset server
+            if let asset::LoadState::Failed(error) | asset::LoadState::ReloadFailed(error) =
+                self.asset_server.load_state(&self.shader_handle)
+            {
+                self.gui.state.shader_errors.insert("scene", error);
+            }
+
+            return;
+        }
+
+        let Some(shader) = self.asset_server.get(&self.shader_handle) else {
+            return;
+        };
+
+        match Self::create_render_pipeline(
+            &mut self.pipeline_cache,
+            &self.context.wgpu,
+            &self.render_pipeline_layout,
+            &shader.module,
+        ) {
+            Ok(render_pipeline) => {
+                log::info!("Recreated render pipeline from \"{}\"", shader.path);
+                self.render_pipeline = Some(render_pipeline);
+                self.shader = Some(shader);
+                self.gui.state.shader_errors.remove("scene");
+                self.pipeline_cache.remove_unused();
+            }
+            Err(error) => {
+                let error = format!(
+                    "Couldn't recreate render pipeline from \"{}\", keeping the old one: {error}",
+                    shader.path
+                );
+                log::error!("{error}");
+                self.gui.state.shader_errors.insert("scene", error);
+            }
         }
     }
 
@@ -176,7 +393,7 @@ impl<App> ApplicationHandler<App> {
             | WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
-                        ApplicationHandler: ElementApplicationHandler::Pressed,
+                        state: ElementState::Pressed,
                         virtual_keycode: Some(VirtualKeyCode::Escape),
                         ..
                     },
@@ -184,108 +401,306 @@ impl<App> ApplicationHandler<App> {
             } => *control_flow = ControlFlow::Exit,
             WindowEvent::Resized(physical_size) => {
                 self.context.resize(*physical_size);
+                self.tone_mapping.resize(&self.context.wgpu);
+                if let Some(post_process) = &mut self.post_process {
+                    post_process.resize(&self.context.wgpu);
+                }
             }
             WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                 self.context.resize(**new_inner_size);
-            }
-            WindowEvent::CursorMoved { .. } => {
-                if input::cursor_ApplicationHandler() == input::CursorApplicationHandler::Hidden {
-                    self.camera.update_direction(self.time.delta);
+                self.tone_mapping.resize(&self.context.wgpu);
+                if let Some(post_process) = &mut self.post_process {
+                    post_process.resize(&self.context.wgpu);
                 }
             }
+            WindowEvent::CursorMoved { .. }
+                if input::cursor_state() == input::CursorState::Hidden =>
+            {
+                self.camera.update_direction(self.time.delta);
+            }
             _ => {}
         }
     }
 
     // called per frame
     fn update(&mut self) {
+        self.reload_changed_assets();
+
         self.context.wgpu.queue.write_buffer(
-            &self.view_projection_buffer,
+            &self.camera_buffer,
             0,
-            bytemuck::cast_slice(&[self.camera.build_view_projection_matrix().to_uniform()]),
+            bytemuck::cast_slice(&[camera::CameraUniform::from(&self.camera)]),
         );
 
-        self.render_data.models[1].instances = Some(
-            // TODO magic num
-            (0..20)
-                .flat_map(|x| {
-                    // TODO
-                    let num_instances_per_column = 20 as f32;
-                    let pi = std::f32::consts::PI;
-                    let angle = x as f32 / num_instances_per_column * pi * 2.0; // between 0 and 2pi
-                    let angle = (angle + self.time.current.as_secs_f32()) % (pi * 2.0); // shift period by time
-                    let y = angle.sin() * 2.0;
-
-                    // TODO
-                    (0..20).map(move |z| instance::ModelInstance {
-                        position: cg::Vector3 {
-                            x: x as f32 * 2.5,
-                            y: y * 3.0,
-                            z: z as f32 * 2.5,
-                        },
-                        rotation: cg::Quaternion::from_angle_x(cg::Deg(0.0)),
+        // missing if it failed to load
+        let cube = self
+            .render_data
+            .models
+            .iter_mut()
+            .find(|model| model.model.name.as_str() == "cube.obj");
+
+        if let Some(cube) = cube {
+            cube.instances = Some(
+                // TODO magic num
+                (0..20)
+                    .flat_map(|x| {
+                        // TODO
+                        let num_instances_per_column = 20.0;
+                        let pi = std::f32::consts::PI;
+                        let angle = x as f32 / num_instances_per_column * pi * 2.0; // between 0 and 2pi
+                        let angle = (angle + self.time.current.as_secs_f32()) % (pi * 2.0); // shift period by time
+                        let y = angle.sin() * 2.0;
+
+                        // TODO
+                        (0..20).map(move |z| instance::ModelInstance {
+                            position: cg::Vector3 {
+                                x: x as f32 * 2.5,
+                                y: y * 3.0,
+                                z: z as f32 * 2.5,
+                            },
+                            rotation: cg::Quaternion::from_angle_x(cg::Deg(0.0)),
+                        })
                     })
-                })
-                .collect(),
-        );
+                    .collect(),
+            );
+        }
+
+        // cull with the camera the uniform above was written from, not the moved one below
+        let frustum = self.camera.frustum();
+        let shadow_sweep = self.shadow_map.caster_sweep(&self.render_data.lights);
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            let culling_pipelines =
+                gpu_culling.update_pipelines(&mut self.asset_server, &self.context.wgpu.device);
+            self.gui
+                .state
+                .show_pipeline_result("GPU culling", culling_pipelines);
 
+            gpu_culling.update(
+                &self.context.wgpu,
+                &mut self.render_data,
+                &frustum,
+                shadow_sweep,
+            );
+        }
+        self.render_data.cull(&frustum, shadow_sweep);
+        self.render_data
+            .select_lods(&self.camera, &self.gui.state.lod_settings);
         self.render_data.update_instance_buffers(&self.context.wgpu);
         self.camera.update_position(self.time.delta);
+
+        self.shadow_map.update(
+            &self.context.wgpu.queue,
+            &self.render_data.lights,
+            &self.camera,
+        );
+
+        self.tone_mapping
+            .update(&self.context.wgpu, self.time.delta);
+        if let Some(post_process) = &self.post_process {
+            post_process.update(&self.context.wgpu);
+        }
+
+        let shadow_pipeline = self
+            .shadow_map
+            .update_pipeline(&mut self.asset_server, &self.context.wgpu.device);
+        self.gui
+            .state
+            .show_pipeline_result("shadows", shadow_pipeline);
+
+        let tone_mapping_pipelines = self
+            .tone_mapping
+            .update_pipelines(&mut self.asset_server, &self.context.wgpu.device);
+        self.gui
+            .state
+            .show_pipeline_result("tone mapping", tone_mapping_pipelines);
+
+        let debug_view_pipelines = self.debug_views.update(
+            &mut self.asset_server,
+            &mut self.pipeline_cache,
+            &self.context.wgpu,
+        );
+        self.gui
+            .state
+            .show_pipeline_result("debug views", debug_view_pipelines);
     }
 
     fn render(&mut self) -> Result<()> {
         // wait for surface to provide a new SurfaceTexture to write on
-        let output = self.context.wgpu.surface.get_current_texture()?;
+        let output = self.context.surface.get_current_texture()?;
 
         // view describes a texture
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        // creates and encodes commands to send to the gpu
-        let mut encoder =
-            self.context
-                .wgpu
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("render_encoder"),
-                });
+        let wgpu = &self.context.wgpu;
 
-        // begin_render_pass returns a render pass with the same lifetime as the encoder, since the encoder is borrowed mutably for this function it cannot be borrowed later on as immutable unless the render pass is dropped and the reference dropped, hence the limiting scope
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render_pass"),
-                // where to draw color to
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    // texture to recieve output, same as view unless using multisampling
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
+        // passes are recorded in the order their reads and writes need, not the order they are added
+        let mut graph = render::RenderGraph::new();
+
+        if let Some(gpu_culling) = &self.gpu_culling {
+            graph.add_pass("culling", &[], &[render::INSTANCES], |encoder| {
+                gpu_culling.cull(encoder, &self.render_data);
+                Ok(())
+            });
+        }
+
+        graph.add_pass(
+            "shadow",
+            &[render::INSTANCES],
+            &[render::SHADOW_MAP],
+            |encoder| {
+                self.shadow_map.render(encoder, &self.render_data);
+                Ok(())
+            },
+        );
+
+        graph.add_pass(
+            "main",
+            &[render::SHADOW_MAP, render::INSTANCES],
+            &[render::HDR, render::DEPTH],
+            |encoder| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("render_pass"),
+                    // where to draw color to, goes through the msaa texture first if msaa is on
+                    color_attachments: &[Some(wgpu.color_attachment(
+                        &wgpu.hdr_texture.view,
+                        wgpu::Operations {
+                            // debug views are easier to read against black, the overdraw view adds onto it
+                            load: wgpu::LoadOp::Clear(if self.debug_views.replaces_scene() {
+                                wgpu::Color::BLACK
+                            } else {
+                                wgpu::Color {
+                                    r: 0.1,
+                                    g: 0.2,
+                                    b: 0.3,
+                                    a: 1.0,
+                                }
+                            }),
+                            store: true,
+                        },
+                    ))],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &wgpu.depth_texture.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: true,
                         }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.context.wgpu.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
+                        stencil_ops: None,
                     }),
-                    stencil_ops: None,
-                }),
+                });
+
+                if let (Some(render_pipeline), false) =
+                    (&self.render_pipeline, self.debug_views.replaces_scene())
+                {
+                    render_pass.set_pipeline(render_pipeline);
+                    render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                    render_pass.set_bind_group(3, self.shadow_map.bind_group(), &[]);
+                    render_pass.draw_models(&self.render_data);
+                }
+
+                // the wireframe is drawn over the lit scene too
+                self.debug_views
+                    .draw(&mut render_pass, &self.render_data, &self.camera_bind_group);
+
+                // anything the application draws itself goes on top of the scene
+                self.app.render(&mut render_pass)
+            },
+        );
+
+        let post_process_target = self
+            .post_process
+            .as_ref()
+            .map_or(render::SURFACE, |post_process| {
+                post_process.target_resource()
             });
+
+        graph.add_pass(
+            "tone_mapping",
+            &[render::HDR],
+            &[post_process_target],
+            |encoder| {
+                let target = match &self.post_process {
+                    Some(post_process) => post_process.target(wgpu, &view),
+                    None => &view,
+                };
+                self.tone_mapping.render(encoder, wgpu, target);
+                Ok(())
+            },
+        );
+
+        if let Some(post_process) = &self.post_process {
+            graph.add_pass(
+                "post_process",
+                &[post_process_target],
+                &[render::SURFACE],
+                |encoder| {
+                    post_process.render(encoder, wgpu, &view);
+                    Ok(())
+                },
+            );
+        }
+
+        // drawn on top of the scene
+        graph.add_pass("gui", &[render::SURFACE], &[render::SURFACE], |encoder| {
+            self.gui
+                .render(encoder, &view, &self.context, &self.time, &self.render_data)
+        });
+
+        let command_buffer = graph.execute(wgpu)?;
+
+        // takes effect from the next frame
+        self.shadow_map.set_settings(
+            &self.context.wgpu.device,
+            self.gui.state.shadow_settings.clone(),
+        );
+        self.tone_mapping
+            .set_settings(self.gui.state.tone_mapping_settings.clone());
+        if let Some(post_process) = &mut self.post_process {
+            post_process.set_settings(self.gui.state.post_process_settings.clone());
+        }
+        self.debug_views
+            .set_settings(self.gui.state.debug_view_settings.clone());
+        for (model, thresholds) in self
+            .render_data
+            .models
+            .iter_mut()
+            .zip(self.gui.state.lod_thresholds.iter())
+        {
+            for (lod, &threshold) in model.lods.iter_mut().zip(thresholds) {
+                lod.threshold = threshold;
+            }
+        }
+
+        if self.gui.state.sample_count != self.context.wgpu.sample_count {
+            self.context
+                .wgpu
+                .set_sample_count(self.gui.state.sample_count)?;
+            // the hdr and transient textures are recreated along with the msaa texture
+            self.tone_mapping.resize(&self.context.wgpu);
+            if let Some(post_process) = &mut self.post_process {
+                post_process.resize(&self.context.wgpu);
+            }
+            if let Some(shader) = &self.shader {
+                let render_pipeline = Self::create_render_pipeline(
+                    &mut self.pipeline_cache,
+                    &self.context.wgpu,
+                    &self.render_pipeline_layout,
+                    &shader.module,
+                );
+                self.render_pipeline = render_pipeline.as_ref().ok().cloned();
+                self.gui
+                    .state
+                    .show_pipeline_result("scene", render_pipeline.map(|_| true));
+            }
+            self.pipeline_cache.remove_unused();
         }
 
         // submit to render queue
         self.context
             .wgpu
             .queue
-            .submit(std::iter::once(encoder.finish()));
+            .submit(std::iter::once(command_buffer));
         output.present();
 
         Ok(())
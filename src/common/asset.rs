@@ -0,0 +1,803 @@
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use rustc_hash::FxHashMap;
+
+use crate::common::context::WgpuContext;
+use crate::common::import;
+use crate::common::model::Model;
+use crate::common::pipeline::{Shader, ShaderDefines, ShaderError};
+use crate::common::texture::{Mipmaps, SamplerSettings, Texture};
+
+// refers to an asset in an AssetServer, the asset is kept loaded for as long as any handle to it is alive
+pub struct Handle<T> {
+    id: u64,
+    // every handle to the same asset shares this, the server only keeps a weak reference to count them
+    alive: Rc<()>,
+    _asset: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+// derive would require T: Clone
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            alive: self.alive.clone(),
+            _asset: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id)
+    }
+}
+
+// loading is synchronous so there is no state for an asset that is still loading
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    Loaded,
+    // the error that stopped it loading, loading it again retries
+    Failed(String),
+    // the file changed into something that didn't load, the asset from before is still used
+    ReloadFailed(String),
+}
+
+struct Entry<T> {
+    path: Rc<String>,
+    asset: std::result::Result<Rc<T>, String>,
+    handles: Weak<()>,
+    // cleared once it loads again
+    reload_error: Option<String>,
+}
+
+// every asset of one type, keyed by its canonical path
+pub struct Assets<T> {
+    entries: FxHashMap<u64, Entry<T>>,
+    ids: FxHashMap<Rc<String>, u64>,
+    next_id: u64,
+}
+
+impl<T> Assets<T> {
+    fn new() -> Self {
+        Self {
+            entries: FxHashMap::default(),
+            ids: FxHashMap::default(),
+            next_id: 0,
+        }
+    }
+
+    fn loaded(&self, path: &Rc<String>) -> Option<Rc<T>> {
+        let id = self.ids.get(path)?;
+        self.entries[id].asset.as_ref().ok().cloned()
+    }
+
+    // replaces the asset if the path was loaded before so existing handles see the new one
+    fn insert(&mut self, path: Rc<String>, asset: std::result::Result<Rc<T>, String>) -> Handle<T> {
+        let id = *self.ids.entry(path.clone()).or_insert_with(|| {
+            self.next_id += 1;
+            self.next_id
+        });
+
+        let entry = self.entries.entry(id).or_insert_with(|| Entry {
+            path,
+            asset: Err(String::new()),
+            handles: Weak::new(),
+            reload_error: None,
+        });
+        entry.asset = asset;
+        entry.reload_error = None;
+
+        self.handle(id)
+    }
+
+    // another handle to an asset that is already loaded, leaves the asset and any reload error as they are
+    fn handle(&mut self, id: u64) -> Handle<T> {
+        let entry = self.entries.get_mut(&id).unwrap();
+        let alive = entry.handles.upgrade().unwrap_or_else(|| {
+            let alive = Rc::new(());
+            entry.handles = Rc::downgrade(&alive);
+            alive
+        });
+
+        Handle {
+            id,
+            alive,
+            _asset: PhantomData,
+        }
+    }
+
+    // in use if there are handles to it or anything else kept a reference e.g a material's textures
+    fn remove_unused(&mut self) -> Vec<Rc<String>> {
+        let unused = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.handles.strong_count() == 0
+                    && entry
+                        .asset
+                        .as_ref()
+                        .ok()
+                        .is_none_or(|asset| Rc::strong_count(asset) == 1)
+            })
+            .map(|(&id, _)| id)
+            .collect::<Vec<u64>>();
+
+        unused
+            .into_iter()
+            .filter_map(|id| self.entries.remove(&id))
+            .map(|entry| {
+                self.ids.remove(&entry.path);
+                entry.path
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+pub trait Asset: Sized + 'static {
+    // used in log messages
+    const NAME: &'static str;
+
+    fn assets(asset_server: &AssetServer) -> &Assets<Self>;
+    fn assets_mut(asset_server: &mut AssetServer) -> &mut Assets<Self>;
+}
+
+impl Asset for Texture {
+    const NAME: &'static str = "texture";
+
+    fn assets(asset_server: &AssetServer) -> &Assets<Self> {
+        &asset_server.textures
+    }
+
+    fn assets_mut(asset_server: &mut AssetServer) -> &mut Assets<Self> {
+        &mut asset_server.textures
+    }
+}
+
+impl Asset for Model {
+    const NAME: &'static str = "model";
+
+    fn assets(asset_server: &AssetServer) -> &Assets<Self> {
+        &asset_server.models
+    }
+
+    fn assets_mut(asset_server: &mut AssetServer) -> &mut Assets<Self> {
+        &mut asset_server.models
+    }
+}
+
+impl Asset for Shader {
+    const NAME: &'static str = "shader";
+
+    fn assets(asset_server: &AssetServer) -> &Assets<Self> {
+        &asset_server.shaders
+    }
+
+    fn assets_mut(asset_server: &mut AssetServer) -> &mut Assets<Self> {
+        &mut asset_server.shaders
+    }
+}
+
+// assets whose files changed and were loaded again successfully, see AssetServer::reload
+#[derive(Default)]
+pub struct ReloadedAssets {
+    pub textures: Vec<Handle<Texture>>,
+    pub models: Vec<Handle<Model>>,
+    pub shaders: Vec<Handle<Shader>>,
+}
+
+impl ReloadedAssets {
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty() && self.models.is_empty() && self.shaders.is_empty()
+    }
+}
+
+// how a model was loaded, so it's reloaded the same way
+#[derive(Clone, Debug)]
+struct ModelFile {
+    path: Rc<String>,
+    // the path it was asked for so log messages match what the caller wrote, relative paths still work because the working directory doesn't change
+    name: String,
+    // e.g 0.5 = simplified to half the triangles
+    simplify_ratio: Option<f32>,
+    // the mtl files of an obj model, the materials are made from them so changing one reloads the model
+    material_files: Vec<Rc<String>>,
+}
+
+impl ModelFile {
+    fn new(path: &str, simplify_ratio: Option<f32>) -> Self {
+        Self {
+            path: AssetServer::canonical_path(path),
+            name: path.to_string(),
+            simplify_ratio,
+            material_files: Self::material_files(path),
+        }
+    }
+
+    // looked up again on every load as the obj file can change which mtl files it uses
+    fn material_files(path: &str) -> Vec<Rc<String>> {
+        let is_obj = std::path::Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("obj"));
+        if !is_obj {
+            return vec![];
+        }
+
+        // a model that can't be read fails to load anyway
+        import::obj_material_files(path)
+            .unwrap_or_default()
+            .iter()
+            .map(|file| AssetServer::canonical_path(&file.to_string_lossy()))
+            .collect()
+    }
+
+    fn load(
+        &self,
+        asset_server: &mut AssetServer,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Result<Model> {
+        match self.simplify_ratio {
+            Some(ratio) => Model::load_simplified(
+                self.name.clone(),
+                ratio,
+                asset_server,
+                material_bind_group_layout,
+                wgpu_context,
+            ),
+            None => Model::load(
+                self.name.clone(),
+                asset_server,
+                material_bind_group_layout,
+                wgpu_context,
+            ),
+        }
+    }
+}
+
+// loads each file once no matter how many things use it, and frees it once nothing does
+pub struct AssetServer {
+    textures: Assets<Texture>,
+    models: Assets<Model>,
+    shaders: Assets<Shader>,
+    // the file and defines behind each shader's key, kept for the ones that failed so they can be retried
+    shader_permutations: FxHashMap<Rc<String>, (Rc<String>, ShaderDefines)>,
+    // the files each shader that failed to load was made from, as far as it got, fixing any of them retries it
+    shader_error_files: FxHashMap<Rc<String>, Vec<Rc<String>>>,
+    // the file behind each texture's key and whether it was loaded as a normal map, so it's reloaded the same way
+    texture_files: FxHashMap<Rc<String>, (Rc<String>, bool)>,
+    // the file behind each model's key, a simplified model is keyed apart from the full one
+    model_files: FxHashMap<Rc<String>, ModelFile>,
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetServer {
+    // textures are put on models so are viewed from any distance and angle
+    const MIPMAPS: Mipmaps = Mipmaps::Anisotropic(16);
+
+    // generated textures are stored alongside loaded ones, these keys can't clash with a file path
+    const WHITE_KEY: &str = "<white>";
+    const FLAT_NORMAL_MAP_KEY: &str = "<flat-normal-map>";
+
+    pub fn new() -> Self {
+        Self {
+            textures: Assets::new(),
+            models: Assets::new(),
+            shaders: Assets::new(),
+            shader_permutations: FxHashMap::default(),
+            shader_error_files: FxHashMap::default(),
+            texture_files: FxHashMap::default(),
+            model_files: FxHashMap::default(),
+        }
+    }
+
+    // the same file reached through different relative paths is only loaded once
+    // keys that aren't files e.g generated or embedded textures are kept as they are
+    fn canonical_path(path: &str) -> Rc<String> {
+        Rc::new(
+            std::fs::canonicalize(path)
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| path.to_string()),
+        )
+    }
+
+    // a normal map isn't in srgb so the same file loaded as one is a different texture
+    fn texture_key(path: Rc<String>, is_normal_map: bool) -> Rc<String> {
+        if is_normal_map {
+            Rc::new(format!("{path}#normal-map"))
+        } else {
+            path
+        }
+    }
+
+    // loads the asset unless it is already loaded, a failure is logged and kept so it can be queried through the handle
+    fn load<T: Asset>(
+        &mut self,
+        path: Rc<String>,
+        load: impl FnOnce(&mut Self, &str) -> Result<T>,
+    ) -> (Handle<T>, Result<Rc<T>>) {
+        if let Some(asset) = T::assets(self).loaded(&path) {
+            log::info!(
+                "Already loaded {} \"{path}\", returning reference.",
+                T::NAME
+            );
+            let assets = T::assets_mut(self);
+            let handle = assets.handle(assets.ids[&path]);
+            return (handle, Ok(asset));
+        }
+
+        log::info!("Loading {} \"{path}\".", T::NAME);
+        let asset = load(self, &path).map(Rc::new).map_err(|error| {
+            let error = format!("{error:#}");
+            log::error!("Failed to load {} \"{path}\": {error}", T::NAME);
+            error
+        });
+
+        let handle = T::assets_mut(self).insert(path, asset.clone());
+
+        (handle, asset.map_err(|error| eyre!(error)))
+    }
+
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<Rc<T>> {
+        T::assets(self).entries[&handle.id]
+            .asset
+            .as_ref()
+            .ok()
+            .cloned()
+    }
+
+    // the error it failed to load with otherwise, the asset server has already logged it
+    pub fn try_get<T: Asset>(&self, handle: &Handle<T>) -> Result<Rc<T>> {
+        T::assets(self).entries[&handle.id]
+            .asset
+            .clone()
+            .map_err(|error| eyre!(error))
+    }
+
+    pub fn load_state<T: Asset>(&self, handle: &Handle<T>) -> LoadState {
+        let entry = &T::assets(self).entries[&handle.id];
+        match (&entry.asset, &entry.reload_error) {
+            (Ok(_), None) => LoadState::Loaded,
+            (Ok(_), Some(error)) => LoadState::ReloadFailed(error.clone()),
+            (Err(error), _) => LoadState::Failed(error.clone()),
+        }
+    }
+
+    pub fn path<T: Asset>(&self, handle: &Handle<T>) -> Rc<String> {
+        T::assets(self).entries[&handle.id].path.clone()
+    }
+
+    pub fn assets<T: Asset>(&self) -> &Assets<T> {
+        T::assets(self)
+    }
+
+    // frees every asset without handles that nothing else holds onto, returns how many were freed
+    pub fn unload_unused(&mut self) -> usize {
+        fn unload<T: Asset>(asset_server: &mut AssetServer) -> usize {
+            let paths = T::assets_mut(asset_server).remove_unused();
+            for path in paths.iter() {
+                log::info!("Unloaded {} \"{path}\".", T::NAME);
+            }
+
+            paths.len()
+        }
+
+        // models first so the textures only their materials used are freed too
+        let unloaded = unload::<Model>(self) + unload::<Texture>(self) + unload::<Shader>(self);
+
+        let shader_ids = &self.shaders.ids;
+        self.shader_permutations
+            .retain(|key, _| shader_ids.contains_key(key));
+        self.shader_error_files
+            .retain(|key, _| shader_ids.contains_key(key));
+        let texture_ids = &self.textures.ids;
+        self.texture_files
+            .retain(|key, _| texture_ids.contains_key(key));
+        let model_ids = &self.models.ids;
+        self.model_files
+            .retain(|key, _| model_ids.contains_key(key));
+
+        unloaded
+    }
+
+    // loads the asset at path again if it was loaded before, returns the old one if the new one loaded
+    // if it fails the old one is kept so a mistake in a file being edited doesn't break anything already using it
+    fn reload_asset<T: Asset>(
+        &mut self,
+        path: &Rc<String>,
+        load: impl FnOnce(&mut Self, Option<&Rc<T>>) -> Result<T>,
+    ) -> Option<(Handle<T>, Option<Rc<T>>)> {
+        let old_asset = T::assets(self)
+            .entries
+            .get(T::assets(self).ids.get(path)?)?
+            .asset
+            .clone();
+
+        log::info!("Reloading {} \"{path}\".", T::NAME);
+        match load(self, old_asset.as_ref().ok()) {
+            Ok(asset) => {
+                let handle = T::assets_mut(self).insert(path.clone(), Ok(Rc::new(asset)));
+                Some((handle, old_asset.ok()))
+            }
+            Err(error) => {
+                let error = format!("{error:#}");
+
+                if old_asset.is_ok() {
+                    log::error!(
+                        "Failed to reload {} \"{path}\", keeping the old one: {error}",
+                        T::NAME
+                    );
+
+                    let id = T::assets(self).ids[path];
+                    T::assets_mut(self)
+                        .entries
+                        .get_mut(&id)
+                        .unwrap()
+                        .reload_error = Some(error);
+                } else {
+                    log::error!("Failed to load {} \"{path}\": {error}", T::NAME);
+                    T::assets_mut(self).insert(path.clone(), Err(error));
+                }
+
+                None
+            }
+        }
+    }
+
+    // loads every asset from changed_paths again, and every model using a texture that was reloaded
+    // anything holding the old assets e.g RenderData has to swap them for the new ones itself
+    pub fn reload(
+        &mut self,
+        changed_paths: &[PathBuf],
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> ReloadedAssets {
+        let mut reloaded = ReloadedAssets::default();
+        let mut old_textures = vec![];
+
+        let changed_paths = changed_paths
+            .iter()
+            .map(|path| Rc::new(path.to_string_lossy().into_owned()))
+            .collect::<Vec<Rc<String>>>();
+
+        // a file loaded as both a color texture and a normal map is two textures
+        let changed_textures = self
+            .texture_files
+            .iter()
+            .filter(|(_, (path, _))| changed_paths.contains(path))
+            .map(|(key, &(ref path, is_normal_map))| (key.clone(), path.clone(), is_normal_map))
+            .collect::<Vec<_>>();
+
+        for (key, path, is_normal_map) in changed_textures {
+            let texture = self.reload_asset(&key, |_, _: Option<&Rc<Texture>>| {
+                Texture::from_path(
+                    &path,
+                    is_normal_map,
+                    Self::MIPMAPS,
+                    &wgpu_context.device,
+                    &wgpu_context.queue,
+                    Some(&key),
+                )
+            });
+
+            if let Some((handle, old_texture)) = texture {
+                reloaded.textures.push(handle);
+                old_textures.extend(old_texture);
+            }
+        }
+
+        let changed_models = self
+            .model_files
+            .iter()
+            .filter(|(_, file)| {
+                changed_paths.contains(&file.path)
+                    || file
+                        .material_files
+                        .iter()
+                        .any(|material_file| changed_paths.contains(material_file))
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<Rc<String>>>();
+
+        // material bind groups point at the old textures, so the models are loaded again to rebuild them
+        let models_using_old_textures = self
+            .models
+            .entries
+            .values()
+            .filter(|entry| {
+                entry.asset.as_ref().is_ok_and(|model| {
+                    model.materials.iter().any(|material| {
+                        old_textures.iter().any(|texture| {
+                            Rc::ptr_eq(&material.diffuse, texture)
+                                || Rc::ptr_eq(&material.normal, texture)
+                        })
+                    })
+                })
+            })
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<Rc<String>>>();
+
+        let model_keys = changed_models
+            .into_iter()
+            .chain(models_using_old_textures)
+            .collect::<rustc_hash::FxHashSet<_>>();
+
+        for key in model_keys {
+            let Some(mut file) = self.model_files.get(&key).cloned() else {
+                continue;
+            };
+            file.material_files = ModelFile::material_files(&file.name);
+            self.model_files.insert(key.clone(), file.clone());
+
+            let model = self.reload_asset(&key, |asset_server, _: Option<&Rc<Model>>| {
+                file.load(asset_server, material_bind_group_layout, wgpu_context)
+            });
+
+            reloaded.models.extend(model.map(|(handle, _)| handle));
+        }
+
+        // a shader is also made from the files it includes, including any its last failed load got to
+        let changed_shaders = self
+            .shaders
+            .entries
+            .values()
+            .filter(|entry| {
+                let loaded_files = entry.asset.iter().flat_map(|shader| shader.files.iter());
+                let error_files = self
+                    .shader_error_files
+                    .get(&entry.path)
+                    .into_iter()
+                    .flatten();
+
+                changed_paths.contains(&self.shader_permutations[&entry.path].0)
+                    || loaded_files
+                        .chain(error_files)
+                        .any(|file| changed_paths.contains(file))
+            })
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<Rc<String>>>();
+
+        for key in changed_shaders {
+            let (path, defines) = self.shader_permutations[&key].clone();
+            let shader = self.reload_asset(&key, |asset_server, _: Option<&Rc<Shader>>| {
+                asset_server.load_shader_permutation(&key, &path, &defines, &wgpu_context.device)
+            });
+
+            reloaded.shaders.extend(shader.map(|(handle, _)| handle));
+        }
+
+        reloaded
+    }
+
+    pub fn load_texture(
+        &mut self,
+        path: &str,
+        is_normal_map: bool,
+        wgpu_context: &WgpuContext,
+    ) -> Handle<Texture> {
+        self.load_texture_file(
+            path,
+            is_normal_map,
+            &wgpu_context.device,
+            &wgpu_context.queue,
+        )
+        .0
+    }
+
+    pub fn load_model(
+        &mut self,
+        path: &str,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Handle<Model> {
+        let file = ModelFile::new(path, None);
+
+        self.load_model_file(
+            file.path.clone(),
+            file,
+            material_bind_group_layout,
+            wgpu_context,
+        )
+    }
+
+    // a lower detail copy of the model at path, e.g 0.5 = half the triangles
+    pub fn load_simplified_model(
+        &mut self,
+        path: &str,
+        ratio: f32,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Handle<Model> {
+        let file = ModelFile::new(path, Some(ratio));
+
+        self.load_model_file(
+            Rc::new(format!("{}#simplified-{ratio}", file.path)),
+            file,
+            material_bind_group_layout,
+            wgpu_context,
+        )
+    }
+
+    fn load_model_file(
+        &mut self,
+        key: Rc<String>,
+        file: ModelFile,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Handle<Model> {
+        self.model_files.insert(key.clone(), file.clone());
+
+        self.load(key, |asset_server, _| {
+            file.load(asset_server, material_bind_group_layout, wgpu_context)
+        })
+        .0
+    }
+
+    // each set of defines is its own permutation, the same permutation is only compiled once
+    pub fn load_shader(
+        &mut self,
+        path: &str,
+        defines: &ShaderDefines,
+        device: &wgpu::Device,
+    ) -> Handle<Shader> {
+        let path = Self::canonical_path(path);
+        let key = Rc::new(defines.permutation_name(&path));
+
+        self.shader_permutations
+            .insert(key.clone(), (path.clone(), defines.clone()));
+
+        self.load(key.clone(), |asset_server, _| {
+            asset_server.load_shader_permutation(&key, &path, defines, device)
+        })
+        .0
+    }
+
+    // remembers which files a failed shader was made from so it's retried when one of them changes
+    fn load_shader_permutation(
+        &mut self,
+        key: &Rc<String>,
+        path: &str,
+        defines: &ShaderDefines,
+        device: &wgpu::Device,
+    ) -> Result<Shader> {
+        let shader = Shader::from_path(path, defines, device);
+
+        match shader
+            .as_ref()
+            .map_err(|error| error.downcast_ref::<ShaderError>())
+        {
+            Ok(_) | Err(None) => {
+                self.shader_error_files.remove(key);
+            }
+            Err(Some(error)) => {
+                let files = error.files.iter().map(|file| Rc::new(file.clone()));
+                self.shader_error_files.insert(key.clone(), files.collect());
+            }
+        }
+
+        shader
+    }
+
+    // used by the model loaders, shares the texture with anything else that loaded the same file
+    pub fn texture(
+        &mut self,
+        path: &str,
+        is_normal_map: bool,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Rc<Texture>> {
+        self.load_texture_file(path, is_normal_map, device, queue).1
+    }
+
+    fn load_texture_file(
+        &mut self,
+        path: &str,
+        is_normal_map: bool,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> (Handle<Texture>, Result<Rc<Texture>>) {
+        let path = Self::canonical_path(path);
+        let key = Self::texture_key(path.clone(), is_normal_map);
+
+        self.texture_files
+            .insert(key.clone(), (path.clone(), is_normal_map));
+
+        self.load(key, |_, key| {
+            Texture::from_path(
+                &path,
+                is_normal_map,
+                Self::MIPMAPS,
+                device,
+                queue,
+                Some(key),
+            )
+        })
+    }
+
+    // for images that are already decoded e.g embedded in a glTF file, key should be unique to the image and sampler
+    pub fn texture_from_image(
+        &mut self,
+        key: &str,
+        image: &image::DynamicImage,
+        is_normal_map: bool,
+        sampler: &SamplerSettings,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Rc<Texture>> {
+        let key = Self::texture_key(Self::canonical_path(key), is_normal_map);
+
+        self.load(key, |_, key| {
+            Texture::from_image(
+                image,
+                is_normal_map,
+                Self::MIPMAPS,
+                device,
+                queue,
+                Some(key),
+            )
+            .map(|texture| texture.with_sampler(device, Self::MIPMAPS, sampler))
+        })
+        .1
+    }
+
+    // multiplied with the diffuse color so untextured materials show their mtl color
+    pub fn white_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Rc<Texture>> {
+        self.load(Rc::new(Self::WHITE_KEY.to_string()), |_, key| {
+            Texture::from_color([255, 255, 255, 255], false, device, queue, Some(key))
+        })
+        .1
+    }
+
+    // normal pointing straight out of the surface, for materials without a normal map
+    pub fn flat_normal_map(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Rc<Texture>> {
+        self.load(Rc::new(Self::FLAT_NORMAL_MAP_KEY.to_string()), |_, key| {
+            Texture::from_color([128, 128, 255, 255], true, device, queue, Some(key))
+        })
+        .1
+    }
+}
@@ -2,15 +2,17 @@ use cg::prelude::*;
 use cgmath as cg;
 use winit::event::VirtualKeyCode;
 
+use crate::common::culling::Frustum;
 use crate::common::input;
 
 // opengl NDC has z dimension from -1 to 1, wgpu has it from 0 to 1
+// cgmath matrices are column major so z' = 0.5z + 0.5w comes from the last column
 #[rustfmt::skip]
-const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 pub struct Camera {
@@ -25,6 +27,23 @@ pub struct Camera {
     pub z_far: f32,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view_projection: [[f32; 4]; 4],
+    // w is unused, vec3 in a uniform is padded to 16 bytes anyway
+    position: [f32; 4],
+}
+
+impl From<&Camera> for CameraUniform {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            view_projection: camera.build_view_projection_matrix().into(),
+            position: camera.position.to_homogeneous().into(),
+        }
+    }
+}
+
 impl Camera {
     pub fn build_view_projection_matrix(&self) -> cg::Matrix4<f32> {
         let view = cg::Matrix4::look_at_rh(self.position, self.position + self.direction, self.up);
@@ -38,6 +57,10 @@ impl Camera {
         OPENGL_TO_WGPU_MATRIX * projection * view
     }
 
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.build_view_projection_matrix())
+    }
+
     pub fn update_position(&mut self, delta: instant::Duration) {
         let forward = self.direction.normalize();
         let right = forward.cross(self.up).normalize();
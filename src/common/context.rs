@@ -1,15 +1,203 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use crate::common::render;
+use crate::common::texture::Texture;
+
 pub struct RenderingContext {
     pub wgpu: WgpuContext,
+    pub surface: wgpu::Surface,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub window: winit::window::Window,
 }
 
+// renders into an offscreen texture instead of a window, used by tests and command line tools
+pub struct HeadlessRenderingContext {
+    pub wgpu: WgpuContext,
+    pub size: winit::dpi::PhysicalSize<u32>,
+    pub color_texture: Texture,
+}
+
 pub struct WgpuContext {
-    pub surface: wgpu::Surface,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
-    pub depth_texture: crate::common::texture::Texture,
+    pub depth_texture: Texture,
+    // the scene is rendered into this then tone mapped into the surface
+    pub hdr_texture: Texture,
+    // msaa samples per pixel, 1 = no msaa
+    pub sample_count: u32,
+    // sample counts the color and depth formats support on this adapter, always contains 1
+    pub supported_sample_counts: Vec<u32>,
+    // rendered to instead of the hdr texture when sample_count > 1, then resolved into it
+    pub msaa_texture: Option<Texture>,
+    // textures render graph passes draw into, see create_transient_texture
+    pub transient_textures: render::TransientTextures,
+    // what the adapter can do beyond the webgl2 baseline e.g compute shaders and indirect draws
+    pub downlevel_flags: wgpu::DownlevelFlags,
+}
+
+impl WgpuContext {
+    fn create_instance() -> wgpu::Instance {
+        // instance = establish backend to create surfaces and adapters
+        // all backends = vulkan, dx12, metal
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all() & !wgpu::Backends::BROWSER_WEBGPU,
+            dx12_shader_compiler: Default::default(),
+        })
+    }
+
+    // falls back to no msaa if sample_count isn't supported
+    async fn new(
+        adapter: &wgpu::Adapter,
+        config: wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Result<Self> {
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    // without TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES only 1x and 4x msaa are allowed
+                    // without POLYGON_MODE_LINE the wireframe debug view falls back to a shader, see DebugViews
+                    features: adapter.features()
+                        & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                            | wgpu::Features::POLYGON_MODE_LINE),
+                    limits: wgpu::Limits::default(),
+                    label: None,
+                },
+                None,
+            )
+            .await?;
+
+        let supported_sample_counts =
+            Self::supported_sample_counts(adapter, &device, config.format);
+
+        let sample_count = if supported_sample_counts.contains(&sample_count) {
+            sample_count
+        } else {
+            log::warn!(
+                "{sample_count}x MSAA is not supported, supported sample counts are {supported_sample_counts:?}. Falling back to no MSAA."
+            );
+            1
+        };
+
+        let depth_texture =
+            Texture::create_depth_texture(&device, &config, sample_count, Some("depth-texture"));
+        let hdr_texture = Texture::create_hdr_texture(&device, &config, Some("hdr-texture"));
+        let msaa_texture = Self::create_msaa_texture(&device, &config, sample_count);
+
+        Ok(Self {
+            device,
+            queue,
+            config,
+            depth_texture,
+            hdr_texture,
+            sample_count,
+            supported_sample_counts,
+            msaa_texture,
+            transient_textures: render::TransientTextures::default(),
+            downlevel_flags: adapter.get_downlevel_capabilities().flags,
+        })
+    }
+
+    fn supported_sample_counts(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+    ) -> Vec<u32> {
+        let format_flags = |format: wgpu::TextureFormat| {
+            if device
+                .features()
+                .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            {
+                adapter.get_texture_format_features(format).flags
+            } else {
+                format.guaranteed_format_features(device.features()).flags
+            }
+        };
+
+        let color_flags = format_flags(color_format);
+        let depth_flags = format_flags(Texture::DEPTH_FORMAT);
+
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| {
+                count == 1
+                    || (color_flags.sample_count_supported(count)
+                        && color_flags
+                            .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                        && depth_flags.sample_count_supported(count))
+            })
+            .collect()
+    }
+
+    fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<Texture> {
+        (sample_count > 1).then(|| {
+            Texture::create_msaa_texture(device, config, sample_count, Some("msaa-texture"))
+        })
+    }
+
+    // attachments that match the size of the target have to be recreated when it changes
+    fn recreate_attachments(&mut self) {
+        self.depth_texture = Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.sample_count,
+            Some("depth-texture"),
+        );
+        self.hdr_texture =
+            Texture::create_hdr_texture(&self.device, &self.config, Some("hdr-texture"));
+        self.msaa_texture =
+            Self::create_msaa_texture(&self.device, &self.config, self.sample_count);
+        self.transient_textures.resize(&self.device, &self.config);
+    }
+
+    // lives as long as the context and is resized along with the surface
+    pub fn create_transient_texture(
+        &mut self,
+        descriptor: render::TransientTextureDescriptor,
+    ) -> render::TextureId {
+        self.transient_textures
+            .create(&self.device, &self.config, descriptor)
+    }
+
+    // pipelines rendering into the color attachment have to be recreated with the new sample count
+    pub fn set_sample_count(&mut self, sample_count: u32) -> Result<()> {
+        if !self.supported_sample_counts.contains(&sample_count) {
+            return Err(eyre!(
+                "{sample_count}x MSAA is not supported, supported sample counts are {:?}",
+                self.supported_sample_counts
+            ));
+        }
+
+        self.sample_count = sample_count;
+        self.recreate_attachments();
+
+        Ok(())
+    }
+
+    // renders into the msaa texture and resolves into target when msaa is on, otherwise renders straight into target
+    pub fn color_attachment<'a>(
+        &'a self,
+        target: &'a wgpu::TextureView,
+        ops: wgpu::Operations<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        match &self.msaa_texture {
+            Some(msaa_texture) => wgpu::RenderPassColorAttachment {
+                view: &msaa_texture.view,
+                resolve_target: Some(target),
+                ops,
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops,
+            },
+        }
+    }
 }
 
 impl RenderingContext {
@@ -20,28 +208,18 @@ impl RenderingContext {
         self.size = new_size;
         self.wgpu.config.width = new_size.width;
         self.wgpu.config.height = new_size.height;
-        self.wgpu
-            .surface
-            .configure(&self.wgpu.device, &self.wgpu.config);
+        self.surface.configure(&self.wgpu.device, &self.wgpu.config);
 
-        self.wgpu.depth_texture = crate::common::texture::Texture::create_depth_texture(
-            &self.wgpu.device,
-            &self.wgpu.config,
-            Some("depth-texture"),
-        );
+        self.wgpu.recreate_attachments();
     }
 }
 
 impl RenderingContext {
-    pub async fn new(window: winit::window::Window) -> Self {
+    // sample_count = msaa samples per pixel, 1 = no msaa
+    pub async fn new(window: winit::window::Window, sample_count: u32) -> Self {
         let size = window.inner_size();
 
-        // instance = establish backend to create surfaces and adapters
-        // all backends = vulkan, dx12, metal
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all() & !wgpu::Backends::BROWSER_WEBGPU,
-            dx12_shader_compiler: Default::default(),
-        });
+        let instance = WgpuContext::create_instance();
 
         // the part of the window that we draw to
         let surface = unsafe { instance.create_surface(&window).unwrap() };
@@ -56,18 +234,6 @@ impl RenderingContext {
             .await
             .unwrap();
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
-
         let surface_capabilities = surface.get_capabilities(&adapter);
 
         let surface_format = surface_capabilities
@@ -91,24 +257,153 @@ impl RenderingContext {
             view_formats: vec![],
         };
 
-        surface.configure(&device, &config);
+        let wgpu = WgpuContext::new(&adapter, config, sample_count)
+            .await
+            .unwrap();
 
-        let depth_texture = crate::common::texture::Texture::create_depth_texture(
-            &device,
-            &config,
-            Some("depth-texture"),
-        );
+        surface.configure(&wgpu.device, &wgpu.config);
 
         Self {
-            wgpu: WgpuContext {
-                surface,
-                device,
-                queue,
-                config,
-                depth_texture,
-            },
+            wgpu,
+            surface,
             size,
             window,
         }
     }
 }
+
+impl HeadlessRenderingContext {
+    pub const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    // sample_count = msaa samples per pixel, 1 = no msaa
+    // force_fallback_adapter = use a software renderer e.g. llvmpipe, for machines without a gpu
+    pub async fn new(
+        size: winit::dpi::PhysicalSize<u32>,
+        sample_count: u32,
+        force_fallback_adapter: bool,
+    ) -> Result<Self> {
+        assert_ne!(size.width, 0);
+        assert_ne!(size.height, 0);
+
+        let instance = WgpuContext::create_instance();
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await
+            .ok_or_else(|| eyre!("No suitable adapter found for headless rendering"))?;
+
+        log::info!("Headless rendering with adapter {:?}", adapter.get_info());
+
+        // never used to configure a surface, only describes the offscreen target so that pipelines and depth textures are created the same way as for a window
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: Self::COLOR_FORMAT,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        let wgpu = WgpuContext::new(&adapter, config, sample_count).await?;
+
+        let color_texture =
+            Texture::create_render_target(&wgpu.device, &wgpu.config, Some("color-texture"));
+
+        Ok(Self {
+            wgpu,
+            size,
+            color_texture,
+        })
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        assert_ne!(new_size.width, 0);
+        assert_ne!(new_size.height, 0);
+
+        self.size = new_size;
+        self.wgpu.config.width = new_size.width;
+        self.wgpu.config.height = new_size.height;
+
+        self.color_texture = Texture::create_render_target(
+            &self.wgpu.device,
+            &self.wgpu.config,
+            Some("color-texture"),
+        );
+        self.wgpu.recreate_attachments();
+    }
+
+    // copies the color texture back to the cpu, blocks until the gpu has finished all submitted work
+    pub fn read_frame(&self) -> Result<image::RgbaImage> {
+        let width = self.wgpu.config.width;
+        let height = self.wgpu.config.height;
+
+        // texture to buffer copies need each row to be aligned to 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let readback_buffer = self.wgpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback_buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            self.wgpu
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("readback_encoder"),
+                });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.color_texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        self.wgpu.queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        self.wgpu.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let pixels = buffer_slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect::<Vec<u8>>();
+
+        readback_buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| eyre!("Frame readback has the wrong size for a {width}x{height} image"))
+    }
+}
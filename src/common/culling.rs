@@ -0,0 +1,522 @@
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use crate::common::asset::{AssetServer, Handle};
+use crate::common::context::WgpuContext;
+use crate::common::instance::RawInstance;
+use crate::common::model::InstancedModel;
+use crate::common::pipeline::{self, Shader, ShaderDefines};
+use crate::common::render::RenderData;
+
+// relative to the assets directory
+const SHADER_PATH: &str = "culling.wgsl";
+
+// must match the workgroup size in culling.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+// axis aligned box, in model space for meshes and models
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: cg::Point3<f32>,
+    pub max: cg::Point3<f32>,
+}
+
+impl BoundingBox {
+    // a single point at the origin if there aren't any points
+    pub fn from_points(points: impl IntoIterator<Item = cg::Point3<f32>>) -> Self {
+        let mut points = points.into_iter();
+
+        let Some(first) = points.next() else {
+            return Self {
+                min: cg::Point3::origin(),
+                max: cg::Point3::origin(),
+            };
+        };
+
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |bounds, point| Self {
+                min: cg::Point3::new(
+                    bounds.min.x.min(point.x),
+                    bounds.min.y.min(point.y),
+                    bounds.min.z.min(point.z),
+                ),
+                max: cg::Point3::new(
+                    bounds.max.x.max(point.x),
+                    bounds.max.y.max(point.y),
+                    bounds.max.z.max(point.z),
+                ),
+            },
+        )
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self::from_points([self.min, self.max, other.min, other.max])
+    }
+
+    pub fn center(&self) -> cg::Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    // loose fit but unaffected by rotation, so it only has to be moved to test instances
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: self.center(),
+            radius: (self.max - self.min).magnitude() / 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: cg::Point3<f32>,
+    pub radius: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Plane {
+    // points inside the frustum
+    normal: cg::Vector3<f32>,
+    distance: f32,
+}
+
+impl Plane {
+    fn from_coefficients(coefficients: cg::Vector4<f32>) -> Self {
+        let length = coefficients.truncate().magnitude();
+
+        Self {
+            normal: coefficients.truncate() / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    // positive in front of the plane i.e inside the frustum
+    fn signed_distance(&self, point: cg::Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+// the volume a camera can see, anything completely outside it doesn't need to be drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    // left, right, bottom, top, near, far
+    planes: [Plane; 6],
+}
+
+impl Frustum {
+    // gribb and hartmann, a point is inside when its clip space position is within -w..w for x and y and 0..w for z (wgpu depth range)
+    // each of those inequalities is a plane made from the rows of the matrix
+    // see https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf
+    pub fn from_view_projection(view_projection: cg::Matrix4<f32>) -> Self {
+        let row = |i: usize| view_projection.row(i);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z].map(Plane::from_coefficients),
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_box(&self, bounds: &BoundingBox) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane's normal, if that is behind the plane the whole box is
+            let corner = cg::Point3::new(
+                if plane.normal.x >= 0.0 {
+                    bounds.max.x
+                } else {
+                    bounds.min.x
+                },
+                if plane.normal.y >= 0.0 {
+                    bounds.max.y
+                } else {
+                    bounds.min.y
+                },
+                if plane.normal.z >= 0.0 {
+                    bounds.max.z
+                } else {
+                    bounds.min.z
+                },
+            );
+
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+
+    // the sphere dragged along sweep e.g along the sun's direction to where its shadow could land
+    // conservative, can say a sweep near a corner of the frustum intersects it when it doesn't
+    pub fn intersects_swept_sphere(
+        &self,
+        sphere: &BoundingSphere,
+        sweep: cg::Vector3<f32>,
+    ) -> bool {
+        self.planes.iter().all(|plane| {
+            let start = plane.signed_distance(sphere.center);
+            let end = plane.signed_distance(sphere.center + sweep);
+
+            start.max(end) >= -sphere.radius
+        })
+    }
+
+    // xyz = normal, w = distance, in the same order as planes
+    fn plane_coefficients(&self) -> [[f32; 4]; 6] {
+        self.planes
+            .map(|plane| plane.normal.extend(plane.distance).into())
+    }
+}
+
+// how much of the scene was left to draw after the last RenderData::cull
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub visible_models: u32,
+    pub culled_models: u32,
+    pub visible_instances: u32,
+    pub culled_instances: u32,
+    // instances left to GpuCulling, how many of them are visible is only known on the gpu
+    pub gpu_instances: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullingUniform {
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    sweep: [f32; 4],
+    instance_count: u32,
+    mesh_count: u32,
+    _padding: [u32; 2],
+}
+
+// a model's buffers for culling its instances on the gpu, created by GpuCulling::update
+pub struct IndirectDraws {
+    // every instance, uploaded each frame
+    instance_buffer: wgpu::Buffer,
+    // the instances that passed, packed together at the start, bound as the instance vertex buffer
+    pub visible_instance_buffer: wgpu::Buffer,
+    // a wgpu::util::DrawIndexedIndirect for each mesh
+    pub draw_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // how many instances the buffers have room for
+    capacity: usize,
+    instance_count: u32,
+    mesh_count: u32,
+}
+
+impl IndirectDraws {
+    const DRAW_SIZE: u64 = std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as u64;
+
+    fn new(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        model: &InstancedModel,
+        capacity: usize,
+    ) -> Self {
+        let instances_size = (capacity * std::mem::size_of::<RawInstance>()) as u64;
+        let mesh_count = model.model.meshes.len() as u32;
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(format!("{:?}-culling-instance-buffer", model.model.name).as_str()),
+            size: instances_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let visible_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(format!("{:?}-visible-instance-buffer", model.model.name).as_str()),
+            size: instances_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+
+        let draw_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(format!("{:?}-draw-buffer", model.model.name).as_str()),
+            size: Self::DRAW_SIZE * mesh_count.max(1) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(format!("{:?}-culling-uniform-buffer", model.model.name).as_str()),
+            size: std::mem::size_of::<CullingUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(format!("{:?}-culling-bind-group", model.model.name).as_str()),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: visible_instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: draw_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            instance_buffer,
+            visible_instance_buffer,
+            draw_buffer,
+            uniform_buffer,
+            bind_group,
+            capacity,
+            instance_count: 0,
+            mesh_count,
+        }
+    }
+
+    // where the draw arguments for a mesh are in draw_buffer
+    pub fn draw_offset(mesh_index: usize) -> wgpu::BufferAddress {
+        mesh_index as u64 * Self::DRAW_SIZE
+    }
+}
+
+// culls instances with a compute shader instead of on the cpu, for models with too many instances to go through one by one every frame
+// models without instances are still culled by RenderData::cull
+// only against the frustum, there is no hi-z occlusion culling so instances hidden behind others are still drawn
+pub struct GpuCulling {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    // kept so it stays loaded and the pipelines can be recreated when it's hot reloaded
+    shader: Option<Handle<Shader>>,
+    // cull_instances then write_draw_arguments, None until update_pipelines makes them, everything is culled on the cpu without them
+    pipelines: Option<(wgpu::ComputePipeline, wgpu::ComputePipeline)>,
+    // false makes update_pipelines recreate the pipelines
+    built: bool,
+}
+
+impl GpuCulling {
+    pub fn is_supported(wgpu: &WgpuContext) -> bool {
+        wgpu.downlevel_flags.contains(
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION,
+        )
+    }
+
+    pub fn new(wgpu: &WgpuContext) -> Result<Self> {
+        if !Self::is_supported(wgpu) {
+            return Err(eyre!(
+                "GPU culling needs compute shaders and indirect draws, which this adapter doesn't support"
+            ));
+        }
+
+        let device = &wgpu.device;
+
+        let bind_group_layout = pipeline::create_culling_bind_group_layout(device);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("culling_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Ok(Self {
+            bind_group_layout,
+            pipeline_layout,
+            shader: None,
+            pipelines: None,
+            built: false,
+        })
+    }
+
+    // recreates the pipelines if they haven't been made yet or the shader was reloaded, true if they were
+    // a failure is only returned once, they're tried again when the shader is reloaded
+    pub fn update_pipelines(
+        &mut self,
+        asset_server: &mut AssetServer,
+        device: &wgpu::Device,
+    ) -> Result<bool> {
+        if self.built {
+            return Ok(false);
+        }
+        self.built = true;
+
+        let handle = asset_server.load_shader(SHADER_PATH, &ShaderDefines::new(), device);
+        self.shader = Some(handle.clone());
+
+        let pipelines = asset_server.try_get(&handle).and_then(|shader| {
+            pipeline::validated(device, || {
+                let create = |entry_point: &str, label: &str| {
+                    pipeline::create_compute_pipeline(
+                        device,
+                        &self.pipeline_layout,
+                        &shader.module,
+                        entry_point,
+                        Some(label),
+                    )
+                };
+
+                (
+                    create("cull_instances", "cull_instances_pipeline"),
+                    create("write_draw_arguments", "write_draw_arguments_pipeline"),
+                )
+            })
+        });
+
+        match pipelines {
+            Ok(pipelines) => {
+                self.pipelines = Some(pipelines);
+                Ok(true)
+            }
+            Err(error) => {
+                self.pipelines = None;
+                Err(error)
+            }
+        }
+    }
+
+    // the pipelines are recreated by the next update_pipelines if the shader was reloaded
+    pub fn reload(&mut self, reloaded_shaders: &[Handle<Shader>]) {
+        if self
+            .shader
+            .as_ref()
+            .is_some_and(|handle| reloaded_shaders.contains(handle))
+        {
+            self.built = false;
+        }
+    }
+
+    // uploads every instance and the frustum, call before RenderData::cull so it knows to skip these models
+    // shadow_sweep is the same as for RenderData::cull
+    pub fn update(
+        &self,
+        wgpu: &WgpuContext,
+        render_data: &mut RenderData,
+        frustum: &Frustum,
+        shadow_sweep: Option<cg::Vector3<f32>>,
+    ) {
+        for model in render_data.models.iter_mut() {
+            let instance_data = match &model.instances {
+                // the pipelines couldn't be made, RenderData::cull does it instead
+                _ if self.pipelines.is_none() => {
+                    model.indirect_draws = None;
+                    continue;
+                }
+                Some(instances) if !instances.is_empty() && model.lods.is_empty() => {
+                    instances.iter().map(RawInstance::from).collect::<Vec<_>>()
+                }
+                // nothing to cull, or lods which are picked on the cpu, drawn the normal way
+                _ => {
+                    model.indirect_draws = None;
+                    continue;
+                }
+            };
+
+            let needs_new_buffers = model.indirect_draws.as_ref().is_none_or(|draws| {
+                draws.capacity < instance_data.len()
+                    || draws.mesh_count != model.model.meshes.len() as u32
+            });
+
+            if needs_new_buffers {
+                model.indirect_draws = Some(IndirectDraws::new(
+                    &wgpu.device,
+                    &self.bind_group_layout,
+                    model,
+                    instance_data.len(),
+                ));
+
+                log::info!(
+                    "Created GPU culling buffers for {} instances of model \"{}\"",
+                    instance_data.len(),
+                    model.model.name
+                );
+            }
+
+            let sphere = model.model.bounds.bounding_sphere();
+            let draws = model.indirect_draws.as_mut().unwrap();
+            draws.instance_count = instance_data.len() as u32;
+
+            let uniform = CullingUniform {
+                planes: frustum.plane_coefficients(),
+                sphere: sphere.center.to_vec().extend(sphere.radius).into(),
+                sweep: match shadow_sweep {
+                    Some(sweep) => sweep.extend(1.0).into(),
+                    None => [0.0; 4],
+                },
+                instance_count: draws.instance_count,
+                mesh_count: draws.mesh_count,
+                _padding: [0; 2],
+            };
+
+            // instance counts start at 0 and are counted up by the compute shader
+            let draw_arguments = model
+                .model
+                .meshes
+                .iter()
+                .flat_map(|mesh| {
+                    wgpu::util::DrawIndexedIndirect {
+                        vertex_count: mesh.indices_count,
+                        instance_count: 0,
+                        base_index: 0,
+                        vertex_offset: 0,
+                        base_instance: 0,
+                    }
+                    .as_bytes()
+                    .to_vec()
+                })
+                .collect::<Vec<u8>>();
+
+            wgpu.queue
+                .write_buffer(&draws.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+            wgpu.queue.write_buffer(
+                &draws.instance_buffer,
+                0,
+                bytemuck::cast_slice(&instance_data),
+            );
+            wgpu.queue
+                .write_buffer(&draws.draw_buffer, 0, &draw_arguments);
+        }
+    }
+
+    // fills every model's visible instance buffer and draw arguments, before anything is drawn
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder, render_data: &RenderData) {
+        let Some((cull_instances_pipeline, write_draw_arguments_pipeline)) = &self.pipelines else {
+            return;
+        };
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("culling_pass"),
+        });
+
+        let indirect_draws = render_data
+            .models
+            .iter()
+            .filter_map(|model| model.indirect_draws.as_ref());
+
+        compute_pass.set_pipeline(cull_instances_pipeline);
+        for draws in indirect_draws.clone() {
+            compute_pass.set_bind_group(0, &draws.bind_group, &[]);
+            compute_pass.dispatch_workgroups(draws.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+
+        // separate dispatches so every instance has been counted before the count is copied
+        compute_pass.set_pipeline(write_draw_arguments_pipeline);
+        for draws in indirect_draws {
+            compute_pass.set_bind_group(0, &draws.bind_group, &[]);
+            compute_pass.dispatch_workgroups(draws.mesh_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+}
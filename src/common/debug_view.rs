@@ -0,0 +1,332 @@
+use std::rc::Rc;
+
+use color_eyre::Result;
+use wgpu::util::DeviceExt;
+
+use crate::common::asset::{AssetServer, Handle};
+use crate::common::context::WgpuContext;
+use crate::common::instance::RawInstance;
+use crate::common::model::{BufferContents, DrawModels, ModelVertex, WireframeVertex};
+use crate::common::pipeline::{self, PipelineBuilder, PipelineCache, Shader, ShaderDefines};
+use crate::common::render::RenderData;
+use crate::common::texture::Texture;
+
+// relative to the assets directory, every view is a permutation of it
+const SHADER_PATH: &str = "debug_view.wgsl";
+// meshes after this many get the same slots, and so colors, as the first ones again
+const MAX_MESHES: u32 = 256;
+// dynamic offsets have to be multiples of min_uniform_buffer_offset_alignment, 256 is the most it can be
+const SLOT_SIZE: u64 = 256;
+
+// what the scene is drawn as
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    // the normal shaded scene
+    #[default]
+    Lit,
+    // world space normals, x = red y = green z = blue
+    Normals,
+    // u = red v = green
+    TextureCoords,
+    // distance from the camera, white is close
+    Depth,
+    // brighter where more triangles are drawn on top of each other, hidden ones included
+    Overdraw,
+    // a different random color for every mesh
+    MeshColors,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 6] = [
+        Self::Lit,
+        Self::Normals,
+        Self::TextureCoords,
+        Self::Depth,
+        Self::Overdraw,
+        Self::MeshColors,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Lit => "Lit",
+            Self::Normals => "Normals",
+            Self::TextureCoords => "UVs",
+            Self::Depth => "Depth",
+            Self::Overdraw => "Overdraw",
+            Self::MeshColors => "Mesh colors",
+        }
+    }
+
+    // must match the #ifdefs in debug_view.wgsl, None is drawn by the scene's own pipeline
+    pub fn define(&self) -> Option<&'static str> {
+        match self {
+            Self::Lit => None,
+            Self::Normals => Some("NORMALS"),
+            Self::TextureCoords => Some("TEXTURE_COORDS"),
+            Self::Depth => Some("DEPTH"),
+            Self::Overdraw => Some("OVERDRAW"),
+            Self::MeshColors => Some("MESH_COLORS"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugViewSettings {
+    pub view: DebugView,
+    // triangle edges drawn over the view
+    pub wireframe: bool,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeshUniform {
+    index: u32,
+    _padding: [u32; 3],
+}
+
+// draws the scene in place of or on top of the lit one to see what the renderer is doing
+// wireframes are drawn with PolygonMode::Line if the device has Features::POLYGON_MODE_LINE,
+// otherwise each mesh's WireframeBuffers are drawn with a shader that only keeps the pixels near triangle edges
+pub struct DebugViews {
+    settings: DebugViewSettings,
+    line_polygon_mode: bool,
+    pipeline_layout: wgpu::PipelineLayout,
+    // one MeshUniform per SLOT_SIZE, only written once
+    _mesh_buffer: wgpu::Buffer,
+    mesh_bind_group: wgpu::BindGroup,
+    // the permutations the pipelines below were made from, kept so they stay loaded and can be hot reloaded
+    shaders: Vec<Handle<Shader>>,
+    view_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    wireframe_pipeline: Option<Rc<wgpu::RenderPipeline>>,
+    // settings and sample count the pipelines were made for, None makes update recreate them
+    built_for: Option<(DebugViewSettings, u32)>,
+}
+
+impl DebugViews {
+    pub fn new(wgpu: &WgpuContext) -> Self {
+        let device = &wgpu.device;
+
+        let mesh_bind_group_layout = pipeline::create_debug_mesh_bind_group_layout(device);
+        let camera_bind_group_layout = pipeline::create_camera_bind_group_layout(device);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("debug_view_pipeline_layout"),
+            bind_group_layouts: &[&mesh_bind_group_layout, &camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut mesh_slots = vec![0u8; (SLOT_SIZE * MAX_MESHES as u64) as usize];
+        for (index, slot) in mesh_slots.chunks_exact_mut(SLOT_SIZE as usize).enumerate() {
+            let uniform = MeshUniform {
+                index: index as u32,
+                _padding: [0; 3],
+            };
+            slot[..std::mem::size_of::<MeshUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+
+        let mesh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("debug_view_mesh_buffer"),
+            contents: &mesh_slots,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mesh_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("debug_view_mesh_bind_group"),
+            layout: &mesh_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &mesh_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<MeshUniform>() as u64),
+                }),
+            }],
+        });
+
+        Self {
+            settings: DebugViewSettings::default(),
+            line_polygon_mode: device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE),
+            pipeline_layout,
+            _mesh_buffer: mesh_buffer,
+            mesh_bind_group,
+            shaders: Vec::new(),
+            view_pipeline: None,
+            wireframe_pipeline: None,
+            built_for: None,
+        }
+    }
+
+    // the dynamic offset of a mesh's slot in the mesh bind group, meshes are counted across every model and lod in RenderData
+    pub fn mesh_offset(mesh_index: u32) -> u32 {
+        ((mesh_index % MAX_MESHES) as u64 * SLOT_SIZE) as u32
+    }
+
+    pub fn settings(&self) -> &DebugViewSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: DebugViewSettings) {
+        self.settings = settings;
+    }
+
+    // whether draw draws the whole scene so the lit one doesn't have to be
+    // false if the view's pipeline couldn't be made, the lit scene is drawn instead
+    pub fn replaces_scene(&self) -> bool {
+        self.view_pipeline.is_some()
+    }
+
+    // recreates the pipelines if the settings or sample count changed since they were last made, true if they were
+    // a failure is only returned once, the pipelines are tried again when the settings change or the shader is reloaded
+    pub fn update(
+        &mut self,
+        asset_server: &mut AssetServer,
+        pipeline_cache: &mut PipelineCache,
+        wgpu: &WgpuContext,
+    ) -> Result<bool> {
+        let built_for = Some((self.settings.clone(), wgpu.sample_count));
+        if self.built_for == built_for {
+            return Ok(false);
+        }
+        self.built_for = built_for;
+
+        self.shaders.clear();
+
+        let view = self.settings.view.define().map(|define| {
+            let builder = if self.settings.view == DebugView::Overdraw {
+                // every triangle is added on top of what's there, including the ones behind others
+                let additive = wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                };
+
+                PipelineBuilder::new()
+                    .vertex_buffers(&[ModelVertex::buffer_layout(), RawInstance::buffer_layout()])
+                    .color_target(Texture::HDR_FORMAT, Some(additive))
+                    .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::Always)
+                    .sample_count(wgpu.sample_count)
+            } else {
+                pipeline::scene_pipeline_builder(wgpu.sample_count)
+            };
+
+            (define, builder)
+        });
+
+        let wireframe = self.settings.wireframe.then(|| {
+            // only drawn where it's in front of or on the surface that's already there, pulled towards the camera so it doesn't flicker with it
+            let builder = PipelineBuilder::new()
+                .color_target(Texture::HDR_FORMAT, None)
+                .depth(
+                    Texture::DEPTH_FORMAT,
+                    false,
+                    wgpu::CompareFunction::LessEqual,
+                )
+                .depth_bias(wgpu::DepthBiasState {
+                    constant: -2,
+                    slope_scale: -1.0,
+                    clamp: 0.0,
+                })
+                .sample_count(wgpu.sample_count);
+
+            if self.line_polygon_mode {
+                (
+                    "WIREFRAME",
+                    builder
+                        .vertex_buffers(&[
+                            ModelVertex::buffer_layout(),
+                            RawInstance::buffer_layout(),
+                        ])
+                        .polygon_mode(wgpu::PolygonMode::Line),
+                )
+            } else {
+                (
+                    "BARYCENTRIC_WIREFRAME",
+                    builder.vertex_buffers(&[
+                        WireframeVertex::buffer_layout(),
+                        RawInstance::buffer_layout(),
+                    ]),
+                )
+            }
+        });
+
+        let mut create = |(define, builder): (&str, PipelineBuilder)| {
+            self.create_pipeline(asset_server, pipeline_cache, wgpu, define, &builder)
+        };
+        let view_pipeline = view.map(&mut create).transpose();
+        let wireframe_pipeline = wireframe.map(&mut create).transpose();
+
+        // one failing doesn't stop the other from being drawn
+        self.view_pipeline = view_pipeline.as_ref().ok().cloned().flatten();
+        self.wireframe_pipeline = wireframe_pipeline.as_ref().ok().cloned().flatten();
+
+        view_pipeline.and(wireframe_pipeline).map(|_| true)
+    }
+
+    fn create_pipeline(
+        &mut self,
+        asset_server: &mut AssetServer,
+        pipeline_cache: &mut PipelineCache,
+        wgpu: &WgpuContext,
+        define: &str,
+        builder: &PipelineBuilder,
+    ) -> Result<Rc<wgpu::RenderPipeline>> {
+        let defines = ShaderDefines::new().with(define);
+        let handle = asset_server.load_shader(SHADER_PATH, &defines, &wgpu.device);
+        self.shaders.push(handle.clone());
+
+        let shader = asset_server.try_get(&handle)?;
+
+        pipeline_cache.get(
+            builder,
+            &wgpu.device,
+            &self.pipeline_layout,
+            &shader.module,
+            &format!("debug_view_pipeline_{}", define.to_lowercase()),
+        )
+    }
+
+    // the pipelines are recreated by the next update if any of their shaders were reloaded
+    pub fn reload(&mut self, reloaded_shaders: &[Handle<Shader>]) {
+        if self
+            .shaders
+            .iter()
+            .any(|handle| reloaded_shaders.contains(handle))
+        {
+            self.built_for = None;
+        }
+    }
+
+    // into the main pass after or instead of the lit scene, see replaces_scene
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        render_data: &'a RenderData,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.view_pipeline.is_none() && self.wireframe_pipeline.is_none() {
+            return;
+        }
+
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+
+        if let Some(view_pipeline) = &self.view_pipeline {
+            render_pass.set_pipeline(view_pipeline);
+            render_pass.draw_models_debug(render_data, &self.mesh_bind_group, false);
+        }
+
+        if let Some(wireframe_pipeline) = &self.wireframe_pipeline {
+            render_pass.set_pipeline(wireframe_pipeline);
+            render_pass.draw_models_debug(
+                render_data,
+                &self.mesh_bind_group,
+                !self.line_polygon_mode,
+            );
+        }
+    }
+}
@@ -1,34 +1,61 @@
+use std::collections::BTreeMap;
+
 use color_eyre::eyre::Result;
 use egui_winit_platform::PlatformDescriptor;
 
 use crate::common::context;
 use crate::common::context::RenderingContext;
+use crate::common::debug_view;
 use crate::common::input;
+use crate::common::lod;
+use crate::common::post_process;
+use crate::common::shadow;
+use crate::common::tone_mapping;
 
 #[derive(Debug)]
 pub enum GuiEvent {
     RequestRedraw,
 }
 
-// egui hook into winit so that it can request redraws from separate threads
-struct RepaintSignal(std::sync::Mutex<winit::event_loop::EventLoopProxy<GuiEvent>>);
+pub struct GuiState {
+    // edited through the gui, applied to the shadow map after the frame
+    pub shadow_settings: shadow::ShadowSettings,
+    // msaa samples per pixel, applied to the context after the frame
+    pub sample_count: u32,
+    pub tone_mapping_settings: tone_mapping::ToneMappingSettings,
+    // copied from the post process stack once its effects are added
+    pub post_process_settings: post_process::PostProcessSettings,
+    pub lod_settings: lod::LodSettings,
+    // one list per model in RenderData, filled once the scene is loaded and applied to the lods after the frame
+    pub lod_thresholds: Vec<Vec<f32>>,
+    pub debug_view_settings: debug_view::DebugViewSettings,
+    // why each part of the renderer e.g "scene" or "shadows" isn't drawn with the shaders on disk, until it's fixed
+    // kept apart so one part being fixed doesn't clear the error of another
+    pub shader_errors: BTreeMap<&'static str, String>,
+}
 
-impl epi::backend::RepaintSignal for RepaintSignal {
-    fn request_repaint(&self) {
-        self.0
-            .lock()
-            .unwrap()
-            .send_event(GuiEvent::RequestRedraw)
-            .ok();
+impl GuiState {
+    // result is whether the part's pipelines were recreated, an error stays in shader_errors until they are made again
+    pub fn show_pipeline_result(&mut self, part: &'static str, result: Result<bool>) {
+        match result {
+            Ok(true) => {
+                self.shader_errors.remove(part);
+            }
+            Ok(false) => {}
+            Err(error) => {
+                let error = format!("Couldn't create {part} pipelines: {error}");
+                log::error!("{error}");
+                self.shader_errors.insert(part, error);
+            }
+        }
     }
 }
 
-pub struct GuiState {}
-
 pub struct Gui {
     pub render_pass: egui_wgpu_backend::RenderPass,
     pub platform: egui_winit_platform::Platform,
     pub state: GuiState,
+    supported_sample_counts: Vec<u32>,
 }
 
 impl Gui {
@@ -41,15 +68,26 @@ impl Gui {
             style: egui::Style::default(),
         });
 
+        // drawn straight onto the surface after the scene has been resolved, egui does its own anti aliasing so it doesn't need msaa
         let render_pass =
             egui_wgpu_backend::RenderPass::new(&context.wgpu.device, context.wgpu.config.format, 1);
 
-        let state = GuiState {};
+        let state = GuiState {
+            shadow_settings: shadow::ShadowSettings::default(),
+            sample_count: context.wgpu.sample_count,
+            tone_mapping_settings: tone_mapping::ToneMappingSettings::default(),
+            post_process_settings: post_process::PostProcessSettings::default(),
+            lod_settings: lod::LodSettings::default(),
+            lod_thresholds: vec![],
+            debug_view_settings: debug_view::DebugViewSettings::default(),
+            shader_errors: BTreeMap::new(),
+        };
 
         Ok(Self {
             render_pass,
             platform,
             state,
+            supported_sample_counts: context.wgpu.supported_sample_counts.clone(),
         })
     }
 
@@ -129,22 +167,261 @@ impl Gui {
                 ui.colored_label(color, text);
             });
 
+        if !self.state.shader_errors.is_empty() {
+            egui::Window::new("shader errors").show(&self.platform.context(), |ui| {
+                for (part, shader_error) in self.state.shader_errors.iter() {
+                    ui.heading(*part);
+                    ui.label(
+                        egui::RichText::new(shader_error)
+                            .monospace()
+                            .color(egui::Color32::RED),
+                    );
+                }
+            });
+        }
+
         egui::Window::new("scene-viewer").show(&self.platform.context(), |ui| {
+            let stats = &render_data.culling_stats;
+            ui.label(format!(
+                "Models: {} visible, {} culled",
+                stats.visible_models, stats.culled_models
+            ));
+            ui.label(format!(
+                "Instances: {} visible, {} culled",
+                stats.visible_instances, stats.culled_instances
+            ));
+            if stats.gpu_instances > 0 {
+                ui.label(format!(
+                    "Instances culled on the GPU: {} (count not read back)",
+                    stats.gpu_instances
+                ));
+            }
+            ui.separator();
+
             egui::ScrollArea::vertical()
                 .max_height(200.0)
                 .auto_shrink([false; 2])
                 .show(ui, |ui| {
                     for model in render_data.models.iter() {
+                        let lod_instance_counts = model
+                            .lods
+                            .iter()
+                            .map(|lod| lod.visible_instances.len().to_string())
+                            .collect::<Vec<_>>();
+
                         ui.label(format!(
-                            "{} | {}",
+                            "{} | {}/{}{}",
                             model.model.name.as_str(),
+                            model.drawn_instance_count(),
                             model
                                 .instances
                                 .as_ref()
-                                .map_or(1, |instances| instances.len())
+                                .map_or(1, |instances| instances.len()),
+                            if lod_instance_counts.is_empty() {
+                                String::new()
+                            } else {
+                                format!(" | LODs {}", lod_instance_counts.join("/"))
+                            }
                         ));
                     }
                 });
+
+            ui.separator();
+
+            let lod_settings = &mut self.state.lod_settings;
+            ui.checkbox(&mut lod_settings.crossfade, "LOD crossfade");
+            ui.add(
+                egui::Slider::new(&mut lod_settings.crossfade_width, 0.01..=1.0)
+                    .text("Crossfade width"),
+            );
+            ui.add(egui::Slider::new(&mut lod_settings.bias, 0.1..=4.0).text("LOD bias"));
+
+            for (model, thresholds) in render_data
+                .models
+                .iter()
+                .zip(self.state.lod_thresholds.iter_mut())
+                .filter(|(_, thresholds)| !thresholds.is_empty())
+            {
+                ui.label(model.model.name.as_str());
+
+                // fraction of the screen height or distance in world units, see LodMetric
+                let range = match model.lod_metric {
+                    lod::LodMetric::ScreenSize => 0.0..=1.0,
+                    lod::LodMetric::Distance => 0.0..=100.0,
+                };
+
+                for (level, threshold) in thresholds.iter_mut().enumerate() {
+                    ui.add(
+                        egui::Slider::new(threshold, range.clone())
+                            .text(format!("LOD {} threshold", level + 1)),
+                    );
+                }
+            }
+        });
+
+        egui::Window::new("graphics").show(&self.platform.context(), |ui| {
+            egui::ComboBox::from_label("MSAA")
+                .selected_text(format!("{}x", self.state.sample_count))
+                .show_ui(ui, |ui| {
+                    for &sample_count in self.supported_sample_counts.iter() {
+                        ui.selectable_value(
+                            &mut self.state.sample_count,
+                            sample_count,
+                            format!("{sample_count}x"),
+                        );
+                    }
+                });
+
+            let tone_mapping_settings = &mut self.state.tone_mapping_settings;
+
+            egui::ComboBox::from_label("Tone mapping")
+                .selected_text(tone_mapping_settings.operator.name())
+                .show_ui(ui, |ui| {
+                    for operator in tone_mapping::ToneMappingOperator::ALL {
+                        ui.selectable_value(
+                            &mut tone_mapping_settings.operator,
+                            operator,
+                            operator.name(),
+                        );
+                    }
+                });
+
+            ui.checkbox(&mut tone_mapping_settings.auto_exposure, "Auto exposure");
+            if tone_mapping_settings.auto_exposure {
+                ui.add(
+                    egui::Slider::new(&mut tone_mapping_settings.exposure_compensation, -4.0..=4.0)
+                        .text("Compensation (EV)"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut tone_mapping_settings.adaptation_speed, 0.1..=10.0)
+                        .text("Adaptation speed"),
+                );
+            } else {
+                ui.add(
+                    egui::Slider::new(&mut tone_mapping_settings.exposure, -8.0..=8.0)
+                        .text("Exposure (EV)"),
+                );
+            }
+            ui.add(egui::Slider::new(&mut tone_mapping_settings.gamma, 1.0..=3.0).text("Gamma"));
+
+            ui.separator();
+
+            let debug_view_settings = &mut self.state.debug_view_settings;
+
+            egui::ComboBox::from_label("View")
+                .selected_text(debug_view_settings.view.name())
+                .show_ui(ui, |ui| {
+                    for view in debug_view::DebugView::ALL {
+                        ui.selectable_value(&mut debug_view_settings.view, view, view.name());
+                    }
+                });
+            ui.checkbox(&mut debug_view_settings.wireframe, "Wireframe");
+        });
+
+        let post_process_settings = &mut self.state.post_process_settings;
+
+        egui::Window::new("post processing").show(&self.platform.context(), |ui| {
+            // swapped after the loop so the list isn't changed while iterating it
+            let mut swap = None;
+            let effect_count = post_process_settings.effects.len();
+
+            for (i, effect) in post_process_settings.effects.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut effect.enabled, effect.name.as_str());
+
+                    if ui
+                        .add_enabled(i > 0, egui::Button::new("▲").small())
+                        .clicked()
+                    {
+                        swap = Some((i - 1, i));
+                    }
+                    if ui
+                        .add_enabled(i + 1 < effect_count, egui::Button::new("▼").small())
+                        .clicked()
+                    {
+                        swap = Some((i, i + 1));
+                    }
+                });
+
+                if effect.enabled {
+                    ui.indent(&effect.name, |ui| {
+                        for parameter in effect.parameters.iter_mut() {
+                            ui.add(
+                                egui::Slider::new(&mut parameter.value, parameter.range.clone())
+                                    .text(parameter.name.as_str()),
+                            );
+                        }
+                    });
+                }
+            }
+
+            if let Some((a, b)) = swap {
+                post_process_settings.effects.swap(a, b);
+            }
+        });
+
+        let shadow_settings = &mut self.state.shadow_settings;
+
+        egui::Window::new("shadows").show(&self.platform.context(), |ui| {
+            egui::ComboBox::from_label("Resolution")
+                .selected_text(shadow_settings.resolution.to_string())
+                .show_ui(ui, |ui| {
+                    for resolution in [512, 1024, 2048, 4096] {
+                        ui.selectable_value(
+                            &mut shadow_settings.resolution,
+                            resolution,
+                            resolution.to_string(),
+                        );
+                    }
+                });
+
+            ui.add(
+                egui::Slider::new(
+                    &mut shadow_settings.cascade_count,
+                    1..=shadow::MAX_CASCADES as u32,
+                )
+                .text("Cascades"),
+            );
+            ui.add(
+                egui::Slider::new(&mut shadow_settings.max_distance, 10.0..=200.0)
+                    .text("Max distance"),
+            );
+            ui.add(
+                egui::Slider::new(&mut shadow_settings.split_lambda, 0.0..=1.0)
+                    .text("Split lambda"),
+            );
+            ui.add(
+                egui::Slider::new(&mut shadow_settings.blend_fraction, 0.0..=0.5)
+                    .text("Cascade blend"),
+            );
+            ui.add(egui::Slider::new(&mut shadow_settings.pcf_radius, 0..=4).text("PCF radius"));
+            ui.add(
+                egui::Slider::new(&mut shadow_settings.constant_bias, 0..=16).text("Constant bias"),
+            );
+            ui.add(
+                egui::Slider::new(&mut shadow_settings.slope_bias, 0.0..=8.0).text("Slope bias"),
+            );
+            ui.add(
+                egui::Slider::new(&mut shadow_settings.comparison_bias, 0.0..=0.01)
+                    .text("Comparison bias"),
+            );
+            ui.add(
+                egui::Slider::new(&mut shadow_settings.normal_bias, 0.0..=4.0).text("Normal bias"),
+            );
+
+            ui.checkbox(&mut shadow_settings.debug_cascades, "Show cascades");
+            if shadow_settings.debug_cascades {
+                ui.horizontal(|ui| {
+                    for (i, [r, g, b]) in shadow::CASCADE_DEBUG_COLORS
+                        .iter()
+                        .take(shadow_settings.cascade_count as usize)
+                        .enumerate()
+                    {
+                        let color = egui::Rgba::from_rgb(*r, *g, *b);
+                        ui.colored_label(color, format!("Cascade {i}"));
+                    }
+                });
+            }
         });
     }
 }
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use instant::{Duration, Instant};
+use rustc_hash::FxHashMap;
+
+// notices files in a directory being changed by checking when they were last modified every so often
+// polling a few dozen files is cheap enough that it doesn't need a thread or a platform specific watcher
+pub struct FileWatcher {
+    root: PathBuf,
+    modified_times: FxHashMap<PathBuf, SystemTime>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let modified_times = Self::scan(&root);
+
+        log::info!(
+            "Watching {} files in \"{}\" for changes",
+            modified_times.len(),
+            root.display()
+        );
+
+        Self {
+            root,
+            modified_times,
+            interval: Self::DEFAULT_INTERVAL,
+            last_poll: Instant::now(),
+        }
+    }
+
+    // canonical paths of the files that were changed, created or removed since the last poll
+    // a removed file is reported so whatever was loaded from it can fail to reload instead of silently going stale
+    // does nothing until the interval has passed so it can be called every frame
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < self.interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let modified_times = Self::scan(&self.root);
+
+        let changed = modified_times
+            .iter()
+            .filter(|(path, modified)| self.modified_times.get(*path) != Some(modified))
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<PathBuf>>();
+
+        for path in changed.iter() {
+            log::info!("File \"{}\" changed", path.display());
+        }
+
+        let removed = self
+            .modified_times
+            .keys()
+            .filter(|path| !modified_times.contains_key(*path))
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+
+        for path in removed.iter() {
+            log::info!("File \"{}\" removed", path.display());
+        }
+
+        self.modified_times = modified_times;
+
+        changed.into_iter().chain(removed).collect()
+    }
+
+    fn scan(root: &Path) -> FxHashMap<PathBuf, SystemTime> {
+        let mut modified_times = FxHashMap::default();
+        let mut directories = vec![root.to_path_buf()];
+
+        while let Some(directory) = directories.pop() {
+            // files can disappear while they're being looked at e.g editors saving through a temporary file
+            let Ok(entries) = std::fs::read_dir(&directory) else {
+                continue;
+            };
+
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+
+                if metadata.is_dir() {
+                    directories.push(path);
+                } else if let (Ok(modified), Ok(path)) = (metadata.modified(), path.canonicalize())
+                {
+                    modified_times.insert(path, modified);
+                }
+            }
+        }
+
+        modified_times
+    }
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::Result;
+use rustc_hash::FxHashMap;
+
+use crate::common::model::{MeshData, ModelVertex};
+use crate::common::optimize;
+
+// how normals are made for meshes that don't have any
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NormalGeneration {
+    // averaged over the triangles around each position, weighted by their area
+    #[default]
+    Smooth,
+    // each triangle's own normal, splits every vertex between the triangles using it
+    Flat,
+}
+
+// how texture coordinates are made for meshes that don't have any
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureCoordGeneration {
+    // every vertex samples the corner of the texture
+    Zero,
+    // each triangle is projected onto the axis plane it faces most, one texture repeat per unit
+    #[default]
+    BoxProjection,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportSettings {
+    pub normals: NormalGeneration,
+    pub texture_coords: TextureCoordGeneration,
+}
+
+// something a model was missing that the loader had to make up
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImportWarning {
+    MissingNormals {
+        mesh: String,
+        generated: NormalGeneration,
+    },
+    MissingTextureCoords {
+        mesh: String,
+        generated: TextureCoordGeneration,
+    },
+}
+
+impl fmt::Display for ImportWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingNormals { mesh, generated } => {
+                let generated = match generated {
+                    NormalGeneration::Smooth => "generated smooth normals",
+                    NormalGeneration::Flat => "generated flat normals",
+                };
+
+                write!(f, "Mesh \"{}\" has no normals, {}", mesh, generated)
+            }
+            Self::MissingTextureCoords { mesh, generated } => {
+                let generated = match generated {
+                    TextureCoordGeneration::Zero => "using (0, 0)",
+                    TextureCoordGeneration::BoxProjection => "projected them from the positions",
+                };
+
+                write!(
+                    f,
+                    "Mesh \"{}\" has no texture coordinates, {}",
+                    mesh, generated
+                )
+            }
+        }
+    }
+}
+
+pub struct ObjFile {
+    pub meshes: Vec<MeshData>,
+    // a missing or broken mtl file is an error here, the meshes are still usable without it
+    pub materials: Result<Vec<tobj::Material>, tobj::LoadError>,
+    pub warnings: Vec<ImportWarning>,
+}
+
+// loads the meshes of an obj file, filling in any normals or texture coordinates they are missing
+pub fn load_obj(path: &str, settings: &ImportSettings) -> Result<ObjFile> {
+    let (models, materials) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ..Default::default()
+        },
+    )?;
+
+    let mut warnings = vec![];
+    let meshes = models
+        .into_iter()
+        .map(|model| {
+            let (mesh, mesh_warnings) = MeshData::from_obj(model, settings);
+            warnings.extend(mesh_warnings);
+            mesh
+        })
+        .collect();
+
+    Ok(ObjFile {
+        meshes,
+        materials,
+        warnings,
+    })
+}
+
+// the mtl files an obj file uses, resolved next to it the same way tobj does
+pub fn obj_material_files(path: &str) -> Result<Vec<PathBuf>> {
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+        // the file name can include spaces
+        .map(|file_name| directory.join(file_name.trim()))
+        .collect())
+}
+
+fn face_normal(positions: [cg::Vector3<f32>; 3]) -> cg::Vector3<f32> {
+    let [a, b, c] = positions;
+    // not normalized, the length is twice the triangle's area
+    (b - a).cross(c - a)
+}
+
+// one normal per position, vertices split at uv seams still get the same normal so the seam doesn't show
+pub fn smooth_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut position_ids = FxHashMap::<[u32; 3], usize>::default();
+    let vertex_position_ids = positions
+        .iter()
+        .map(|position| {
+            let next_id = position_ids.len();
+            *position_ids
+                .entry(position.map(|component| (component + 0.0).to_bits()))
+                .or_insert(next_id)
+        })
+        .collect::<Vec<usize>>();
+
+    let mut normals = vec![cg::Vector3::<f32>::zero(); position_ids.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let normal = face_normal([0, 1, 2].map(|i| positions[triangle[i] as usize].into()));
+
+        for &vertex in triangle {
+            normals[vertex_position_ids[vertex as usize]] += normal;
+        }
+    }
+
+    vertex_position_ids
+        .iter()
+        .map(|&id| {
+            let normal = normals[id];
+
+            // only used by degenerate triangles, any direction will do
+            if normal.magnitude2() > 0.0 {
+                normal.normalize().into()
+            } else {
+                [0.0, 1.0, 0.0]
+            }
+        })
+        .collect()
+}
+
+// gives every triangle its own vertices so they can be changed per triangle, welded back together after
+fn unshare(vertices: &[ModelVertex], indices: &[u32]) -> Vec<ModelVertex> {
+    indices
+        .iter()
+        .map(|&index| vertices[index as usize])
+        .collect()
+}
+
+fn triangle_positions(triangle: &[ModelVertex]) -> [cg::Vector3<f32>; 3] {
+    [0, 1, 2].map(|i| triangle[i].position.into())
+}
+
+pub fn generate_normals(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    generation: NormalGeneration,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    match generation {
+        NormalGeneration::Smooth => {
+            let positions = vertices
+                .iter()
+                .map(|vertex| vertex.position)
+                .collect::<Vec<[f32; 3]>>();
+
+            let vertices = vertices
+                .iter()
+                .zip(smooth_normals(&positions, indices))
+                .map(|(vertex, normal)| ModelVertex { normal, ..*vertex })
+                .collect();
+
+            (vertices, indices.to_vec())
+        }
+        NormalGeneration::Flat => {
+            let mut vertices = unshare(vertices, indices);
+
+            for triangle in vertices.chunks_exact_mut(3) {
+                let normal = face_normal(triangle_positions(triangle));
+                let normal = if normal.magnitude2() > 0.0 {
+                    normal.normalize().into()
+                } else {
+                    [0.0, 1.0, 0.0]
+                };
+
+                for vertex in triangle.iter_mut() {
+                    vertex.normal = normal;
+                }
+            }
+
+            optimize::weld(&vertices, &(0..vertices.len() as u32).collect::<Vec<u32>>())
+        }
+    }
+}
+
+pub fn generate_texture_coords(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    generation: TextureCoordGeneration,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    match generation {
+        TextureCoordGeneration::Zero => {
+            let vertices = vertices
+                .iter()
+                .map(|vertex| ModelVertex {
+                    texture_coords: [0.0; 2],
+                    ..*vertex
+                })
+                .collect();
+
+            (vertices, indices.to_vec())
+        }
+        TextureCoordGeneration::BoxProjection => {
+            let mut vertices = unshare(vertices, indices);
+
+            for triangle in vertices.chunks_exact_mut(3) {
+                let normal = face_normal(triangle_positions(triangle));
+                let (x, y, z) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+
+                for vertex in triangle.iter_mut() {
+                    let [px, py, pz] = vertex.position;
+
+                    // v goes down the texture, so up in the world is -v
+                    vertex.texture_coords = if x >= y && x >= z {
+                        [pz, -py]
+                    } else if y >= z {
+                        [px, pz]
+                    } else {
+                        [px, -py]
+                    };
+                }
+            }
+
+            optimize::weld(&vertices, &(0..vertices.len() as u32).collect::<Vec<u32>>())
+        }
+    }
+}
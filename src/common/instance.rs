@@ -25,11 +25,27 @@ pub struct Instances {
 
 #[repr(C)]
 #[derive(Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct RawInstance([[f32; 4]; 4]);
+pub struct RawInstance {
+    model: [[f32; 4]; 4],
+    // dithers the instance out while it crossfades between lods, see lod.rs
+    fade: f32,
+    // 16 byte aligned so it matches the Instance struct in culling.wgsl
+    _padding: [f32; 3],
+}
 
 impl RawInstance {
-    const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 4] =
-        wgpu::vertex_attr_array![3 => Float32x4, 4 => Float32x4, 5 => Float32x4, 6 => Float32x4];
+    const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![5 => Float32x4, 6 => Float32x4, 7 => Float32x4, 8 => Float32x4, 9 => Float32];
+
+    // fade = 1 draws the whole instance
+    pub fn new(instance: &ModelInstance, fade: f32) -> Self {
+        Self {
+            model: (cg::Matrix4::from_translation(instance.position)
+                * cg::Matrix4::from(instance.rotation))
+            .into(),
+            fade,
+            _padding: [0.0; 3],
+        }
+    }
 }
 
 impl BufferContents for RawInstance {
@@ -45,10 +61,6 @@ impl BufferContents for RawInstance {
 
 impl From<&ModelInstance> for RawInstance {
     fn from(instance: &ModelInstance) -> Self {
-        RawInstance(
-            (cg::Matrix4::from_translation(instance.position)
-                * cg::Matrix4::from(instance.rotation))
-            .into(),
-        )
+        RawInstance::new(instance, 1.0)
     }
 }
@@ -0,0 +1,92 @@
+use cgmath as cg;
+
+// must match MAX_LIGHTS in shader.wgsl
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Clone, Debug)]
+pub enum Light {
+    // infinitely far away e.g the sun, direction is the way the light travels
+    Directional {
+        direction: cg::Vector3<f32>,
+        color: cg::Vector3<f32>,
+        intensity: f32,
+    },
+    // light fades out to nothing at range
+    Point {
+        position: cg::Point3<f32>,
+        color: cg::Vector3<f32>,
+        intensity: f32,
+        range: f32,
+    },
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RawLight {
+    // w = 0 for directional lights (xyz = direction), w = 1 for point lights (xyz = position)
+    position: [f32; 4],
+    color: [f32; 3],
+    intensity: f32,
+    range: f32,
+    _padding: [f32; 3],
+}
+
+impl From<&Light> for RawLight {
+    fn from(light: &Light) -> Self {
+        match *light {
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+            } => Self {
+                position: direction.extend(0.0).into(),
+                color: color.into(),
+                intensity,
+                ..Default::default()
+            },
+            Light::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => Self {
+                position: position.to_homogeneous().into(),
+                color: color.into(),
+                intensity,
+                range,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightsUniform {
+    ambient: [f32; 3],
+    count: u32,
+    lights: [RawLight; MAX_LIGHTS],
+}
+
+impl LightsUniform {
+    pub fn new(ambient: cg::Vector3<f32>, lights: &[Light]) -> Self {
+        if lights.len() > MAX_LIGHTS {
+            log::warn!(
+                "{} lights in scene but only {} are supported, ignoring the rest",
+                lights.len(),
+                MAX_LIGHTS
+            );
+        }
+
+        let mut raw_lights = [RawLight::default(); MAX_LIGHTS];
+        for (raw_light, light) in raw_lights.iter_mut().zip(lights.iter()) {
+            *raw_light = RawLight::from(light);
+        }
+
+        Self {
+            ambient: ambient.into(),
+            count: lights.len().min(MAX_LIGHTS) as u32,
+            lights: raw_lights,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use crate::common::asset::{AssetServer, Handle, LoadState};
+use crate::common::camera::Camera;
+use crate::common::context::WgpuContext;
+use crate::common::model::{InstancedModel, Model, VisibleInstance};
+
+// what decides when a lower detail lod is used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LodMetric {
+    // fraction of the screen height the model's bounding sphere covers, a lod is used once the instance is smaller than its threshold
+    ScreenSize,
+    // distance from the camera, a lod is used once the instance is further away than its threshold
+    Distance,
+}
+
+// a lower detail version of a model, drawn instead of it for instances that are small on screen or far away
+pub struct Lod {
+    pub model: Rc<Model>,
+    // set if the model was loaded through an AssetServer so it can be swapped when reloaded
+    pub handle: Option<Handle<Model>>,
+    // see LodMetric, each lod's threshold should be past the one before it
+    pub threshold: f32,
+    // set by RenderData::select_lods
+    pub visible_instances: Vec<VisibleInstance>,
+    pub instance_buffer: Option<wgpu::Buffer>,
+}
+
+impl Lod {
+    pub fn new(model: Rc<Model>, threshold: f32) -> Self {
+        Self {
+            model,
+            handle: None,
+            threshold,
+            visible_instances: vec![],
+            instance_buffer: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodSettings {
+    // dither between two lods just before the threshold instead of popping from one to the other
+    pub crossfade: bool,
+    // fraction of the threshold the crossfade happens over
+    pub crossfade_width: f32,
+    // > 1 keeps higher detail lods for longer, < 1 switches to lower detail sooner
+    pub bias: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            crossfade: true,
+            crossfade_width: 0.2,
+            bias: 1.0,
+        }
+    }
+}
+
+// loads name_lod0.ext as the model and name_lod1.ext, name_lod2.ext... as its lods, one for each threshold
+// falls back to path as lod 0 if there isn't a name_lod0.ext
+// if there aren't any lod files the lods are simplified from the model instead, one ratio per threshold e.g 0.5 = half the triangles
+// see LodMetric for what the thresholds mean, lods are picked per instance so a model only drawn once should be given a single instance
+pub fn load_lod_group(
+    path: &str,
+    metric: LodMetric,
+    thresholds: &[f32],
+    generated_lod_ratios: &[f32],
+    asset_server: &mut AssetServer,
+    material_bind_group_layout: &wgpu::BindGroupLayout,
+    wgpu_context: &WgpuContext,
+) -> Result<InstancedModel> {
+    let lod_path = |level: usize| {
+        let path = Path::new(path);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+
+        path.with_file_name(format!("{stem}_lod{level}.{extension}"))
+            .to_string_lossy()
+            .to_string()
+    };
+
+    let first_path = match lod_path(0) {
+        lod_0_path if Path::new(&lod_0_path).exists() => lod_0_path,
+        _ => path.to_string(),
+    };
+
+    let lod_paths = (1..=thresholds.len())
+        .map(lod_path)
+        .take_while(|path| Path::new(path).exists())
+        .collect::<Vec<String>>();
+
+    let model_handle =
+        asset_server.load_model(&first_path, material_bind_group_layout, wgpu_context);
+
+    let lod_handles = if lod_paths.is_empty() {
+        if generated_lod_ratios.len() != thresholds.len() {
+            return Err(eyre!(
+                "Model \"{}\" has {} LOD thresholds but {} ratios to generate LODs with",
+                path,
+                thresholds.len(),
+                generated_lod_ratios.len()
+            ));
+        }
+
+        generated_lod_ratios
+            .iter()
+            .map(|&ratio| {
+                asset_server.load_simplified_model(
+                    &first_path,
+                    ratio,
+                    material_bind_group_layout,
+                    wgpu_context,
+                )
+            })
+            .collect::<Vec<Handle<Model>>>()
+    } else {
+        if lod_paths.len() < thresholds.len() {
+            log::warn!(
+                "Model \"{}\" has {} LOD files for {} thresholds, the rest of the thresholds are unused",
+                path,
+                lod_paths.len(),
+                thresholds.len()
+            );
+        }
+
+        lod_paths
+            .iter()
+            .map(|path| asset_server.load_model(path, material_bind_group_layout, wgpu_context))
+            .collect()
+    };
+
+    let mut instanced_model = InstancedModel::from(loaded_model(asset_server, &model_handle)?);
+    instanced_model.handle = Some(model_handle);
+    instanced_model.lod_metric = metric;
+
+    for (handle, &threshold) in lod_handles.into_iter().zip(thresholds) {
+        let mut lod = Lod::new(loaded_model(asset_server, &handle)?, threshold);
+        lod.handle = Some(handle);
+        instanced_model.lods.push(lod);
+    }
+
+    log::info!(
+        "Loaded model \"{}\" with {} LODs",
+        instanced_model.model.name,
+        instanced_model.lods.len()
+    );
+
+    Ok(instanced_model)
+}
+
+// the error it failed to load with otherwise
+fn loaded_model(asset_server: &AssetServer, handle: &Handle<Model>) -> Result<Rc<Model>> {
+    asset_server
+        .get(handle)
+        .ok_or_else(|| match asset_server.load_state(handle) {
+            LoadState::Failed(error) => eyre!(error),
+            state => eyre!(
+                "Model \"{}\" isn't loaded: {:?}",
+                asset_server.path(handle),
+                state
+            ),
+        })
+}
+
+// how much detail an instance needs, bigger = more, compared against the thresholds in the same units
+fn detail(
+    metric: LodMetric,
+    camera: &Camera,
+    center: cg::Point3<f32>,
+    radius: f32,
+    bias: f32,
+) -> f32 {
+    let distance = (center - camera.position).magnitude().max(f32::EPSILON);
+
+    match metric {
+        LodMetric::ScreenSize => radius / (distance * (cg::Deg(camera.fov) / 2.0).tan()) * bias,
+        LodMetric::Distance => bias / distance,
+    }
+}
+
+fn threshold_detail(metric: LodMetric, threshold: f32) -> f32 {
+    match metric {
+        LodMetric::ScreenSize => threshold,
+        LodMetric::Distance => 1.0 / threshold.max(f32::EPSILON),
+    }
+}
+
+// sorts the visible instances of a model with lods into the lod each is drawn with
+// instances being crossfaded are put in two lods with opposite fades so together they cover every pixel once
+pub(crate) fn select_lods(
+    instanced_model: &mut InstancedModel,
+    camera: &Camera,
+    settings: &LodSettings,
+) {
+    for lod in instanced_model.lods.iter_mut() {
+        lod.visible_instances.clear();
+    }
+
+    let Some(instances) = &instanced_model.instances else {
+        return;
+    };
+
+    if instanced_model.lods.is_empty() {
+        return;
+    }
+
+    let visible_instances = instanced_model
+        .visible_instances
+        .take()
+        .unwrap_or_else(|| (0..instances.len()).map(VisibleInstance::new).collect());
+
+    let sphere = instanced_model.model.bounds.bounding_sphere();
+    let metric = instanced_model.lod_metric;
+
+    let thresholds = instanced_model
+        .lods
+        .iter()
+        .map(|lod| threshold_detail(metric, lod.threshold))
+        .collect::<Vec<_>>();
+
+    // index 0 is the model itself
+    let mut buckets = vec![vec![]; thresholds.len() + 1];
+
+    for visible_instance in visible_instances {
+        let instance = &instances[visible_instance.index];
+        let center = cg::Point3::from_vec(
+            instance.rotation.rotate_vector(sphere.center.to_vec()) + instance.position,
+        );
+        let detail = detail(metric, camera, center, sphere.radius, settings.bias);
+
+        let level = thresholds
+            .iter()
+            .take_while(|&&threshold| detail < threshold)
+            .count();
+
+        // fades from 1 at the start of the crossfade to 0 at the next threshold
+        let fade = thresholds.get(level).and_then(|&next_threshold| {
+            let start = next_threshold * (1.0 + settings.crossfade_width);
+            let fade = 1.0 - (start - detail) / (start - next_threshold);
+
+            (settings.crossfade && fade > 0.0 && fade < 1.0).then_some(fade)
+        });
+
+        match fade {
+            Some(fade) => {
+                buckets[level].push(VisibleInstance {
+                    fade,
+                    ..visible_instance
+                });
+                buckets[level + 1].push(VisibleInstance {
+                    fade: -fade,
+                    ..visible_instance
+                });
+            }
+            None => buckets[level].push(visible_instance),
+        }
+    }
+
+    let mut buckets = buckets.into_iter();
+    instanced_model.visible_instances = buckets.next();
+
+    for (lod, bucket) in instanced_model.lods.iter_mut().zip(buckets) {
+        lod.visible_instances = bucket;
+    }
+}
@@ -1,13 +1,25 @@
 pub mod application;
 pub mod application_handler;
+pub mod asset;
 pub mod camera;
 pub mod context;
+pub mod culling;
+pub mod debug_view;
 pub mod gui;
+pub mod hot_reload;
+pub mod import;
 pub mod input;
 pub mod instance;
+pub mod light;
+pub mod lod;
 pub mod model;
+pub mod optimize;
 pub mod pipeline;
+pub mod post_process;
 pub mod render;
 pub mod resources;
+pub mod shadow;
+pub mod simplify;
 pub mod texture;
 pub mod time;
+pub mod tone_mapping;
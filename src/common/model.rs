@@ -1,13 +1,23 @@
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use wgpu::util::DeviceExt;
 
+use crate::common::asset::{AssetServer, Handle};
 use crate::common::context::WgpuContext;
+use crate::common::culling::{BoundingBox, IndirectDraws};
+use crate::common::debug_view::DebugViews;
+use crate::common::import::{self, ImportSettings, ImportWarning, NormalGeneration};
 use crate::common::instance::ModelInstance;
+use crate::common::lod::{Lod, LodMetric};
+use crate::common::optimize;
 use crate::common::render::RenderData;
-use crate::common::texture::{Texture, TextureAtlas};
+use crate::common::simplify;
+use crate::common::texture::{SamplerSettings, Texture};
 
 pub trait BufferContents {
     fn buffer_layout() -> wgpu::VertexBufferLayout<'static>;
@@ -19,11 +29,86 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub texture_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
 }
 
 impl ModelVertex {
-    const MODEL_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 3] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3];
+    const MODEL_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x3, 3 => Float32x3, 4 => Float32x3];
+}
+
+// tangents and bitangents follow the u and v directions of the texture, together with the normal they move normal map samples into model space
+// see https://sotrh.github.io/learn-wgpu/intermediate/tutorial11-normals/#the-tangent-and-the-bitangent
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![cg::Vector3::<f32>::zero(); vertices.len()];
+    let mut bitangents = vec![cg::Vector3::<f32>::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ];
+
+        let position_a = cg::Vector3::from(vertices[a].position);
+        let position_b = cg::Vector3::from(vertices[b].position);
+        let position_c = cg::Vector3::from(vertices[c].position);
+
+        let uv_a = cg::Vector2::from(vertices[a].texture_coords);
+        let uv_b = cg::Vector2::from(vertices[b].texture_coords);
+        let uv_c = cg::Vector2::from(vertices[c].texture_coords);
+
+        let delta_position_1 = position_b - position_a;
+        let delta_position_2 = position_c - position_a;
+        let delta_uv_1 = uv_b - uv_a;
+        let delta_uv_2 = uv_c - uv_a;
+
+        let determinant = delta_uv_1.x * delta_uv_2.y - delta_uv_1.y * delta_uv_2.x;
+
+        // uvs are degenerate, the triangle has no texture direction to follow
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+
+        let r = 1.0 / determinant;
+        let tangent = (delta_position_1 * delta_uv_2.y - delta_position_2 * delta_uv_1.y) * r;
+        let bitangent = (delta_position_2 * delta_uv_1.x - delta_position_1 * delta_uv_2.x) * r;
+
+        // not normalized so bigger triangles have more say in the average
+        for index in [a, b, c] {
+            tangents[index] += tangent;
+            bitangents[index] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = cg::Vector3::from(vertex.normal);
+
+        // gram-schmidt, tangent has to be perpendicular to the normal after averaging
+        let mut tangent = tangent - normal * normal.dot(tangent);
+
+        if tangent.magnitude2() < f32::EPSILON {
+            // no uv direction at this vertex, any tangent perpendicular to the normal will do
+            let axis = if normal.x.abs() < 0.9 {
+                cg::Vector3::unit_x()
+            } else {
+                cg::Vector3::unit_y()
+            };
+            tangent = axis - normal * normal.dot(axis);
+        }
+
+        let tangent = tangent.normalize();
+
+        // mirrored uvs flip the bitangent
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        vertex.tangent = tangent.into();
+        vertex.bitangent = (normal.cross(tangent) * handedness).into();
+    }
 }
 
 impl BufferContents for ModelVertex {
@@ -36,19 +121,93 @@ impl BufferContents for ModelVertex {
     }
 }
 
-// models are only going to be loaded once ever anyway so no Rc for model
+// a corner of a triangle that doesn't share vertices with any other, see WireframeBuffers
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WireframeVertex {
+    pub position: [f32; 3],
+    // 1 for this corner and 0 for the others, interpolated it's how far a pixel is from each edge
+    pub barycentric: [f32; 3],
+}
+
+impl WireframeVertex {
+    const WIREFRAME_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+}
+
+impl BufferContents for WireframeVertex {
+    fn buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::WIREFRAME_VERTEX_ATTRIBUTES,
+        }
+    }
+}
+
+// an instance that passed culling
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VisibleInstance {
+    // into InstancedModel::instances
+    pub index: usize,
+    // 1 unless it is crossfading between lods, see lod.rs
+    pub fade: f32,
+}
+
+impl VisibleInstance {
+    pub fn new(index: usize) -> Self {
+        Self { index, fade: 1.0 }
+    }
+}
+
+// a model and every instance it is drawn at, the model itself can be shared with the AssetServer and other InstancedModels
 pub struct InstancedModel {
-    pub model: Model,
+    // lod 0 if there are lods, shared with the AssetServer if it was loaded through one
+    pub model: Rc<Model>,
+    // set when model came from an AssetServer so RenderData::reload_models can swap in the reloaded one
+    pub handle: Option<Handle<Model>>,
     pub instances: Option<Vec<ModelInstance>>,
     pub instance_buffer: Option<wgpu::Buffer>,
+    // instances that are in view, set by RenderData::cull and narrowed down by RenderData::select_lods, None = draw every instance
+    pub visible_instances: Option<Vec<VisibleInstance>>,
+    // for models without instances, set by RenderData::cull
+    pub visible: bool,
+    // set by GpuCulling::update, drawn with draw_indexed_indirect instead of from instance_buffer
+    pub indirect_draws: Option<IndirectDraws>,
+    // lower detail versions of model, only used for models with instances
+    pub lods: Vec<Lod>,
+    pub lod_metric: LodMetric,
 }
 
 impl From<Model> for InstancedModel {
     fn from(model: Model) -> Self {
+        Rc::new(model).into()
+    }
+}
+
+impl From<Rc<Model>> for InstancedModel {
+    fn from(model: Rc<Model>) -> Self {
         Self {
             model,
+            handle: None,
             instances: None,
             instance_buffer: None,
+            visible_instances: None,
+            visible: true,
+            indirect_draws: None,
+            lods: vec![],
+            lod_metric: LodMetric::ScreenSize,
+        }
+    }
+}
+
+impl InstancedModel {
+    // how many instances are in the instance buffer, not counting the ones drawn with lods
+    pub fn drawn_instance_count(&self) -> u32 {
+        match (&self.instances, &self.visible_instances) {
+            (Some(_), Some(visible_instances)) => visible_instances.len() as u32,
+            (Some(instances), None) => instances.len() as u32,
+            (None, _) => self.visible as u32,
         }
     }
 }
@@ -57,178 +216,970 @@ pub struct Model {
     pub name: Rc<String>,
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    // around every mesh
+    pub bounds: BoundingBox,
 }
 
 pub struct Material {
     pub name: Rc<String>,
     // the same texture can be used in multiple materials to avoid loading the same image multiple times hence Rc
     pub diffuse: Rc<Texture>,
-    pub normal: Option<Rc<Texture>>,
+    // flat normal map if the material doesn't have one
+    pub normal: Rc<Texture>,
+    pub uniform: MaterialUniform,
+    pub uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
+// mtl parameters, see http://paulbourke.net/dataformats/mtl/
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    // Ka, multiplied with the scene's ambient light
+    pub ambient: [f32; 3],
+    // Ns
+    pub shininess: f32,
+    // Kd, multiplied with the diffuse texture
+    pub diffuse: [f32; 3],
+    // d, 1 = opaque, below that only a dithered share of the pixels are drawn
+    pub dissolve: f32,
+    // Ks
+    pub specular: [f32; 3],
+    // Pm, from the pbr extension to mtl or glTF
+    pub metallic: f32,
+    // Ke, added on top of the lighting
+    pub emissive: [f32; 3],
+    // Pr, from the pbr extension to mtl or glTF
+    pub roughness: f32,
+}
+
+impl MaterialUniform {
+    // glTF materials are metallic-roughness, the blinn-phong terms are approximated from them
+    pub fn from_metallic_roughness(
+        base_color: [f32; 4],
+        metallic: f32,
+        roughness: f32,
+        emissive: [f32; 3],
+    ) -> Self {
+        let base_color_rgb = cg::Vector3::new(base_color[0], base_color[1], base_color[2]);
+
+        // non metals reflect about 4% of light, metals reflect their base color
+        let specular =
+            cg::Vector3::new(0.04, 0.04, 0.04) * (1.0 - metallic) + base_color_rgb * metallic;
+
+        // https://simonstechblog.blogspot.com/2011/12/microfacet-brdf.html
+        let shininess = (2.0 / roughness.max(0.01).powi(4) - 2.0).max(1.0);
+
+        Self {
+            ambient: [1.0; 3],
+            shininess,
+            diffuse: (base_color_rgb * (1.0 - metallic)).into(),
+            dissolve: base_color[3],
+            specular: specular.into(),
+            metallic,
+            emissive,
+            roughness,
+        }
+    }
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            ambient: [1.0; 3],
+            shininess: 32.0,
+            diffuse: [1.0; 3],
+            dissolve: 1.0,
+            specular: [0.5; 3],
+            metallic: 0.0,
+            emissive: [0.0; 3],
+            roughness: 1.0,
+        }
+    }
+}
+
+impl From<&tobj::Material> for MaterialUniform {
+    fn from(material: &tobj::Material) -> Self {
+        let default = Self::default();
+
+        // tobj doesn't know the pbr extension so those end up as unknown parameters
+        let unknown_float = |key: &str| {
+            material
+                .unknown_param
+                .get(key)
+                .and_then(|value| value.trim().parse::<f32>().ok())
+        };
+
+        Self {
+            ambient: material.ambient.unwrap_or(default.ambient),
+            shininess: material.shininess.unwrap_or(default.shininess),
+            diffuse: material.diffuse.unwrap_or(default.diffuse),
+            dissolve: material.dissolve.unwrap_or(default.dissolve),
+            specular: material.specular.unwrap_or(default.specular),
+            metallic: unknown_float("Pm").unwrap_or(default.metallic),
+            emissive: material.emissive.unwrap_or(default.emissive),
+            roughness: unknown_float("Pr").unwrap_or(default.roughness),
+        }
+    }
+}
+
+impl Material {
+    pub fn new(
+        name: Rc<String>,
+        diffuse: Rc<Texture>,
+        normal: Rc<Texture>,
+        uniform: MaterialUniform,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        device: &wgpu::Device,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?}-material-buffer", name)),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: material_bind_group_layout,
+            label: Some(&format!("{:?}-material-bind-group", name)),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&normal.sampler),
+                },
+            ],
+        });
+
+        Self {
+            name,
+            diffuse,
+            normal,
+            uniform,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+}
+
 pub struct Mesh {
     pub name: Rc<String>,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub indices_count: u32,
     pub material_index: usize,
+    // in model space
+    pub bounds: BoundingBox,
+    // only made if the device can't draw lines, see DebugViews
+    pub wireframe: Option<WireframeBuffers>,
+}
+
+// every triangle with its own vertices so they can have barycentric coordinates, drawn in the same order as the mesh
+// the indices just count up, they're there so the mesh's indirect draw arguments can draw these too
+pub struct WireframeBuffers {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+}
+
+impl WireframeBuffers {
+    pub fn new(
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        device: &wgpu::Device,
+    ) -> Self {
+        const CORNERS: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+        let wireframe_vertices = indices
+            .iter()
+            .zip(CORNERS.iter().cycle())
+            .map(|(&index, &barycentric)| WireframeVertex {
+                position: vertices[index as usize].position,
+                barycentric,
+            })
+            .collect::<Vec<WireframeVertex>>();
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?}-wireframe-vertex-buffer", name)),
+            contents: bytemuck::cast_slice(&wireframe_vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?}-wireframe-index-buffer", name)),
+            contents: bytemuck::cast_slice(&(0..indices.len() as u32).collect::<Vec<u32>>()),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+        }
+    }
+}
+
+impl Mesh {
+    pub fn new(
+        name: Rc<String>,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material_index: usize,
+        device: &wgpu::Device,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?}-vertex-buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?}-index-buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let bounds = BoundingBox::from_points(
+            vertices
+                .iter()
+                .map(|vertex| cg::Point3::from(vertex.position)),
+        );
+
+        let wireframe = (!device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE))
+        .then(|| WireframeBuffers::new(&name, vertices, indices, device));
+
+        Self {
+            name,
+            vertex_buffer,
+            index_buffer,
+            indices_count: indices.len() as u32,
+            material_index,
+            bounds,
+            wireframe,
+        }
+    }
+}
+
+// a mesh before it is uploaded, loaders make these first so the vertices can be processed e.g simplified
+#[derive(Clone, Debug)]
+pub struct MeshData {
+    pub name: Rc<String>,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub material_index: usize,
+}
+
+impl MeshData {
+    // tobj has to be loaded with single_index and triangulate, see import::load_obj
+    // missing normals and texture coordinates are generated and reported instead of dropping the mesh
+    pub fn from_obj(mesh: tobj::Model, settings: &ImportSettings) -> (Self, Vec<ImportWarning>) {
+        let vertex_count = mesh.mesh.positions.len() / 3;
+        // tobj leaves these empty if any face in the mesh is missing them
+        let has_normals = mesh.mesh.normals.len() == vertex_count * 3;
+        let has_texture_coords = mesh.mesh.texcoords.len() == vertex_count * 2;
+
+        let mut vertices = (0..vertex_count)
+            .map(|i| ModelVertex {
+                position: [
+                    mesh.mesh.positions[i * 3],
+                    mesh.mesh.positions[i * 3 + 1],
+                    mesh.mesh.positions[i * 3 + 2],
+                ],
+                texture_coords: if has_texture_coords {
+                    [mesh.mesh.texcoords[i * 2], mesh.mesh.texcoords[i * 2 + 1]]
+                } else {
+                    [0.0; 2]
+                },
+                normal: if has_normals {
+                    [
+                        mesh.mesh.normals[i * 3],
+                        mesh.mesh.normals[i * 3 + 1],
+                        mesh.mesh.normals[i * 3 + 2],
+                    ]
+                } else {
+                    [0.0; 3]
+                },
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+            })
+            .collect::<Vec<ModelVertex>>();
+        let mut indices = mesh.mesh.indices;
+        let mut warnings = vec![];
+
+        if !has_normals {
+            warnings.push(ImportWarning::MissingNormals {
+                mesh: mesh.name.clone(),
+                generated: settings.normals,
+            });
+            (vertices, indices) = import::generate_normals(&vertices, &indices, settings.normals);
+        }
+
+        if !has_texture_coords {
+            warnings.push(ImportWarning::MissingTextureCoords {
+                mesh: mesh.name.clone(),
+                generated: settings.texture_coords,
+            });
+            (vertices, indices) =
+                import::generate_texture_coords(&vertices, &indices, settings.texture_coords);
+        }
+
+        // obj files can repeat the same vertex on different lines, simplifying would see a crack between the copies
+        // done here so the offline simplify tool welds them too, before tangents so they're accumulated over the welded triangles
+        (vertices, indices) = optimize::weld(&vertices, &indices);
+
+        compute_tangents(&mut vertices, &indices);
+
+        let mesh_data = Self {
+            name: Rc::new(mesh.name),
+            vertices,
+            indices,
+            material_index: mesh.mesh.material_id.unwrap_or(0),
+        };
+
+        (mesh_data, warnings)
+    }
+
+    // about target_ratio of the triangles, see simplify::simplify
+    pub fn simplified(&self, target_ratio: f32) -> Self {
+        let (vertices, indices) = simplify::simplify(&self.vertices, &self.indices, target_ratio);
+
+        Self {
+            name: self.name.clone(),
+            vertices,
+            indices,
+            material_index: self.material_index,
+        }
+    }
+
+    // see optimize::optimize
+    pub fn optimize(&mut self) -> optimize::OptimizationStats {
+        let (vertices, indices, stats) = optimize::optimize(&self.vertices, &self.indices);
+        self.vertices = vertices;
+        self.indices = indices;
+
+        stats
+    }
+
+    pub fn upload(&self, device: &wgpu::Device) -> Mesh {
+        Mesh::new(
+            self.name.clone(),
+            &self.vertices,
+            &self.indices,
+            self.material_index,
+            device,
+        )
+    }
 }
 
 impl Model {
+    pub fn new(
+        name: Rc<String>,
+        materials: Vec<Material>,
+        meshes: &[MeshData],
+        device: &wgpu::Device,
+    ) -> Self {
+        let meshes = meshes
+            .iter()
+            .map(|mesh| mesh.upload(device))
+            .collect::<Vec<Mesh>>();
+
+        let bounds = meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(BoundingBox::from_points([]));
+
+        Self {
+            name,
+            materials,
+            meshes,
+            bounds,
+        }
+    }
+
     pub fn load(
         path: String,
-        texture_atlas: &mut TextureAtlas,
-        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        asset_server: &mut AssetServer,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
         wgpu_context: &WgpuContext,
     ) -> Result<Self> {
-        let (meshes, model_materials) = tobj::load_obj(
-            path.clone(),
-            &tobj::LoadOptions {
-                single_index: true,
-                triangulate: true,
-                ..Default::default()
-            },
+        let (materials, meshes) = Self::load_data(
+            &path,
+            asset_server,
+            material_bind_group_layout,
+            wgpu_context,
+        )?;
+
+        Ok(Self::new(
+            Rc::new(path),
+            materials,
+            &meshes,
+            &wgpu_context.device,
+        ))
+    }
+
+    // the model simplified to ratio of its triangles, e.g 0.5 = half the triangles
+    pub fn load_simplified(
+        path: String,
+        ratio: f32,
+        asset_server: &mut AssetServer,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Result<Self> {
+        let (materials, meshes) = Self::load_data(
+            &path,
+            asset_server,
+            material_bind_group_layout,
+            wgpu_context,
         )?;
 
+        let triangle_count = |meshes: &[MeshData]| {
+            meshes
+                .iter()
+                .map(|mesh| mesh.indices.len() / 3)
+                .sum::<usize>()
+        };
+
+        let mut simplified_meshes = meshes
+            .iter()
+            .map(|mesh| mesh.simplified(ratio))
+            .collect::<Vec<MeshData>>();
+        let name = format!("{path}#simplified-{ratio}");
+        Self::optimize_meshes(&name, &mut simplified_meshes);
+
+        log::info!(
+            "Simplified model \"{}\" to {} triangles from {}",
+            path,
+            triangle_count(&simplified_meshes),
+            triangle_count(&meshes)
+        );
+
+        Ok(Self::new(
+            Rc::new(name),
+            materials,
+            &simplified_meshes,
+            &wgpu_context.device,
+        ))
+    }
+
+    // picks the loader from the file extension
+    fn load_data(
+        path: &str,
+        asset_server: &mut AssetServer,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Result<(Vec<Material>, Vec<MeshData>)> {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        let (materials, mut meshes) = match extension.as_deref() {
+            Some("obj") => {
+                Self::load_obj(path, asset_server, material_bind_group_layout, wgpu_context)
+            }
+            Some("gltf") | Some("glb") => {
+                Self::load_gltf(path, asset_server, material_bind_group_layout, wgpu_context)
+            }
+            _ => Err(eyre!("Unsupported model format \"{}\"", path)),
+        }?;
+
+        Self::optimize_meshes(path, &mut meshes);
+
+        Ok((materials, meshes))
+    }
+
+    fn optimize_meshes(model_name: &str, meshes: &mut [MeshData]) {
+        for mesh in meshes.iter_mut() {
+            let stats = mesh.optimize();
+
+            log::info!(
+                "Optimized mesh \"{}\" of model \"{}\", {} -> {} vertices, ACMR {:.3} -> {:.3}",
+                mesh.name,
+                model_name,
+                stats.vertices_before,
+                stats.vertices_after,
+                stats.acmr_before,
+                stats.acmr_after
+            );
+        }
+    }
+
+    fn default_material(
+        asset_server: &mut AssetServer,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Result<Material> {
+        let default_texture = asset_server.texture(
+            "default.png",
+            false,
+            &wgpu_context.device,
+            &wgpu_context.queue,
+        )?;
+        let flat_normal_map =
+            asset_server.flat_normal_map(&wgpu_context.device, &wgpu_context.queue)?;
+
+        Ok(Material::new(
+            Rc::new("default-material".to_string()),
+            default_texture,
+            flat_normal_map,
+            MaterialUniform::default(),
+            material_bind_group_layout,
+            &wgpu_context.device,
+        ))
+    }
+
+    fn load_obj(
+        path: &str,
+        asset_server: &mut AssetServer,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Result<(Vec<Material>, Vec<MeshData>)> {
+        let obj_file = import::load_obj(path, &ImportSettings::default())?;
+
         log::info!("Loaded model \"{}\"", path);
 
-        let model_materials = model_materials?;
+        for warning in obj_file.warnings.iter() {
+            log::warn!("{} in model \"{}\"", warning, path);
+        }
 
-        let materials = if model_materials.is_empty() {
-            let default_texture_path: Rc<String> = Rc::new("default.png".to_string());
-            let default_texture = texture_atlas.get(
-                default_texture_path,
-                &wgpu_context.device,
-                &wgpu_context.queue,
-            )?;
-
-            let bind_group = wgpu_context
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &texture_bind_group_layout,
-                    label: Some("default-texture-bind-group"),
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&default_texture.view),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&default_texture.sampler),
-                        },
-                    ],
-                });
+        let model_materials = obj_file.materials?;
 
-            vec![Material {
-                diffuse: default_texture,
-                normal: None,
-                bind_group,
-                name: Rc::new("default-material".to_string()),
-            }]
+        let materials = if model_materials.is_empty() {
+            vec![Self::default_material(
+                asset_server,
+                material_bind_group_layout,
+                wgpu_context,
+            )?]
         } else {
             model_materials
                 .into_iter()
                 .map(|material| {
-                    let diffuse_texture_path = match material.diffuse_texture {
-                        Some(texture_path) => texture_path,
+                    let uniform = MaterialUniform::from(&material);
+
+                    let diffuse_texture = match material.diffuse_texture {
+                        Some(texture_path) => asset_server.texture(
+                            &texture_path,
+                            false,
+                            &wgpu_context.device,
+                            &wgpu_context.queue,
+                        )?,
                         None => {
-                            log::warn!(
-                                "No diffuse texture found for model \"{}\", using default texture.",
+                            log::info!(
+                                "No diffuse texture found for material \"{}\" in model \"{}\", using diffuse color.",
+                                material.name,
                                 path
                             );
-                            "default.png".to_string()
+                            asset_server.white_texture(&wgpu_context.device, &wgpu_context.queue)?
                         }
                     };
 
-                    let diffuse_texture = texture_atlas.get(
-                        Rc::new(diffuse_texture_path),
+                    // map_Bump
+                    let normal_texture = match material.normal_texture {
+                        Some(texture_path) => asset_server.texture(
+                            &texture_path,
+                            true,
+                            &wgpu_context.device,
+                            &wgpu_context.queue,
+                        )?,
+                        None => asset_server
+                            .flat_normal_map(&wgpu_context.device, &wgpu_context.queue)?,
+                    };
+
+                    Ok(Material::new(
+                        Rc::new(material.name),
+                        diffuse_texture,
+                        normal_texture,
+                        uniform,
+                        material_bind_group_layout,
                         &wgpu_context.device,
-                        &wgpu_context.queue,
-                    )?;
-
-                    let bind_group =
-                        wgpu_context
-                            .device
-                            .create_bind_group(&wgpu::BindGroupDescriptor {
-                                layout: &texture_bind_group_layout,
-                                label: Some("default-texture-bind-group"),
-                                entries: &[
-                                    wgpu::BindGroupEntry {
-                                        binding: 0,
-                                        resource: wgpu::BindingResource::TextureView(
-                                            &diffuse_texture.view,
-                                        ),
-                                    },
-                                    wgpu::BindGroupEntry {
-                                        binding: 1,
-                                        resource: wgpu::BindingResource::Sampler(
-                                            &diffuse_texture.sampler,
-                                        ),
-                                    },
-                                ],
-                            });
-
-                    Ok(Material {
-                        name: Rc::new(material.name),
-                        diffuse: diffuse_texture,
-                        normal: None,
-                        bind_group,
-                    })
+                    ))
                 })
                 .collect::<Result<Vec<Material>>>()?
         };
 
-        let meshes = meshes
+        Ok((materials, obj_file.meshes))
+    }
+
+    fn load_gltf(
+        path: &str,
+        asset_server: &mut AssetServer,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        wgpu_context: &WgpuContext,
+    ) -> Result<(Vec<Material>, Vec<MeshData>)> {
+        // decodes all buffers and images, embedded or external
+        let (document, buffers, images) = gltf::import(path)?;
+
+        log::info!("Loaded model \"{}\"", path);
+
+        let (mut materials, texture_coord_sets): (Vec<Material>, Vec<u32>) = document
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                let base_color_texture = pbr.base_color_texture();
+                let normal_texture = material.normal_texture();
+
+                // vertices only have one set of texture coordinates, the base color texture's wins
+                let texture_coord_set = base_color_texture
+                    .as_ref()
+                    .map(|info| info.tex_coord())
+                    .or_else(|| normal_texture.as_ref().map(|info| info.tex_coord()))
+                    .unwrap_or(0);
+
+                let diffuse_texture = match base_color_texture {
+                    Some(info) => gltf_texture(
+                        path,
+                        &info.texture(),
+                        &images,
+                        false,
+                        asset_server,
+                        wgpu_context,
+                    )?,
+                    None => {
+                        asset_server.white_texture(&wgpu_context.device, &wgpu_context.queue)?
+                    }
+                };
+
+                let normal_texture = match normal_texture {
+                    Some(normal_texture) => {
+                        if normal_texture.tex_coord() != texture_coord_set {
+                            log::warn!(
+                                "Normal map of material {:?} in model \"{}\" uses texture coordinate set {} but the base color uses {}, using {} for both",
+                                material.name(),
+                                path,
+                                normal_texture.tex_coord(),
+                                texture_coord_set,
+                                texture_coord_set
+                            );
+                        }
+
+                        gltf_texture(
+                            path,
+                            &normal_texture.texture(),
+                            &images,
+                            true,
+                            asset_server,
+                            wgpu_context,
+                        )?
+                    }
+                    None => {
+                        asset_server.flat_normal_map(&wgpu_context.device, &wgpu_context.queue)?
+                    }
+                };
+
+                let name = material
+                    .name()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("material-{}", material.index().unwrap_or(0)));
+
+                let material = Material::new(
+                    Rc::new(name),
+                    diffuse_texture,
+                    normal_texture,
+                    MaterialUniform::from_metallic_roughness(
+                        pbr.base_color_factor(),
+                        pbr.metallic_factor(),
+                        pbr.roughness_factor(),
+                        material.emissive_factor(),
+                    ),
+                    material_bind_group_layout,
+                    &wgpu_context.device,
+                );
+
+                Ok((material, texture_coord_set))
+            })
+            .collect::<Result<Vec<(Material, u32)>>>()?
             .into_iter()
-            .map(|mesh| {
-                let positions_chunks = mesh.mesh.positions.chunks(3);
-                let texcoords_chunks = mesh.mesh.texcoords.chunks(2);
-                let normals_chunks = mesh.mesh.normals.chunks(3);
-
-                let vertices = positions_chunks
-                    .zip(texcoords_chunks)
-                    .zip(normals_chunks)
-                    .map(|((pos, tex), norm)| ModelVertex {
-                        position: [pos[0], pos[1], pos[2]],
-                        texture_coords: [tex[0], tex[1]],
-                        normal: [norm[0], norm[1], norm[2]],
-                    })
-                    .collect::<Vec<ModelVertex>>();
-
-                let vertex_buffer =
-                    wgpu_context
-                        .device
-                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some(&format!("{:?}-vertex-buffer", mesh.name)),
-                            contents: bytemuck::cast_slice(&vertices),
-                            usage: wgpu::BufferUsages::VERTEX,
-                        });
-                let index_buffer =
-                    wgpu_context
-                        .device
-                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some(&format!("{:?}-index-buffer", mesh.name)),
-                            contents: bytemuck::cast_slice(&mesh.mesh.indices),
-                            usage: wgpu::BufferUsages::INDEX,
-                        });
-
-                Mesh {
-                    name: Rc::new(mesh.name),
-                    vertex_buffer,
-                    index_buffer,
-                    indices_count: mesh.mesh.indices.len() as u32,
-                    material_index: mesh.mesh.material_id.unwrap_or(0),
+            .unzip();
+
+        // primitives without a material use the default one at the end
+        let default_material_index = materials.len();
+        materials.push(Self::default_material(
+            asset_server,
+            material_bind_group_layout,
+            wgpu_context,
+        )?);
+
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| eyre!("No scene found in \"{}\"", path))?;
+
+        // meshes have no transform of their own so node transforms are baked into the vertices
+        let mut nodes = scene
+            .nodes()
+            .map(|node| (node, cg::Matrix4::<f32>::identity()))
+            .collect::<Vec<_>>();
+        let mut meshes = vec![];
+
+        while let Some((node, parent_transform)) = nodes.pop() {
+            let transform = parent_transform * cg::Matrix4::from(node.transform().matrix());
+
+            nodes.extend(node.children().map(|child| (child, transform)));
+
+            let Some(mesh) = node.mesh() else {
+                continue;
+            };
+
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!(
+                        "Skipping {:?} primitive in model \"{}\", only triangles are supported",
+                        primitive.mode(),
+                        path
+                    );
+                    continue;
                 }
-            })
-            .collect::<Vec<Mesh>>();
 
-        Ok(Self {
-            name: Rc::new(path.to_string()),
-            materials,
-            meshes,
+                let name = format!(
+                    "{}-{}",
+                    mesh.name()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| mesh.index().to_string()),
+                    primitive.index()
+                );
+
+                let material_index = primitive
+                    .material()
+                    .index()
+                    .unwrap_or(default_material_index);
+                let texture_coord_set =
+                    texture_coord_sets.get(material_index).copied().unwrap_or(0);
+
+                let (vertices, indices) = gltf_primitive_vertices(
+                    &primitive,
+                    &buffers,
+                    transform,
+                    texture_coord_set,
+                    &name,
+                )?;
+
+                meshes.push(MeshData {
+                    name: Rc::new(name),
+                    vertices,
+                    indices,
+                    material_index,
+                });
+            }
+        }
+
+        Ok((materials, meshes))
+    }
+}
+
+// embedded images are keyed by their index in the file, external images by their path so they are shared with other models
+// the same image can be used with different samplers so the sampler is part of the key too
+fn gltf_texture(
+    path: &str,
+    texture: &gltf::Texture,
+    images: &[gltf::image::Data],
+    is_normal_map: bool,
+    asset_server: &mut AssetServer,
+    wgpu_context: &WgpuContext,
+) -> Result<Rc<Texture>> {
+    let image = texture.source();
+    let image_key = match image.source() {
+        gltf::image::Source::Uri { uri, .. } => std::path::Path::new(path)
+            .with_file_name(uri)
+            .to_string_lossy()
+            .into_owned(),
+        gltf::image::Source::View { .. } => format!("{}#image{}", path, image.index()),
+    };
+
+    let sampler = texture.sampler();
+    let key = match sampler.index() {
+        Some(index) => format!("{image_key}#sampler{index}"),
+        None => format!("{image_key}#default-sampler"),
+    };
+
+    asset_server.texture_from_image(
+        &key,
+        &gltf_image_to_dynamic_image(&images[image.index()])?,
+        is_normal_map,
+        &gltf_sampler_settings(&sampler),
+        &wgpu_context.device,
+        &wgpu_context.queue,
+    )
+}
+
+fn gltf_sampler_settings(sampler: &gltf::texture::Sampler) -> SamplerSettings {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    use wgpu::FilterMode::{Linear, Nearest};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+
+    // filters left out are up to the renderer, textures always have mipmaps so the ones without still get them
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest) => (Nearest, Nearest),
+        Some(MinFilter::Linear | MinFilter::LinearMipmapNearest) => (Linear, Nearest),
+        Some(MinFilter::NearestMipmapLinear) => (Nearest, Linear),
+        Some(MinFilter::LinearMipmapLinear) | None => (Linear, Linear),
+    };
+
+    SamplerSettings {
+        // repeats unless the sampler says otherwise, unlike obj textures
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        mag_filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => Nearest,
+            Some(MagFilter::Linear) | None => Linear,
+        },
+        min_filter,
+        mipmap_filter,
+    }
+}
+
+fn gltf_primitive_vertices(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    transform: cg::Matrix4<f32>,
+    texture_coord_set: u32,
+    name: &str,
+) -> Result<(Vec<ModelVertex>, Vec<u32>)> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let positions = reader
+        .read_positions()
+        .ok_or_else(|| eyre!("Primitive \"{}\" has no positions", name))?
+        .collect::<Vec<[f32; 3]>>();
+
+    let mut indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<u32>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    let normals = match reader.read_normals() {
+        Some(normals) => normals.collect::<Vec<[f32; 3]>>(),
+        None => {
+            log::warn!(
+                "{}",
+                ImportWarning::MissingNormals {
+                    mesh: name.to_string(),
+                    generated: NormalGeneration::Smooth,
+                }
+            );
+            import::smooth_normals(&positions, &indices)
+        }
+    };
+
+    let texture_coords = match reader.read_tex_coords(texture_coord_set) {
+        Some(texture_coords) => texture_coords.into_f32().collect::<Vec<[f32; 2]>>(),
+        None => vec![[0.0; 2]; positions.len()],
+    };
+
+    // normals and tangents only rotate, inverse transpose undoes any non uniform scale
+    let linear = cg::Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+    let normal_matrix = linear.invert().unwrap_or(linear).transpose();
+
+    // a mirroring transform turns the triangles inside out, swapping two corners turns them back
+    let mirrored = linear.determinant() < 0.0;
+    if mirrored {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    let mut vertices = positions
+        .iter()
+        .zip(normals.iter())
+        .zip(texture_coords.iter())
+        .map(|((position, normal), texture_coords)| ModelVertex {
+            position: (transform * cg::Vector3::from(*position).extend(1.0))
+                .truncate()
+                .into(),
+            texture_coords: *texture_coords,
+            normal: (normal_matrix * cg::Vector3::from(*normal))
+                .normalize()
+                .into(),
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
         })
+        .collect::<Vec<ModelVertex>>();
+
+    match reader.read_tangents() {
+        Some(tangents) => {
+            for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+                let normal = cg::Vector3::from(vertex.normal);
+                let tangent_direction =
+                    (linear * cg::Vector3::new(tangent[0], tangent[1], tangent[2])).normalize();
+
+                // w is the handedness of the bitangent, mirroring flips it
+                let handedness = if mirrored { -tangent[3] } else { tangent[3] };
+                vertex.tangent = tangent_direction.into();
+                vertex.bitangent = (normal.cross(tangent_direction) * handedness).into();
+            }
+        }
+        None => compute_tangents(&mut vertices, &indices),
     }
+
+    Ok((vertices, indices))
+}
+
+fn gltf_image_to_dynamic_image(data: &gltf::image::Data) -> Result<image::DynamicImage> {
+    use gltf::image::Format;
+
+    let (width, height) = (data.width, data.height);
+    let invalid = || eyre!("Image data does not match its {:?} format", data.format);
+
+    // image owns its buffer so 8 bit channels are copied as they are
+    let pixels = || data.pixels.clone();
+
+    // 16 bit channels are stored as native endian bytes
+    let pixels_16 = || -> Vec<u16> {
+        data.pixels
+            .chunks_exact(2)
+            .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+            .collect()
+    };
+
+    Ok(match data.format {
+        Format::R8 => image::DynamicImage::ImageLuma8(
+            image::GrayImage::from_raw(width, height, pixels()).ok_or_else(invalid)?,
+        ),
+        Format::R8G8 => image::DynamicImage::ImageLumaA8(
+            image::GrayAlphaImage::from_raw(width, height, pixels()).ok_or_else(invalid)?,
+        ),
+        Format::R8G8B8 => image::DynamicImage::ImageRgb8(
+            image::RgbImage::from_raw(width, height, pixels()).ok_or_else(invalid)?,
+        ),
+        Format::R8G8B8A8 => image::DynamicImage::ImageRgba8(
+            image::RgbaImage::from_raw(width, height, pixels()).ok_or_else(invalid)?,
+        ),
+        Format::R16 => image::DynamicImage::ImageLuma16(
+            image::ImageBuffer::from_raw(width, height, pixels_16()).ok_or_else(invalid)?,
+        ),
+        Format::R16G16 => image::DynamicImage::ImageLumaA16(
+            image::ImageBuffer::from_raw(width, height, pixels_16()).ok_or_else(invalid)?,
+        ),
+        Format::R16G16B16 => image::DynamicImage::ImageRgb16(
+            image::ImageBuffer::from_raw(width, height, pixels_16()).ok_or_else(invalid)?,
+        ),
+        Format::R16G16B16A16 => image::DynamicImage::ImageRgba16(
+            image::ImageBuffer::from_raw(width, height, pixels_16()).ok_or_else(invalid)?,
+        ),
+        format => return Err(eyre!("Unsupported texture format {:?}", format)),
+    })
 }
 
 impl Hash for Model {
@@ -247,6 +1198,16 @@ impl Eq for Model {}
 
 pub trait DrawModels<'a> {
     fn draw_models(&mut self, render_data: &'a RenderData);
+    // no materials or lights bound, for depth only passes e.g shadows
+    fn draw_models_geometry(&mut self, render_data: &'a RenderData);
+    // each mesh gets its own slot of mesh_bind_group instead of its material, see DebugViews
+    // wireframe draws the meshes' WireframeBuffers instead, the ones without them are drawn as they are
+    fn draw_models_debug(
+        &mut self,
+        render_data: &'a RenderData,
+        mesh_bind_group: &'a wgpu::BindGroup,
+        wireframe: bool,
+    );
 }
 
 impl<'a, 'b> DrawModels<'b> for wgpu::RenderPass<'a>
@@ -255,29 +1216,162 @@ where
 {
     // compile all stuff and draw
     fn draw_models(&mut self, render_data: &'b RenderData) {
-        for instanced_model in render_data.models.iter() {
+        self.set_bind_group(2, render_data.lights_bind_group(), &[]);
+
+        draw_instanced_models(self, render_data, MeshBinding::Material);
+    }
+
+    fn draw_models_geometry(&mut self, render_data: &'b RenderData) {
+        draw_instanced_models(self, render_data, MeshBinding::None);
+    }
+
+    fn draw_models_debug(
+        &mut self,
+        render_data: &'b RenderData,
+        mesh_bind_group: &'b wgpu::BindGroup,
+        wireframe: bool,
+    ) {
+        draw_instanced_models(
+            self,
+            render_data,
+            MeshBinding::Debug {
+                mesh_bind_group,
+                wireframe,
+            },
+        );
+    }
+}
+
+// what is bound for each mesh besides its vertex and index buffers
+#[derive(Clone, Copy)]
+enum MeshBinding<'b> {
+    Material,
+    None,
+    Debug {
+        mesh_bind_group: &'b wgpu::BindGroup,
+        wireframe: bool,
+    },
+}
+
+fn draw_instanced_models<'a, 'b>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    render_data: &'b RenderData,
+    binding: MeshBinding<'b>,
+) where
+    'b: 'a,
+{
+    // counts every mesh whether it's drawn or not so a mesh keeps its index as things are culled
+    let mut first_mesh_index = 0;
+
+    for instanced_model in render_data.models.iter() {
+        let instance_count = instanced_model.drawn_instance_count();
+        let model_first_mesh_index = first_mesh_index;
+        first_mesh_index += instanced_model.model.meshes.len() as u32;
+
+        // culled, or every instance is drawn with a lod
+        if instance_count > 0 {
             // todo, if running low on gpu resources i could make it so that the instanced models are sorted by Option<Instance> so that the single instance buffer is only bound once for all of the single render models. for now this is good
-            match &instanced_model.instance_buffer {
-                Some(instance_buffer) => self.set_vertex_buffer(1, instance_buffer.slice(..)),
-                None => self.set_vertex_buffer(1, render_data.single_instance_slice()),
+            match (
+                &instanced_model.indirect_draws,
+                &instanced_model.instance_buffer,
+            ) {
+                (Some(indirect_draws), _) => render_pass
+                    .set_vertex_buffer(1, indirect_draws.visible_instance_buffer.slice(..)),
+                (None, Some(instance_buffer)) => {
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..))
+                }
+                (None, None) => {
+                    render_pass.set_vertex_buffer(1, render_data.single_instance_slice())
+                }
             }
 
-            for mesh in instanced_model.model.meshes.iter() {
-                self.set_bind_group(
+            draw_meshes(
+                render_pass,
+                &instanced_model.model,
+                instanced_model.indirect_draws.as_ref(),
+                instance_count,
+                binding,
+                model_first_mesh_index,
+            );
+        }
+
+        // a lod can have a different number of meshes than lod 0, so each one gets its own indices after the model's
+        for lod in instanced_model.lods.iter() {
+            let lod_first_mesh_index = first_mesh_index;
+            first_mesh_index += lod.model.meshes.len() as u32;
+
+            let Some(instance_buffer) = &lod.instance_buffer else {
+                continue;
+            };
+
+            if lod.visible_instances.is_empty() {
+                continue;
+            }
+
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            draw_meshes(
+                render_pass,
+                &lod.model,
+                None,
+                lod.visible_instances.len() as u32,
+                binding,
+                lod_first_mesh_index,
+            );
+        }
+    }
+}
+
+// the instance buffer has to be bound already
+fn draw_meshes<'a, 'b>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    model: &'b Model,
+    indirect_draws: Option<&'b IndirectDraws>,
+    instance_count: u32,
+    binding: MeshBinding<'b>,
+    first_mesh_index: u32,
+) where
+    'b: 'a,
+{
+    for (mesh_index, mesh) in model.meshes.iter().enumerate() {
+        let (vertex_buffer, index_buffer) = match binding {
+            MeshBinding::Material => {
+                render_pass.set_bind_group(
                     0,
-                    &instanced_model.model.materials[mesh.material_index].bind_group,
+                    &model.materials[mesh.material_index].bind_group,
                     &[],
                 );
-                self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-                self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                (&mesh.vertex_buffer, &mesh.index_buffer)
+            }
+            MeshBinding::None => (&mesh.vertex_buffer, &mesh.index_buffer),
+            MeshBinding::Debug {
+                mesh_bind_group,
+                wireframe,
+            } => {
+                render_pass.set_bind_group(
+                    0,
+                    mesh_bind_group,
+                    &[DebugViews::mesh_offset(
+                        first_mesh_index + mesh_index as u32,
+                    )],
+                );
 
-                match &instanced_model.instances {
-                    Some(instances) => {
-                        self.draw_indexed(0..mesh.indices_count, 0, 0..instances.len() as u32)
-                    }
-                    None => self.draw_indexed(0..mesh.indices_count, 0, 0..1),
+                match (wireframe, &mesh.wireframe) {
+                    (true, Some(wireframe)) => (&wireframe.vertex_buffer, &wireframe.index_buffer),
+                    _ => (&mesh.vertex_buffer, &mesh.index_buffer),
                 }
             }
+        };
+
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+        match indirect_draws {
+            // instance count was written by the culling compute shader
+            Some(indirect_draws) => render_pass.draw_indexed_indirect(
+                &indirect_draws.draw_buffer,
+                IndirectDraws::draw_offset(mesh_index),
+            ),
+            None => render_pass.draw_indexed(0..mesh.indices_count, 0, 0..instance_count),
         }
     }
 }
@@ -0,0 +1,320 @@
+use cg::prelude::*;
+use cgmath as cg;
+use rustc_hash::FxHashMap;
+
+use crate::common::model::ModelVertex;
+
+// fifo cache acmr is measured with, around what desktop gpus have
+pub const ACMR_CACHE_SIZE: usize = 16;
+
+// lru cache the triangle order is optimized for, see https://tomforsyth1000.github.io/papers/fast_vert_cache_opt.html
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+pub struct OptimizationStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    // average cache miss ratio, vertices shaded per triangle, 0.5 is about the best a big mesh can get and 3 the worst
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+// welds duplicate vertices, then reorders the triangles for the vertex cache and overdraw and the vertices for fetching
+// draws exactly the same triangles, only the order changes
+pub fn optimize(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+) -> (Vec<ModelVertex>, Vec<u32>, OptimizationStats) {
+    let acmr_before = acmr(indices, vertices.len());
+
+    let (welded_vertices, indices) = weld(vertices, indices);
+    let indices = optimize_vertex_cache(&indices, welded_vertices.len());
+    let indices = optimize_overdraw(&welded_vertices, &indices);
+    let (optimized_vertices, indices) = optimize_vertex_fetch(&welded_vertices, &indices);
+
+    let stats = OptimizationStats {
+        vertices_before: vertices.len(),
+        vertices_after: optimized_vertices.len(),
+        acmr_before,
+        acmr_after: acmr(&indices, optimized_vertices.len()),
+    };
+
+    (optimized_vertices, indices, stats)
+}
+
+// vertices transformed per triangle with a fifo cache of ACMR_CACHE_SIZE
+pub fn acmr(indices: &[u32], vertex_count: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+
+    let mut misses = 0;
+    // a vertex is still cached if fewer than ACMR_CACHE_SIZE misses have happened since it was loaded
+    let mut loaded_at = vec![0; vertex_count];
+    let mut timestamp = ACMR_CACHE_SIZE + 1;
+
+    for &index in indices {
+        let loaded_at = &mut loaded_at[index as usize];
+
+        if timestamp - *loaded_at > ACMR_CACHE_SIZE {
+            *loaded_at = timestamp;
+            timestamp += 1;
+            misses += 1;
+        }
+    }
+
+    misses as f32 / triangle_count as f32
+}
+
+// merges vertices that are exactly the same, loaders can split them e.g obj files with repeated positions
+pub fn weld(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut welded_ids = FxHashMap::<[u32; 14], u32>::default();
+    let mut welded_vertices = vec![];
+
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            *welded_ids
+                .entry(bytemuck::cast(*vertex))
+                .or_insert_with(|| {
+                    welded_vertices.push(*vertex);
+                    welded_vertices.len() as u32 - 1
+                })
+        })
+        .collect::<Vec<u32>>();
+
+    let indices = indices.iter().map(|&index| remap[index as usize]).collect();
+
+    (welded_vertices, indices)
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: u32) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        // used by the triangle just drawn, scored lower so the next triangle isn't always a neighbour of it
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+
+    // vertices with only a few triangles left are finished off so they don't have to be loaded again later
+    let valence_boost =
+        VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER);
+
+    cache_score + valence_boost
+}
+
+// greedily draws whichever triangle has the most of its vertices cached next
+fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    // the triangles using each vertex, the first remaining_triangles of them haven't been drawn yet
+    let mut offsets = vec![0; vertex_count + 1];
+    for &index in indices {
+        offsets[index as usize + 1] += 1;
+    }
+    for vertex in 0..vertex_count {
+        offsets[vertex + 1] += offsets[vertex];
+    }
+
+    let mut remaining_triangles = (0..vertex_count)
+        .map(|vertex| (offsets[vertex + 1] - offsets[vertex]) as u32)
+        .collect::<Vec<u32>>();
+
+    let mut vertex_triangles = vec![0; indices.len()];
+    let mut next_slot = offsets.clone();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &vertex in corners {
+            vertex_triangles[next_slot[vertex as usize]] = triangle;
+            next_slot[vertex as usize] += 1;
+        }
+    }
+
+    let mut cache_positions = vec![None; vertex_count];
+    let mut vertex_scores = remaining_triangles
+        .iter()
+        .map(|&remaining| vertex_score(None, remaining))
+        .collect::<Vec<f32>>();
+
+    let mut drawn = vec![false; triangle_count];
+    let mut cache: Vec<u32> = vec![];
+    let mut best_triangle = None;
+    // finding the best triangle in the whole mesh is slow, the next one not drawn is picked instead when nothing cached is left
+    let mut next_undrawn = 0;
+
+    let mut optimized_indices = Vec::with_capacity(indices.len());
+
+    for _ in 0..triangle_count {
+        let triangle = best_triangle.unwrap_or_else(|| {
+            while drawn[next_undrawn] {
+                next_undrawn += 1;
+            }
+            next_undrawn
+        });
+
+        drawn[triangle] = true;
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        optimized_indices.extend_from_slice(corners);
+
+        for &vertex in corners {
+            let vertex = vertex as usize;
+            let remaining = remaining_triangles[vertex] as usize;
+            let triangles = &mut vertex_triangles[offsets[vertex]..offsets[vertex] + remaining];
+
+            // swapped past the end of the ones left
+            if let Some(slot) = triangles.iter().position(|&other| other == triangle) {
+                triangles.swap(slot, remaining - 1);
+            }
+            remaining_triangles[vertex] -= 1;
+        }
+
+        // the triangle's vertices move to the front and the rest shift back, the ones pushed off the end are uncached
+        let mut new_cache = Vec::with_capacity(cache.len() + 3);
+        for &vertex in corners.iter().chain(cache.iter()) {
+            if !new_cache.contains(&vertex) {
+                new_cache.push(vertex);
+            }
+        }
+
+        for (position, &vertex) in new_cache.iter().enumerate() {
+            let vertex = vertex as usize;
+            cache_positions[vertex] = (position < CACHE_SIZE).then_some(position);
+            vertex_scores[vertex] =
+                vertex_score(cache_positions[vertex], remaining_triangles[vertex]);
+        }
+
+        // only triangles touching the cache have changed score
+        best_triangle = None;
+        let mut best_score = f32::MIN;
+
+        for &vertex in new_cache.iter() {
+            let vertex = vertex as usize;
+            let start = offsets[vertex];
+
+            for &other in &vertex_triangles[start..start + remaining_triangles[vertex] as usize] {
+                let score = indices[other * 3..other * 3 + 3]
+                    .iter()
+                    .map(|&corner| vertex_scores[corner as usize])
+                    .sum::<f32>();
+
+                if score > best_score {
+                    best_score = score;
+                    best_triangle = Some(other);
+                }
+            }
+        }
+
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+    }
+
+    optimized_indices
+}
+
+// splits the triangles into clusters and draws the ones facing out from the middle of the mesh first
+// they are more likely to be in front, so more of what comes after fails the depth test before shading
+// same idea as Sander et al. "Fast Triangle Reordering for Vertex Locality and Reduced Overdraw"
+fn optimize_overdraw(vertices: &[ModelVertex], indices: &[u32]) -> Vec<u32> {
+    // starts a new cluster wherever a triangle misses the cache on all three vertices
+    // the cache is cold there anyway so moving the clusters around barely changes the acmr
+    let mut cluster_starts = vec![];
+    let mut loaded_at = vec![0; vertices.len()];
+    let mut timestamp = ACMR_CACHE_SIZE + 1;
+
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        let mut misses = 0;
+
+        for &vertex in corners {
+            let loaded_at = &mut loaded_at[vertex as usize];
+
+            if timestamp - *loaded_at > ACMR_CACHE_SIZE {
+                *loaded_at = timestamp;
+                timestamp += 1;
+                misses += 1;
+            }
+        }
+
+        if triangle == 0 || misses == 3 {
+            cluster_starts.push(triangle * 3);
+        }
+    }
+
+    let position = |index: u32| cg::Vector3::from(vertices[index as usize].position);
+
+    // area weighted so a few small triangles don't drag the middle around
+    let centroid_and_normal = |corners: &[u32]| {
+        corners.chunks_exact(3).fold(
+            (cg::Vector3::zero(), cg::Vector3::zero(), 0.0),
+            |(centroid, normal, area), triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| position(triangle[i]));
+                let triangle_normal = (b - a).cross(c - a);
+                let triangle_area = triangle_normal.magnitude();
+
+                (
+                    centroid + (a + b + c) / 3.0 * triangle_area,
+                    normal + triangle_normal,
+                    area + triangle_area,
+                )
+            },
+        )
+    };
+
+    let (mesh_centroid, _, mesh_area) = centroid_and_normal(indices);
+    let mesh_centroid = mesh_centroid / mesh_area.max(f32::EPSILON);
+
+    let mut clusters = cluster_starts
+        .iter()
+        .zip(cluster_starts.iter().skip(1).chain([&indices.len()]))
+        .map(|(&start, &end)| {
+            let corners = &indices[start..end];
+            let (centroid, normal, area) = centroid_and_normal(corners);
+            let centroid = centroid / area.max(f32::EPSILON);
+
+            let facing_out = if normal.magnitude2() > 0.0 {
+                (centroid - mesh_centroid).dot(normal.normalize())
+            } else {
+                0.0
+            };
+
+            (facing_out, corners)
+        })
+        .collect::<Vec<_>>();
+
+    clusters.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    clusters
+        .into_iter()
+        .flat_map(|(_, corners)| corners.iter().copied())
+        .collect()
+}
+
+// vertices in the order they are first used so fetching them goes through memory in order, unused ones are dropped
+fn optimize_vertex_fetch(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut remap = vec![None; vertices.len()];
+    let mut fetched_vertices = Vec::with_capacity(vertices.len());
+
+    let indices = indices
+        .iter()
+        .map(|&index| {
+            *remap[index as usize].get_or_insert_with(|| {
+                fetched_vertices.push(vertices[index as usize]);
+                fetched_vertices.len() as u32 - 1
+            })
+        })
+        .collect();
+
+    (fetched_vertices, indices)
+}
@@ -1,57 +1,1022 @@
-pub fn create_pipeline(
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::rc::Rc;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use rustc_hash::FxHashMap;
+
+use crate::common::instance::RawInstance;
+use crate::common::model::{BufferContents, ModelVertex};
+use crate::common::texture::Texture;
+
+// a wgsl file loaded at runtime instead of included in the binary, see AssetServer::load_shader
+pub struct Shader {
+    pub path: Rc<String>,
+    pub defines: ShaderDefines,
+    // canonical paths of every file it was preprocessed from, editing any of them reloads it
+    pub files: Vec<Rc<String>>,
+    pub module: wgpu::ShaderModule,
+}
+
+impl Shader {
+    pub fn from_path(path: &str, defines: &ShaderDefines, device: &wgpu::Device) -> Result<Self> {
+        let shader = preprocess(path, defines)?;
+        shader.validate()?;
+
+        let label = defines.permutation_name(path);
+
+        // naga passing doesn't mean the device can run it e.g a feature it doesn't have
+        let module = validated(device, || {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&label),
+                source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+            })
+        })
+        .map_err(|error| ShaderError {
+            path: label.clone(),
+            message: error.to_string(),
+            location: None,
+            files: ShaderError::file_list(&shader.files),
+        })?;
+
+        Ok(Self {
+            path: Rc::new(path.to_string()),
+            defines: defines.clone(),
+            files: shader.files,
+            module,
+        })
+    }
+
+    // checks the wgsl the same way wgpu does before it's given to the device, but keeps where the mistake is
+    pub fn validate(path: &str, source: &str) -> std::result::Result<naga::Module, ShaderError> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|error| {
+            ShaderError::new(path, source, error.message(), error.location(source))
+        })?;
+
+        // every capability is allowed, the ones the device doesn't have are caught when the module is created
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|error| {
+            // the outer errors only say which function or expression, the innermost one says what's wrong with it
+            let mut message = error.to_string();
+            let mut cause = std::error::Error::source(&error);
+            while let Some(inner) = cause {
+                message = format!("{message}: {inner}");
+                cause = inner.source();
+            }
+
+            ShaderError::new(path, source, &message, error.location(source))
+        })?;
+
+        Ok(module)
+    }
+}
+
+// the #defines a shader is preprocessed with, sorted so the same set is always the same permutation
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderDefines(BTreeSet<String>);
+
+impl ShaderDefines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, name: impl Into<String>) -> Self {
+        self.insert(name);
+        self
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>) {
+        self.0.insert(name.into());
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // tells permutations of the same file apart e.g shader.wgsl#NORMAL_MAP,SHADOWS
+    pub fn permutation_name(&self, path: &str) -> String {
+        if self.is_empty() {
+            return path.to_string();
+        }
+
+        let names = self.0.iter().map(String::as_str).collect::<Vec<&str>>();
+        format!("{}#{}", path, names.join(","))
+    }
+}
+
+impl<S: Into<String>> FromIterator<S> for ShaderDefines {
+    fn from_iter<I: IntoIterator<Item = S>>(names: I) -> Self {
+        Self(names.into_iter().map(Into::into).collect())
+    }
+}
+
+// wgsl with the preprocessor directives applied, see preprocess
+#[derive(Debug)]
+pub struct PreprocessedShader {
+    pub source: String,
+    // canonical paths of every file that was included, starting with the one given to preprocess
+    pub files: Vec<Rc<String>>,
+    // the same files as they were named by whatever included them, for error messages
+    paths: Vec<String>,
+    // which file each line of source came from and its line number there
+    line_origins: Vec<(usize, u32)>,
+}
+
+impl PreprocessedShader {
+    // the file and line starting at 1 that a line of source came from
+    pub fn origin(&self, line: u32) -> Option<(&str, u32)> {
+        let &(file, line) = self.line_origins.get(line.checked_sub(1)? as usize)?;
+        Some((&self.paths[file], line))
+    }
+
+    // errors point at the file the mistake is in rather than the line in source
+    pub fn validate(&self) -> std::result::Result<naga::Module, ShaderError> {
+        Shader::validate(&self.paths[0], &self.source).map_err(|mut error| {
+            if let Some(location) = error.location.as_mut() {
+                if let Some((path, line)) = self.origin(location.line) {
+                    error.path = path.to_string();
+                    location.line = line;
+                }
+            }
+            error.files = ShaderError::file_list(&self.files);
+
+            error
+        })
+    }
+}
+
+// applies #include "file.wgsl", #define NAME, #ifdef NAME, #ifndef NAME, #else and #endif to a wgsl file
+// includes are relative to the file they're in and each file is only included once, so shared structs can be included from anywhere
+// a #define applies to the rest of the shader, including files included after it
+pub fn preprocess(
+    path: &str,
+    defines: &ShaderDefines,
+) -> std::result::Result<PreprocessedShader, ShaderError> {
+    let mut preprocessor = Preprocessor {
+        defines: defines.clone(),
+        shader: PreprocessedShader {
+            source: String::new(),
+            files: vec![],
+            paths: vec![],
+            line_origins: vec![],
+        },
+    };
+
+    let included = std::fs::read_to_string(path)
+        .map_err(|error| ShaderError::unreadable(path, &error))
+        .and_then(|source| preprocessor.include(Path::new(path), &source));
+
+    // the files it got as far as, so fixing whichever one is wrong can retry it
+    included.map_err(|mut error| {
+        error.files = ShaderError::file_list(&preprocessor.shader.files);
+        error
+    })?;
+
+    Ok(preprocessor.shader)
+}
+
+struct Preprocessor {
+    defines: ShaderDefines,
+    shader: PreprocessedShader,
+}
+
+impl Preprocessor {
+    fn include(&mut self, path: &Path, source: &str) -> std::result::Result<(), ShaderError> {
+        let display_path = path.to_string_lossy().into_owned();
+        let file = Rc::new(Self::canonical_path(path));
+        if self.shader.files.contains(&file) {
+            return Ok(());
+        }
+
+        let file_index = self.shader.files.len();
+        self.shader.files.push(file);
+        self.shader.paths.push(display_path.clone());
+
+        // whether the lines in each #ifdef the current line is inside are kept, and whether it's had an #else yet
+        let mut conditions: Vec<(bool, bool)> = vec![];
+
+        for (line_number, line) in (1..).zip(source.lines()) {
+            let error = |message: &str| {
+                ShaderError::in_directive(&display_path, line_number, line, message)
+            };
+            let active = conditions.iter().all(|&(kept, _)| kept);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.shader.source.push_str(line);
+                    self.shader.source.push('\n');
+                    self.shader.line_origins.push((file_index, line_number));
+                }
+
+                continue;
+            };
+
+            let (name, argument) = match directive.trim().split_once(char::is_whitespace) {
+                Some((name, argument)) => (name, Some(argument.trim())),
+                None => (directive.trim(), None),
+            };
+
+            match (name, argument) {
+                ("ifdef", Some(define)) => conditions.push((self.defines.contains(define), false)),
+                ("ifndef", Some(define)) => {
+                    conditions.push((!self.defines.contains(define), false))
+                }
+                ("else", _) => match conditions.last_mut() {
+                    Some((kept, has_else @ false)) => {
+                        *kept = !*kept;
+                        *has_else = true;
+                    }
+                    Some(_) => return Err(error("#else after #else")),
+                    None => return Err(error("#else without #ifdef")),
+                },
+                ("endif", _) => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef"))?;
+                }
+                // only the conditions are followed in lines that aren't kept
+                _ if !active => {}
+                ("define", Some(define)) => self.defines.insert(define),
+                ("include", Some(included)) => {
+                    let included = included
+                        .strip_prefix('"')
+                        .and_then(|included| included.strip_suffix('"'))
+                        .ok_or_else(|| error("#include path has to be in quotes"))?;
+
+                    let included_path = path.parent().unwrap_or(Path::new("")).join(included);
+                    let included_source =
+                        std::fs::read_to_string(&included_path).map_err(|read_error| {
+                            // creating the file is how this gets fixed
+                            let missing_file = Rc::new(Self::canonical_path(&included_path));
+                            self.shader.files.push(missing_file);
+                            error(&format!("couldn't read \"{}\": {}", included, read_error))
+                        })?;
+                    self.include(&included_path, &included_source)?;
+                }
+                _ => {
+                    return Err(error(&format!(
+                        "unknown directive \"#{}\"",
+                        directive.trim()
+                    )))
+                }
+            }
+        }
+
+        if !conditions.is_empty() {
+            return Err(ShaderError {
+                path: display_path,
+                message: "#ifdef without #endif".to_string(),
+                location: None,
+                files: vec![],
+            });
+        }
+
+        Ok(())
+    }
+
+    // a file that doesn't exist yet keeps its path, only the directory it would be in is resolved
+    fn canonical_path(path: &Path) -> String {
+        path.canonicalize()
+            .or_else(|error| {
+                let directory = path.parent().ok_or(error)?.canonicalize()?;
+                Ok::<_, std::io::Error>(directory.join(path.file_name().unwrap_or_default()))
+            })
+            .unwrap_or_else(|_| path.to_path_buf())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+// a mistake in a wgsl file and where it is, formatted like a compiler error for the log and the gui
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderError {
+    pub path: String,
+    pub message: String,
+    // None if naga couldn't point at the code responsible
+    pub location: Option<ShaderErrorLocation>,
+    // canonical paths of the files the shader was made from, as far as the preprocessor got
+    // not Rc like Shader::files so the error can be sent between threads as an eyre::Report
+    pub files: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderErrorLocation {
+    // both start at 1
+    pub line: u32,
+    pub column: u32,
+    // in bytes, only the part on the first line is underlined
+    pub length: u32,
+    pub source_line: String,
+}
+
+impl ShaderError {
+    fn new(
+        path: &str,
+        source: &str,
+        message: &str,
+        location: Option<naga::SourceLocation>,
+    ) -> Self {
+        let location = location.map(|location| ShaderErrorLocation {
+            line: location.line_number,
+            column: location.line_position,
+            length: location.length,
+            source_line: source
+                .lines()
+                .nth(location.line_number as usize - 1)
+                .unwrap_or_default()
+                .to_string(),
+        });
+
+        Self {
+            path: path.to_string(),
+            message: message.to_string(),
+            location,
+            files: vec![],
+        }
+    }
+
+    // the preprocessor points at the whole line with the directive
+    fn in_directive(path: &str, line_number: u32, line: &str, message: &str) -> Self {
+        let indent = line.len() - line.trim_start().len();
+
+        Self {
+            path: path.to_string(),
+            message: message.to_string(),
+            location: Some(ShaderErrorLocation {
+                line: line_number,
+                column: indent as u32 + 1,
+                length: line.trim().len() as u32,
+                source_line: line.to_string(),
+            }),
+            files: vec![],
+        }
+    }
+
+    fn file_list(files: &[Rc<String>]) -> Vec<String> {
+        files.iter().map(|file| file.to_string()).collect()
+    }
+
+    fn unreadable(path: &str, error: &std::io::Error) -> Self {
+        Self {
+            path: path.to_string(),
+            message: format!("couldn't read shader: {error}"),
+            location: None,
+            files: vec![],
+        }
+    }
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(location) = &self.location else {
+            return write!(f, "{}: {}", self.path, self.message);
+        };
+
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.path, location.line, location.column, self.message
+        )?;
+        writeln!(f, "{}", location.source_line)?;
+
+        // tabs are kept so the underline lines up however wide they're shown
+        let start = (location.column as usize - 1).min(location.source_line.len());
+        let indent = location.source_line[..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let length =
+            (location.length as usize).clamp(1, (location.source_line.len() - start).max(1));
+
+        write!(f, "{}{}", indent, "^".repeat(length))
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+// returns the validation error from create instead of letting the device's error handler panic on it
+// for anything made from files that can be edited while running e.g shaders and the pipelines using them
+pub fn validated<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(eyre!("{}", error)),
+        None => Ok(created),
+    }
+}
+
+// the fixed function state of a render pipeline, pipelines built from equal builders are shared by a PipelineCache
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineBuilder {
+    vertex_entry_point: String,
+    // None for depth only pipelines e.g shadow maps
+    fragment_entry_point: Option<String>,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    color_targets: Vec<Option<wgpu::ColorTargetState>>,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipelineBuilder {
+    // vs_main and fs_main drawing back face culled, filled triangles with no color targets or depth
+    pub fn new() -> Self {
+        Self {
+            vertex_entry_point: "vs_main".to_string(),
+            fragment_entry_point: Some("fs_main".to_string()),
+            vertex_buffers: vec![],
+            color_targets: vec![],
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                // triangles are considered forward facing if their vertices are in a counter clockwise order
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        }
+    }
+
+    pub fn entry_points(mut self, vertex: &str, fragment: Option<&str>) -> Self {
+        self.vertex_entry_point = vertex.to_string();
+        self.fragment_entry_point = fragment.map(str::to_string);
+        self
+    }
+
+    // type of vertices to pass to the vertex shader
+    pub fn vertex_buffers(mut self, buffers: &[wgpu::VertexBufferLayout<'static>]) -> Self {
+        self.vertex_buffers = buffers.to_vec();
+        self
+    }
+
+    // added in the order of the fragment shader's outputs, blend None replaces what was there
+    pub fn color_target(
+        mut self,
+        format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> Self {
+        self.color_targets.push(Some(wgpu::ColorTargetState {
+            format,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        }));
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.primitive.front_face = front_face;
+        self
+    }
+
+    // Line = wireframe and needs Features::POLYGON_MODE_LINE, Point needs Features::POLYGON_MODE_POINT
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.primitive.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth(
+        mut self,
+        format: wgpu::TextureFormat,
+        write: bool,
+        compare: wgpu::CompareFunction,
+    ) -> Self {
+        self.depth_stencil = Some(wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: write,
+            depth_compare: compare,
+            stencil: wgpu::StencilState::default(),
+            bias: self
+                .depth_stencil
+                .map(|depth_stencil| depth_stencil.bias)
+                .unwrap_or_default(),
+        });
+        self
+    }
+
+    // only does anything once depth has been set
+    pub fn depth_bias(mut self, bias: wgpu::DepthBiasState) -> Self {
+        if let Some(depth_stencil) = self.depth_stencil.as_mut() {
+            depth_stencil.bias = bias;
+        }
+        self
+    }
+
+    // has to match the color and depth attachments, see WgpuContext::sample_count
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.multisample.count = sample_count;
+        self
+    }
+
+    pub fn build(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        label: Option<&str>,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label,
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: &self.vertex_entry_point,
+                buffers: &self.vertex_buffers,
+            },
+            fragment: self
+                .fragment_entry_point
+                .as_ref()
+                .map(|entry_point| wgpu::FragmentState {
+                    module: shader,
+                    entry_point,
+                    targets: &self.color_targets,
+                }),
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil.clone(),
+            multisample: self.multisample,
+            multiview: None,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct PipelineKey {
+    builder: PipelineBuilder,
+    layout: wgpu::Id<wgpu::PipelineLayout>,
+    shader: wgpu::Id<wgpu::ShaderModule>,
+}
+
+// render pipelines shared between everything asking for the same state, layout and shader
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: FxHashMap<PipelineKey, Rc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // fails instead of panicking if the shader doesn't fit the state e.g a reloaded one with different bindings
+    // the label is only used if the pipeline has to be created
+    pub fn get(
+        &mut self,
+        builder: &PipelineBuilder,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        label: &str,
+    ) -> Result<Rc<wgpu::RenderPipeline>> {
+        let key = PipelineKey {
+            builder: builder.clone(),
+            layout: layout.global_id(),
+            shader: shader.global_id(),
+        };
+
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return Ok(pipeline.clone());
+        }
+
+        let pipeline = Rc::new(validated(device, || {
+            builder.build(device, layout, shader, Some(label))
+        })?);
+        log::info!("Created render pipeline \"{label}\"");
+
+        self.pipelines.insert(key, pipeline.clone());
+
+        Ok(pipeline)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    // frees the pipelines nothing else holds onto e.g ones made before a shader was reloaded, returns how many were freed
+    pub fn remove_unused(&mut self) -> usize {
+        let count = self.pipelines.len();
+        self.pipelines
+            .retain(|_, pipeline| Rc::strong_count(pipeline) > 1);
+
+        count - self.pipelines.len()
+    }
+}
+
+// instanced models lit into the hdr texture, see shader.wgsl
+pub fn scene_pipeline_builder(sample_count: u32) -> PipelineBuilder {
+    PipelineBuilder::new()
+        .vertex_buffers(&[ModelVertex::buffer_layout(), RawInstance::buffer_layout()])
+        .color_target(Texture::HDR_FORMAT, None)
+        .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+        .sample_count(sample_count)
+}
+
+// depth only pipeline for rendering shadow maps, bias is applied by the rasterizer to avoid shadow acne
+pub fn create_shadow_pipeline(
     device: &wgpu::Device,
-    surface_config: &wgpu::SurfaceConfiguration,
     layout: &wgpu::PipelineLayout,
-    shader_description: wgpu::ShaderModuleDescriptor,
-    buffers: &[wgpu::VertexBufferLayout],
+    shader: &wgpu::ShaderModule,
+    buffers: &[wgpu::VertexBufferLayout<'static>],
+    bias: wgpu::DepthBiasState,
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader_description);
+    // geometry behind the light's near plane still casts shadows with unclipped depth, but that needs Features::DEPTH_CLIP_CONTROL so it isn't used
+    PipelineBuilder::new()
+        .entry_points("vs_main", None)
+        .vertex_buffers(buffers)
+        .depth(
+            Texture::DEPTH_FORMAT,
+            true,
+            wgpu::CompareFunction::LessEqual,
+        )
+        .depth_bias(bias)
+        .build(device, layout, shader, Some("shadow_pipeline"))
+}
+
+// draws a single triangle covering the screen, the shader generates the vertices from the vertex index so no buffers are needed
+pub fn create_fullscreen_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    target_format: wgpu::TextureFormat,
+    label: Option<&str>,
+) -> wgpu::RenderPipeline {
+    PipelineBuilder::new()
+        .cull_mode(None)
+        .color_target(target_format, None)
+        .build(device, layout, shader, label)
+}
 
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("instanced_render_pipeline"),
+pub fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    label: Option<&str>,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label,
         layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            // type of vertices to pass to the vertex shader
-            buffers,
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            // same color output state as the surface's
-            targets: &[Some(wgpu::ColorTargetState {
-                format: surface_config.format,
-                // replace old data in texture with new data e.g new frame
-                blend: Some(wgpu::BlendState::REPLACE),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            // triangles are considered forward facing if their vertices are in a counter clockwise order
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-            // wgpu::PolygonMode::Line = wireframe
-            polygon_mode: wgpu::PolygonMode::Fill,
-            unclipped_depth: false,
-            conservative: false,
-        },
-        depth_stencil: Some(wgpu::DepthStencilState {
-            format: crate::common::texture::Texture::DEPTH_FORMAT,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            // antialiasing stuff
-            alpha_to_coverage_enabled: false,
+        module: shader,
+        entry_point,
+    })
+}
+
+// bind group describes a set of resources and how they can be accessed by the shaders
+pub fn create_material_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    // IDK
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                // WHAT?
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            // colors and shininess from the mtl file
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("material_bind_group_layout"),
+    })
+}
+
+pub fn create_camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("camera_bind_group_layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            // camera position is needed in the fragment shader for specular highlights
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+// one slot per mesh picked with a dynamic offset, see DebugViews
+pub fn create_debug_mesh_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("debug_mesh_bind_group_layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+pub fn create_lights_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("lights_bind_group_layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+// used by the shadow pass to transform vertices into light space
+pub fn create_shadow_pass_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("shadow_pass_bind_group_layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+// used by the main pass to sample the shadow cascades
+pub fn create_shadow_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("shadow_bind_group_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    // one layer per cascade
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+// hdr scene texture and the exposure to apply to it, read by the tone mapping pass
+pub fn create_tone_mapping_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("tone_mapping_bind_group_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // exposure calculated by the luminance histogram passes
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+// builds a histogram of the hdr scene's luminance and averages it into an exposure
+pub fn create_luminance_histogram_bind_group_layout(
+    device: &wgpu::Device,
+) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("luminance_histogram_bind_group_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // histogram bins
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // exposure, carried over between frames so it can adapt gradually
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+// input texture, its sampler, the effect's parameters and an extra texture e.g a lut, shared by every post processing effect
+pub fn create_post_process_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("post_process_bind_group_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+// a model's culling parameters, all of its instances, the instances that passed and its indirect draw arguments
+pub fn create_culling_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
         },
-        multiview: None,
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("culling_bind_group_layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            storage(1, true),
+            storage(2, false),
+            storage(3, false),
+        ],
     })
 }
@@ -0,0 +1,487 @@
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+use crate::common::context::WgpuContext;
+use crate::common::pipeline;
+use crate::common::render;
+use crate::common::texture;
+
+// must match the parameters array in post_process.wgsl
+pub const MAX_PARAMETERS: usize = 8;
+// slices of the neutral color grading lut, each slice is LUT_SIZE x LUT_SIZE
+const LUT_SIZE: u32 = 16;
+
+// vertex shader, bindings and helpers, every effect's shader is appended to this
+const PRELUDE: &str = include_str!("../../assets/post_process.wgsl");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PostProcessParameter {
+    pub name: String,
+    pub value: f32,
+    // what the gui lets it be set to
+    pub range: RangeInclusive<f32>,
+}
+
+impl PostProcessParameter {
+    pub fn new(name: &str, value: f32, range: RangeInclusive<f32>) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+            range,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PostProcessEffectSettings {
+    // unique, used to find the effect again after the gui has reordered them
+    pub name: String,
+    pub enabled: bool,
+    // read in the shader with parameter(0u), parameter(1u)... in this order
+    pub parameters: Vec<PostProcessParameter>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PostProcessSettings {
+    // in the order they are applied
+    pub effects: Vec<PostProcessEffectSettings>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessUniform {
+    parameters: [f32; MAX_PARAMETERS],
+    texel_size: [f32; 2],
+    output_is_srgb: u32,
+    _padding: u32,
+}
+
+struct PostProcessEffect {
+    name: String,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    texture: Rc<texture::Texture>,
+    // one for reading from each of the ping pong textures
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+// screen space effects run one after the other on the tone mapped image, each reads the texture the previous one wrote to
+pub struct PostProcessStack {
+    settings: PostProcessSettings,
+    effects: Vec<PostProcessEffect>,
+    // same format as the output so the last effect can write straight into it
+    output_format: wgpu::TextureFormat,
+    // transient textures owned by the context
+    ping_pong_textures: [render::TextureId; 2],
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    // bound for effects that don't have a texture of their own
+    white_texture: Rc<texture::Texture>,
+}
+
+impl PostProcessStack {
+    // empty, see add_default_effects and add_effect
+    pub fn new(wgpu: &mut WgpuContext) -> Result<Self> {
+        let device = &wgpu.device;
+
+        let bind_group_layout = pipeline::create_post_process_bind_group_layout(device);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post_process_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // effects sample around each pixel, clamped so the edges don't wrap around
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post_process_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let white_texture = Rc::new(texture::Texture::from_color(
+            [255, 255, 255, 255],
+            false,
+            device,
+            &wgpu.queue,
+            Some("post_process_white_texture"),
+        )?);
+
+        let ping_pong_textures =
+            ["post_process_ping_texture", "post_process_pong_texture"].map(|label| {
+                wgpu.create_transient_texture(render::TransientTextureDescriptor {
+                    label,
+                    format: wgpu.config.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    size: render::TextureSize::Surface,
+                    sample_count: 1,
+                })
+            });
+
+        Ok(Self {
+            settings: PostProcessSettings::default(),
+            effects: Vec::new(),
+            output_format: wgpu.config.format,
+            ping_pong_textures,
+            sampler,
+            bind_group_layout,
+            pipeline_layout,
+            white_texture,
+        })
+    }
+
+    // bloom, chromatic aberration, color grading, vignette then fxaa last so it smooths the edges the others leave
+    pub fn add_default_effects(&mut self, wgpu: &WgpuContext) -> Result<()> {
+        self.add_effect(
+            wgpu,
+            PostProcessEffectSettings {
+                name: "Bloom".to_string(),
+                enabled: true,
+                parameters: vec![
+                    PostProcessParameter::new("Threshold", 0.8, 0.0..=1.0),
+                    PostProcessParameter::new("Intensity", 0.6, 0.0..=2.0),
+                    PostProcessParameter::new("Radius", 12.0, 1.0..=64.0),
+                ],
+            },
+            include_str!("../../assets/bloom.wgsl"),
+            None,
+        )?;
+
+        self.add_effect(
+            wgpu,
+            PostProcessEffectSettings {
+                name: "Chromatic aberration".to_string(),
+                enabled: false,
+                parameters: vec![PostProcessParameter::new("Strength", 3.0, 0.0..=16.0)],
+            },
+            include_str!("../../assets/chromatic_aberration.wgsl"),
+            None,
+        )?;
+
+        let lut = Rc::new(Self::create_neutral_lut(wgpu)?);
+
+        self.add_effect(
+            wgpu,
+            PostProcessEffectSettings {
+                name: "Color grading".to_string(),
+                enabled: false,
+                parameters: vec![
+                    PostProcessParameter::new("Intensity", 1.0, 0.0..=1.0),
+                    PostProcessParameter::new("Saturation", 1.0, 0.0..=2.0),
+                    PostProcessParameter::new("Contrast", 1.0, 0.5..=1.5),
+                ],
+            },
+            include_str!("../../assets/color_grading.wgsl"),
+            Some(lut),
+        )?;
+
+        self.add_effect(
+            wgpu,
+            PostProcessEffectSettings {
+                name: "Vignette".to_string(),
+                enabled: true,
+                parameters: vec![
+                    PostProcessParameter::new("Intensity", 0.4, 0.0..=1.0),
+                    PostProcessParameter::new("Radius", 0.9, 0.0..=1.5),
+                    PostProcessParameter::new("Smoothness", 0.6, 0.01..=1.0),
+                ],
+            },
+            include_str!("../../assets/vignette.wgsl"),
+            None,
+        )?;
+
+        self.add_effect(
+            wgpu,
+            PostProcessEffectSettings {
+                name: "FXAA".to_string(),
+                enabled: true,
+                parameters: vec![
+                    PostProcessParameter::new("Edge threshold", 0.125, 0.063..=0.333),
+                    PostProcessParameter::new("Edge threshold min", 0.0312, 0.0..=0.0833),
+                    PostProcessParameter::new("Subpixel", 0.75, 0.0..=1.0),
+                ],
+            },
+            include_str!("../../assets/fxaa.wgsl"),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    // lut that maps every color to itself, for color grading luts made by editing a screenshot with this pasted in
+    pub fn create_neutral_lut(wgpu: &WgpuContext) -> Result<texture::Texture> {
+        let scale = 255.0 / (LUT_SIZE - 1) as f32;
+
+        let image = image::RgbaImage::from_fn(LUT_SIZE * LUT_SIZE, LUT_SIZE, |x, y| {
+            let red = x % LUT_SIZE;
+            let green = y;
+            let blue = x / LUT_SIZE;
+
+            image::Rgba([
+                (red as f32 * scale).round() as u8,
+                (green as f32 * scale).round() as u8,
+                (blue as f32 * scale).round() as u8,
+                255,
+            ])
+        });
+
+        // the colors in a lut are the result of the lookup so must not be converted from srgb, same as a normal map
+        texture::Texture::from_image(
+            &image::DynamicImage::ImageRgba8(image),
+            true,
+            texture::Mipmaps::None,
+            &wgpu.device,
+            &wgpu.queue,
+            Some("neutral_lut"),
+        )
+    }
+
+    // appended to the end of the stack
+    // shader_source only needs fs_main, post_process.wgsl provides the vertex shader, bindings and parameter()
+    // texture is bound as effect_texture
+    pub fn add_effect(
+        &mut self,
+        wgpu: &WgpuContext,
+        settings: PostProcessEffectSettings,
+        shader_source: &str,
+        texture: Option<Rc<texture::Texture>>,
+    ) -> Result<()> {
+        if self.effect(&settings.name).is_some() {
+            return Err(eyre!(
+                "Post processing effect \"{}\" already exists",
+                settings.name
+            ));
+        }
+
+        if settings.parameters.len() > MAX_PARAMETERS {
+            return Err(eyre!(
+                "Post processing effect \"{}\" has {} parameters, the most it can have is {MAX_PARAMETERS}",
+                settings.name,
+                settings.parameters.len()
+            ));
+        }
+
+        let label = format!("{}_post_process", settings.name);
+
+        // fails instead of panicking so a broken effect doesn't take the rest of the renderer down with it
+        let pipeline = pipeline::validated(&wgpu.device, || {
+            let shader = wgpu
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(&label),
+                    source: wgpu::ShaderSource::Wgsl(Cow::Owned(format!(
+                        "{PRELUDE}\n{shader_source}"
+                    ))),
+                });
+
+            pipeline::create_fullscreen_pipeline(
+                &wgpu.device,
+                &self.pipeline_layout,
+                &shader,
+                self.output_format,
+                Some(&label),
+            )
+        })
+        .map_err(|error| {
+            eyre!(
+                "Post processing effect \"{}\" is invalid: {error}",
+                settings.name
+            )
+        })?;
+
+        let uniform_buffer = wgpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&label),
+            size: std::mem::size_of::<PostProcessUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let texture = texture.unwrap_or_else(|| self.white_texture.clone());
+        let bind_groups = self.create_bind_groups(wgpu, &uniform_buffer, &texture);
+
+        let effect = PostProcessEffect {
+            name: settings.name.clone(),
+            pipeline,
+            uniform_buffer,
+            texture,
+            bind_groups,
+        };
+        self.write_uniform(wgpu, &effect, &settings);
+
+        self.effects.push(effect);
+        self.settings.effects.push(settings);
+
+        Ok(())
+    }
+
+    fn create_bind_groups(
+        &self,
+        wgpu: &WgpuContext,
+        uniform_buffer: &wgpu::Buffer,
+        texture: &texture::Texture,
+    ) -> [wgpu::BindGroup; 2] {
+        self.ping_pong_textures.map(|input| {
+            let input = wgpu.transient_textures.get(input);
+
+            wgpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post_process_bind_group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&input.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                ],
+            })
+        })
+    }
+
+    fn effect(&self, name: &str) -> Option<&PostProcessEffect> {
+        self.effects.iter().find(|effect| effect.name == name)
+    }
+
+    fn enabled_effects(&self) -> Vec<(&PostProcessEffect, &PostProcessEffectSettings)> {
+        self.settings
+            .effects
+            .iter()
+            .filter(|settings| settings.enabled)
+            .filter_map(|settings| Some((self.effect(&settings.name)?, settings)))
+            .collect()
+    }
+
+    // the ping pong textures are recreated along with the surface so have to be bound again
+    pub fn resize(&mut self, wgpu: &WgpuContext) {
+        let bind_groups = self
+            .effects
+            .iter()
+            .map(|effect| self.create_bind_groups(wgpu, &effect.uniform_buffer, &effect.texture))
+            .collect::<Vec<_>>();
+
+        for (effect, bind_groups) in self.effects.iter_mut().zip(bind_groups) {
+            effect.bind_groups = bind_groups;
+        }
+    }
+
+    pub fn settings(&self) -> &PostProcessSettings {
+        &self.settings
+    }
+
+    // can reorder, toggle and tweak the effects but not add new ones, effects the stack doesn't know about are skipped
+    pub fn set_settings(&mut self, settings: PostProcessSettings) {
+        self.settings = settings;
+    }
+
+    fn write_uniform(
+        &self,
+        wgpu: &WgpuContext,
+        effect: &PostProcessEffect,
+        settings: &PostProcessEffectSettings,
+    ) {
+        let mut parameters = [0.0; MAX_PARAMETERS];
+        for (value, parameter) in parameters.iter_mut().zip(settings.parameters.iter()) {
+            *value = parameter.value;
+        }
+
+        let uniform = PostProcessUniform {
+            parameters,
+            texel_size: [
+                1.0 / wgpu.config.width as f32,
+                1.0 / wgpu.config.height as f32,
+            ],
+            output_is_srgb: self.output_format.is_srgb() as u32,
+            _padding: 0,
+        };
+
+        wgpu.queue
+            .write_buffer(&effect.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn update(&self, wgpu: &WgpuContext) {
+        for (effect, settings) in self.enabled_effects() {
+            self.write_uniform(wgpu, effect, settings);
+        }
+    }
+
+    // where the scene should be drawn so that it goes through the stack, the output itself if every effect is off
+    pub fn target<'a>(
+        &self,
+        wgpu: &'a WgpuContext,
+        output: &'a wgpu::TextureView,
+    ) -> &'a wgpu::TextureView {
+        match self.target_resource() {
+            render::Resource::Texture(id) => &wgpu.transient_textures.get(id).view,
+            render::Resource::External(_) => output,
+        }
+    }
+
+    // what target() returns as a render graph resource
+    pub fn target_resource(&self) -> render::Resource {
+        if self.enabled_effects().is_empty() {
+            render::SURFACE
+        } else {
+            render::Resource::Texture(self.ping_pong_textures[0])
+        }
+    }
+
+    // runs the enabled effects in order, the first reads what was drawn to target() and the last writes to output
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        wgpu: &WgpuContext,
+        output: &wgpu::TextureView,
+    ) {
+        let effects = self.enabled_effects();
+
+        for (i, (effect, _)) in effects.iter().enumerate() {
+            let target = if i == effects.len() - 1 {
+                output
+            } else {
+                &wgpu
+                    .transient_textures
+                    .get(self.ping_pong_textures[(i + 1) % 2])
+                    .view
+            };
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("post_process_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // every pixel is overwritten
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&effect.pipeline);
+            render_pass.set_bind_group(0, &effect.bind_groups[i % 2], &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -1,17 +1,34 @@
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
 use wgpu::util::DeviceExt;
 
+use crate::common::asset::{AssetServer, Handle};
+use crate::common::camera::Camera;
 use crate::common::context::WgpuContext;
+use crate::common::culling::{BoundingSphere, CullingStats, Frustum};
 use crate::common::instance::{ModelInstance, RawInstance};
-use crate::common::model::InstancedModel;
+use crate::common::light::{Light, LightsUniform};
+use crate::common::lod::{self, LodSettings};
+use crate::common::model::{InstancedModel, Model, VisibleInstance};
+use crate::common::texture::Texture;
 
 pub struct RenderData {
     pub models: Vec<InstancedModel>,
+    // add or remove lights here, uploaded by update_lights_buffer
+    pub lights: Vec<Light>,
+    pub ambient_light: cg::Vector3<f32>,
+    // from the last call to cull
+    pub culling_stats: CullingStats,
     // instance buffer for models which will only be drawn once e.g the map
     single_instance_buffer: wgpu::Buffer,
+    lights_buffer: wgpu::Buffer,
+    lights_bind_group: wgpu::BindGroup,
 }
 
 impl RenderData {
-    pub fn new(device: &wgpu::Device) -> Self {
+    pub fn new(device: &wgpu::Device, lights_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
         let instance = ModelInstance::default();
         let instance_raw = RawInstance::from(&instance);
         let single_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -20,74 +37,469 @@ impl RenderData {
             contents: bytemuck::cast_slice(&[instance_raw]),
         });
 
+        let ambient_light = cg::Vector3::new(0.1, 0.1, 0.1);
+
+        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lights_buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::cast_slice(&[LightsUniform::new(ambient_light, &[])]),
+        });
+
+        let lights_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("lights_bind_group"),
+            layout: lights_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: lights_buffer.as_entire_binding(),
+            }],
+        });
+
         Self {
             single_instance_buffer,
+            lights_buffer,
+            lights_bind_group,
             models: vec![],
+            lights: vec![],
+            ambient_light,
+            culling_stats: CullingStats::default(),
         }
     }
 
-    pub fn single_instance_slice(&self) -> wgpu::BufferSlice {
+    pub fn lights_bind_group(&self) -> &wgpu::BindGroup {
+        &self.lights_bind_group
+    }
+
+    // buffer is a fixed size so it is always overwritten rather than recreated
+    pub fn update_lights_buffer(&self, wgpu_context: &WgpuContext) {
+        wgpu_context.queue.write_buffer(
+            &self.lights_buffer,
+            0,
+            bytemuck::cast_slice(&[LightsUniform::new(self.ambient_light, &self.lights)]),
+        );
+    }
+
+    pub fn single_instance_slice(&self) -> wgpu::BufferSlice<'_> {
         self.single_instance_buffer.slice(..)
     }
 
-    pub fn update_instance_buffers(&mut self, wgpu_context: &WgpuContext) {
+    // hides models and instances that are outside the frustum, call before update_instance_buffers so only the visible instances are uploaded
+    // shadow_sweep = how far and in which direction shadows are cast, things outside the frustum are kept if their shadow could land inside it
+    pub fn cull(&mut self, frustum: &Frustum, shadow_sweep: Option<cg::Vector3<f32>>) {
+        let mut stats = CullingStats::default();
+
         for model in self.models.iter_mut() {
-            if model.instances.is_none() {
-                continue;
-            }
+            let sphere = model.model.bounds.bounding_sphere();
 
-            let model_instance_data = model
-                .instances
-                .as_ref()
-                .unwrap()
-                .iter()
-                .map(RawInstance::from)
-                .collect::<Vec<RawInstance>>();
-
-            match &model.instance_buffer {
-                Some(instance_buffer) => {
-                    let current_instance_buffer_len =
-                        instance_buffer.size() / std::mem::size_of::<RawInstance>() as u64;
-
-                    let next_instance_buffer_len = model.instances.as_ref().unwrap().len() as u64;
-
-                    if next_instance_buffer_len > current_instance_buffer_len {
-                        model.instance_buffer = Some(wgpu_context.device.create_buffer_init(
-                            &wgpu::util::BufferInitDescriptor {
-                                label: Some(
-                                    format!("{:?}-instance-buffer", model.model.name).as_str(),
+            let is_visible = |sphere: BoundingSphere| match shadow_sweep {
+                Some(sweep) => frustum.intersects_swept_sphere(&sphere, sweep),
+                None => frustum.intersects_sphere(&sphere),
+            };
+
+            match &model.instances {
+                // left to GpuCulling
+                Some(instances) if model.indirect_draws.is_some() => {
+                    stats.gpu_instances += instances.len() as u32;
+                    model.visible_instances = None;
+                }
+                Some(instances) => {
+                    let visible_instances = instances
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, instance)| {
+                            is_visible(BoundingSphere {
+                                center: cg::Point3::from_vec(
+                                    instance.rotation.rotate_vector(sphere.center.to_vec())
+                                        + instance.position,
                                 ),
-                                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                                contents: bytemuck::cast_slice(&model_instance_data),
-                            },
-                        ));
-
-                        log::info!(
-                            "Resized instance buffer for model \"{}\" to {} elements from {} elements",
-                            model.model.name,
-                            current_instance_buffer_len,
-                            next_instance_buffer_len
-                        )
-                    } else {
-                        wgpu_context.queue.write_buffer(
-                            model.instance_buffer.as_ref().unwrap(),
-                            0,
-                            bytemuck::cast_slice(&model_instance_data),
-                        );
-                    }
+                                radius: sphere.radius,
+                            })
+                        })
+                        .map(|(index, _)| VisibleInstance::new(index))
+                        .collect::<Vec<_>>();
+
+                    stats.visible_instances += visible_instances.len() as u32;
+                    stats.culled_instances += (instances.len() - visible_instances.len()) as u32;
+
+                    model.visible_instances = Some(visible_instances);
                 }
                 None => {
-                    model.instance_buffer = Some(wgpu_context.device.create_buffer_init(
-                        &wgpu::util::BufferInitDescriptor {
-                            label: Some(format!("{:?}-instance-buffer", model.model.name).as_str()),
-                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                            contents: bytemuck::cast_slice(&model_instance_data),
-                        },
-                    ));
-
-                    log::info!("Created instance buffer for model \"{}\"", model.model.name)
+                    // the box fits tighter than the sphere and models without instances aren't moved
+                    model.visible = match shadow_sweep {
+                        Some(_) => is_visible(sphere),
+                        None => frustum.intersects_box(&model.model.bounds),
+                    };
+
+                    stats.visible_instances += model.visible as u32;
+                    stats.culled_instances += !model.visible as u32;
                 }
             }
+
+            if model.drawn_instance_count() > 0 {
+                stats.visible_models += 1;
+            } else {
+                stats.culled_models += 1;
+            }
+        }
+
+        self.culling_stats = stats;
+    }
+
+    // picks the lod each visible instance is drawn with, call after cull and before update_instance_buffers
+    // the camera has to be the one the frame is drawn from, same as the frustum given to cull
+    pub fn select_lods(&mut self, camera: &Camera, settings: &LodSettings) {
+        for model in self.models.iter_mut() {
+            lod::select_lods(model, camera, settings);
         }
     }
+
+    // swaps the models and lods loaded through handles for the ones the asset server reloaded, see AssetServer::reload
+    pub fn reload_models(&mut self, asset_server: &AssetServer, reloaded: &[Handle<Model>]) {
+        for model in self.models.iter_mut() {
+            for lod in model.lods.iter_mut() {
+                let reloaded_model = lod
+                    .handle
+                    .as_ref()
+                    .filter(|handle| reloaded.contains(handle))
+                    .and_then(|handle| asset_server.get(handle));
+
+                if let Some(reloaded_model) = reloaded_model {
+                    lod.model = reloaded_model;
+                }
+            }
+
+            let Some(handle) = model
+                .handle
+                .as_ref()
+                .filter(|handle| reloaded.contains(handle))
+            else {
+                continue;
+            };
+
+            if let Some(reloaded_model) = asset_server.get(handle) {
+                model.model = reloaded_model;
+                // sized for the old meshes, GpuCulling::update makes new ones
+                model.indirect_draws = None;
+            }
+        }
+    }
+
+    pub fn update_instance_buffers(&mut self, wgpu_context: &WgpuContext) {
+        for model in self.models.iter_mut() {
+            let Some(instances) = &model.instances else {
+                continue;
+            };
+
+            // uploaded by GpuCulling::update
+            if model.indirect_draws.is_some() {
+                continue;
+            }
+
+            let raw_instances = |visible_instances: &[VisibleInstance]| {
+                visible_instances
+                    .iter()
+                    .map(|visible| RawInstance::new(&instances[visible.index], visible.fade))
+                    .collect::<Vec<RawInstance>>()
+            };
+
+            let model_instance_data = match &model.visible_instances {
+                Some(visible_instances) => raw_instances(visible_instances),
+                None => instances.iter().map(RawInstance::from).collect(),
+            };
+
+            update_instance_buffer(
+                wgpu_context,
+                &mut model.instance_buffer,
+                &model.model.name,
+                &model_instance_data,
+            );
+
+            for lod in model.lods.iter_mut() {
+                update_instance_buffer(
+                    wgpu_context,
+                    &mut lod.instance_buffer,
+                    &lod.model.name,
+                    &raw_instances(&lod.visible_instances),
+                );
+            }
+        }
+    }
+}
+
+// grows the buffer if there are more instances than it has room for
+fn update_instance_buffer(
+    wgpu_context: &WgpuContext,
+    instance_buffer: &mut Option<wgpu::Buffer>,
+    name: &str,
+    model_instance_data: &[RawInstance],
+) {
+    // every instance was culled, nothing will be drawn from the buffer
+    if model_instance_data.is_empty() {
+        return;
+    }
+
+    match instance_buffer {
+        Some(buffer) => {
+            let current_instance_buffer_len =
+                buffer.size() / std::mem::size_of::<RawInstance>() as u64;
+
+            let next_instance_buffer_len = model_instance_data.len() as u64;
+
+            if next_instance_buffer_len > current_instance_buffer_len {
+                *instance_buffer = Some(wgpu_context.device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some(format!("{:?}-instance-buffer", name).as_str()),
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        contents: bytemuck::cast_slice(model_instance_data),
+                    },
+                ));
+
+                log::info!(
+                    "Resized instance buffer for model \"{}\" to {} elements from {} elements",
+                    name,
+                    next_instance_buffer_len,
+                    current_instance_buffer_len
+                )
+            } else {
+                wgpu_context.queue.write_buffer(
+                    buffer,
+                    0,
+                    bytemuck::cast_slice(model_instance_data),
+                );
+            }
+        }
+        None => {
+            *instance_buffer = Some(wgpu_context.device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(format!("{:?}-instance-buffer", name).as_str()),
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    contents: bytemuck::cast_slice(model_instance_data),
+                },
+            ));
+
+            log::info!("Created instance buffer for model \"{}\"", name)
+        }
+    }
+}
+
+// the texture currently being presented, or the headless color texture
+pub const SURFACE: Resource = Resource::External("surface");
+// WgpuContext::hdr_texture, and the msaa texture that resolves into it
+pub const HDR: Resource = Resource::External("hdr");
+// WgpuContext::depth_texture
+pub const DEPTH: Resource = Resource::External("depth");
+// every cascade of the shadow map
+pub const SHADOW_MAP: Resource = Resource::External("shadow_map");
+// the visible instances and draw arguments written by GpuCulling
+pub const INSTANCES: Resource = Resource::External("instances");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureId(usize);
+
+// something a render graph pass reads or writes, only used to work out which order the passes run in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+    // owned by TransientTextures
+    Texture(TextureId),
+    // owned by something outside the graph e.g the shadow map or the context's hdr texture
+    External(&'static str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureSize {
+    // follows the surface when it is resized
+    Surface,
+    Fixed { width: u32, height: u32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct TransientTextureDescriptor {
+    pub label: &'static str,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub size: TextureSize,
+    pub sample_count: u32,
+}
+
+// textures that only exist for passes to render into and read back from, recreated when the surface is resized
+#[derive(Default)]
+pub struct TransientTextures {
+    textures: Vec<(TransientTextureDescriptor, Texture)>,
+}
+
+impl TransientTextures {
+    fn create_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        descriptor: &TransientTextureDescriptor,
+    ) -> Texture {
+        let (width, height) = match descriptor.size {
+            TextureSize::Surface => (config.width, config.height),
+            TextureSize::Fixed { width, height } => (width, height),
+        };
+
+        Texture::from_descriptor(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some(descriptor.label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: descriptor.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: descriptor.format,
+                usage: descriptor.usage,
+                view_formats: &[],
+            },
+        )
+    }
+
+    // use WgpuContext::create_transient_texture
+    pub(crate) fn create(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        descriptor: TransientTextureDescriptor,
+    ) -> TextureId {
+        let texture = Self::create_texture(device, config, &descriptor);
+        self.textures.push((descriptor, texture));
+
+        TextureId(self.textures.len() - 1)
+    }
+
+    pub fn get(&self, id: TextureId) -> &Texture {
+        &self.textures[id.0].1
+    }
+
+    // only surface sized textures change, anything bound to them has to be bound again afterwards
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        for (descriptor, texture) in self.textures.iter_mut() {
+            if descriptor.size == TextureSize::Surface {
+                *texture = Self::create_texture(device, config, descriptor);
+            }
+        }
+    }
+}
+
+type RecordPass<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder) -> Result<()> + 'a>;
+
+struct RenderGraphPass<'a> {
+    name: &'static str,
+    reads: Vec<Resource>,
+    writes: Vec<Resource>,
+    record: RecordPass<'a>,
+}
+
+// built every frame, passes can be added in any order and are run in the order their resources need
+pub struct RenderGraph<'a> {
+    passes: Vec<RenderGraphPass<'a>>,
+}
+
+impl<'a> Default for RenderGraph<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self { passes: Vec::new() }
+    }
+
+    // a pass that reads a resource runs after every pass that writes it
+    // passes that write the same resource run in the order they were added, so a pass that draws on top of another should read and write the resource
+    pub fn add_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[Resource],
+        writes: &[Resource],
+        record: impl FnOnce(&mut wgpu::CommandEncoder) -> Result<()> + 'a,
+    ) {
+        self.passes.push(RenderGraphPass {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record: Box::new(record),
+        });
+    }
+
+    // whether pass a has to run before pass b
+    fn runs_before(&self, a: usize, b: usize) -> bool {
+        let (first, second) = (&self.passes[a], &self.passes[b]);
+
+        first.writes.iter().any(|resource| {
+            if second.writes.contains(resource) {
+                a < b
+            } else {
+                second.reads.contains(resource)
+            }
+        })
+    }
+
+    // topological sort, the earliest added pass goes first when there is a choice
+    fn execution_order(&self) -> Result<Vec<usize>> {
+        let pass_count = self.passes.len();
+
+        let dependents = (0..pass_count)
+            .map(|a| {
+                (0..pass_count)
+                    .filter(|&b| a != b && self.runs_before(a, b))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut dependency_counts = vec![0; pass_count];
+        for &b in dependents.iter().flatten() {
+            dependency_counts[b] += 1;
+        }
+
+        let mut order = Vec::with_capacity(pass_count);
+        let mut scheduled = vec![false; pass_count];
+
+        while order.len() < pass_count {
+            let Some(next) = (0..pass_count).find(|&i| !scheduled[i] && dependency_counts[i] == 0)
+            else {
+                let cycle = (0..pass_count)
+                    .filter(|&i| !scheduled[i])
+                    .map(|i| self.passes[i].name)
+                    .collect::<Vec<_>>();
+
+                return Err(eyre!(
+                    "Render graph passes {cycle:?} depend on each other so can't be ordered"
+                ));
+            };
+
+            scheduled[next] = true;
+            order.push(next);
+
+            for &dependent in dependents[next].iter() {
+                dependency_counts[dependent] -= 1;
+            }
+        }
+
+        Ok(order)
+    }
+
+    // records every pass into one encoder, nothing is recorded if the passes can't be ordered
+    pub fn execute(self, wgpu: &WgpuContext) -> Result<wgpu::CommandBuffer> {
+        let order = self.execution_order()?;
+
+        let mut encoder = wgpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("render_graph_encoder"),
+            });
+
+        let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+
+        for i in order {
+            let pass = passes[i].take().unwrap();
+            (pass.record)(&mut encoder)
+                .map_err(|err| eyre!("Render graph pass \"{}\" failed: {err}", pass.name))?;
+        }
+
+        Ok(encoder.finish())
+    }
 }
@@ -0,0 +1,522 @@
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::Result;
+
+use crate::common::asset::{AssetServer, Handle};
+use crate::common::camera::{Camera, OPENGL_TO_WGPU_MATRIX};
+use crate::common::instance::RawInstance;
+use crate::common::light::Light;
+use crate::common::model::{BufferContents, DrawModels, ModelVertex};
+use crate::common::pipeline;
+use crate::common::render::RenderData;
+use crate::common::texture::Texture;
+
+// must match MAX_CASCADES in shader.wgsl
+pub const MAX_CASCADES: usize = 4;
+
+// tint of each cascade when ShadowSettings::debug_cascades is on, must match CASCADE_DEBUG_COLORS in shader.wgsl
+pub const CASCADE_DEBUG_COLORS: [[f32; 3]; MAX_CASCADES] = [
+    [1.0, 0.3, 0.3],
+    [0.3, 1.0, 0.3],
+    [0.3, 0.3, 1.0],
+    [1.0, 1.0, 0.3],
+];
+
+// relative to the assets directory
+const SHADER_PATH: &str = "shadow.wgsl";
+
+// geometry this far towards the light from a cascade still casts shadows into it
+const CASTER_DISTANCE: f32 = 50.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    // width and height of each cascade in texels
+    pub resolution: u32,
+    // 1 to MAX_CASCADES
+    pub cascade_count: u32,
+    // shadows stop at this distance from the camera, or the camera's z_far if that is closer
+    pub max_distance: f32,
+    // how the camera frustum is split, 0 = evenly, 1 = logarithmically i.e more resolution close up
+    pub split_lambda: f32,
+    // fraction of each cascade at its far end that is blended with the next one to hide the seam
+    pub blend_fraction: f32,
+    // applied by the rasterizer when rendering the shadow map, steeper surfaces get more bias
+    pub constant_bias: i32,
+    pub slope_bias: f32,
+    // subtracted from the fragment's light space depth before comparing
+    pub comparison_bias: f32,
+    // texels to push the fragment along its normal before looking it up, fixes acne on surfaces facing away from the light
+    pub normal_bias: f32,
+    // samples (2 * pcf_radius + 1)^2 texels, 0 = only the hardware 2x2 filtering
+    pub pcf_radius: u32,
+    // tints the scene with CASCADE_DEBUG_COLORS to show which cascade is used where
+    pub debug_cascades: bool,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            cascade_count: 4,
+            max_distance: 60.0,
+            split_lambda: 0.75,
+            blend_fraction: 0.1,
+            constant_bias: 2,
+            slope_bias: 2.0,
+            comparison_bias: 0.0005,
+            normal_bias: 1.5,
+            pcf_radius: 1,
+            debug_cascades: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct RawCascade {
+    view_projection: [[f32; 4]; 4],
+    // view depth of the cascade's far end
+    split_depth: f32,
+    // world space width of one texel
+    texel_world_size: f32,
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    cascades: [RawCascade; MAX_CASCADES],
+    // w is unused, view depth is measured along this from the camera position
+    camera_forward: [f32; 4],
+    comparison_bias: f32,
+    texel_size: f32,
+    pcf_radius: u32,
+    // index into RenderData::lights of the light casting the shadow, NO_SHADOW_LIGHT if there isn't one
+    light_index: u32,
+    normal_bias: f32,
+    cascade_count: u32,
+    blend_fraction: f32,
+    debug_cascades: u32,
+}
+
+// must match NO_SHADOW_LIGHT in shader.wgsl
+const NO_SHADOW_LIGHT: u32 = u32::MAX;
+
+// what the shadow pass needs to render into a single layer of the shadow map
+struct CascadeTarget {
+    view: wgpu::TextureView,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+// cascaded shadow map for the first directional light in the scene i.e the sun
+pub struct ShadowMap {
+    settings: ShadowSettings,
+    texture: Texture,
+    cascade_targets: Vec<CascadeTarget>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pass_bind_group_layout: wgpu::BindGroupLayout,
+    // kept so it stays loaded and the pipeline can be recreated when it's hot reloaded or the bias changes
+    shader: Option<Handle<pipeline::Shader>>,
+    // None until update_pipeline makes it, the cascades are still cleared so nothing is in shadow
+    pipeline: Option<wgpu::RenderPipeline>,
+    // the bias the pipeline was made with, None makes update_pipeline recreate it
+    built_for: Option<wgpu::DepthBiasState>,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let settings = Self::clamp_settings(settings);
+
+        let texture = Self::create_texture(device, &settings);
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadow_buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pass_bind_group_layout = pipeline::create_shadow_pass_bind_group_layout(device);
+        let cascade_targets =
+            Self::create_cascade_targets(device, &pass_bind_group_layout, &texture, &settings);
+
+        let bind_group_layout = pipeline::create_shadow_bind_group_layout(device);
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &texture, &uniform_buffer);
+
+        Self {
+            settings,
+            texture,
+            cascade_targets,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            pass_bind_group_layout,
+            shader: None,
+            pipeline: None,
+            built_for: None,
+        }
+    }
+
+    fn clamp_settings(mut settings: ShadowSettings) -> ShadowSettings {
+        settings.cascade_count = settings.cascade_count.clamp(1, MAX_CASCADES as u32);
+        settings
+    }
+
+    fn create_texture(device: &wgpu::Device, settings: &ShadowSettings) -> Texture {
+        Texture::create_shadow_texture(
+            device,
+            settings.resolution,
+            settings.cascade_count,
+            Some("shadow-map"),
+        )
+    }
+
+    fn create_cascade_targets(
+        device: &wgpu::Device,
+        pass_bind_group_layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+        settings: &ShadowSettings,
+    ) -> Vec<CascadeTarget> {
+        (0..settings.cascade_count)
+            .map(|layer| {
+                let view = texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_cascade_view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+
+                let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("shadow_cascade_buffer"),
+                    size: std::mem::size_of::<[[f32; 4]; 4]>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("shadow_cascade_bind_group"),
+                    layout: pass_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    }],
+                });
+
+                CascadeTarget {
+                    view,
+                    uniform_buffer,
+                    bind_group,
+                }
+            })
+            .collect()
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    fn depth_bias(settings: &ShadowSettings) -> wgpu::DepthBiasState {
+        wgpu::DepthBiasState {
+            constant: settings.constant_bias,
+            slope_scale: settings.slope_bias,
+            clamp: 0.0,
+        }
+    }
+
+    // recreates the pipeline if the bias changed or the shader was reloaded since it was last made, true if it was
+    // a failure is only returned once, it's tried again when the bias changes or the shader is reloaded
+    pub fn update_pipeline(
+        &mut self,
+        asset_server: &mut AssetServer,
+        device: &wgpu::Device,
+    ) -> Result<bool> {
+        let built_for = Some(Self::depth_bias(&self.settings));
+        if self.built_for == built_for {
+            return Ok(false);
+        }
+        self.built_for = built_for;
+
+        let handle = asset_server.load_shader(SHADER_PATH, &pipeline::ShaderDefines::new(), device);
+        self.shader = Some(handle.clone());
+
+        let pipeline = asset_server.try_get(&handle).and_then(|shader| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("shadow_pipeline_layout"),
+                bind_group_layouts: &[&self.pass_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+            // a reloaded shader might not fit the pipeline anymore
+            pipeline::validated(device, || {
+                pipeline::create_shadow_pipeline(
+                    device,
+                    &layout,
+                    &shader.module,
+                    &[ModelVertex::buffer_layout(), RawInstance::buffer_layout()],
+                    Self::depth_bias(&self.settings),
+                )
+            })
+        });
+
+        match pipeline {
+            Ok(pipeline) => {
+                self.pipeline = Some(pipeline);
+                Ok(true)
+            }
+            Err(error) => {
+                self.pipeline = None;
+                Err(error)
+            }
+        }
+    }
+
+    // the pipeline is recreated by the next update_pipeline if its shader was reloaded
+    pub fn reload(&mut self, reloaded_shaders: &[Handle<pipeline::Shader>]) {
+        if self
+            .shader
+            .as_ref()
+            .is_some_and(|handle| reloaded_shaders.contains(handle))
+        {
+            self.built_for = None;
+        }
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    // recreates the shadow map if the settings that it depends on changed, the pipeline is left to update_pipeline
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = Self::clamp_settings(settings);
+
+        if settings.resolution != self.settings.resolution
+            || settings.cascade_count != self.settings.cascade_count
+        {
+            self.texture = Self::create_texture(device, &settings);
+            self.cascade_targets = Self::create_cascade_targets(
+                device,
+                &self.pass_bind_group_layout,
+                &self.texture,
+                &settings,
+            );
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.texture,
+                &self.uniform_buffer,
+            );
+        }
+
+        self.settings = settings;
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    // view depths where each cascade ends, blends between evenly spaced and logarithmic splits
+    fn split_depths(&self, near: f32, far: f32) -> Vec<f32> {
+        let count = self.settings.cascade_count;
+        let lambda = self.settings.split_lambda.clamp(0.0, 1.0);
+
+        (1..=count)
+            .map(|i| {
+                let fraction = i as f32 / count as f32;
+                let logarithmic = near * (far / near).powf(fraction);
+                let uniform = near + (far - near) * fraction;
+
+                lambda * logarithmic + (1.0 - lambda) * uniform
+            })
+            .collect()
+    }
+
+    // bounding sphere of the part of the camera frustum between near and far, a sphere doesn't change size as the camera rotates so the shadows don't shimmer
+    fn frustum_slice_bounds(camera: &Camera, near: f32, far: f32) -> (cg::Point3<f32>, f32) {
+        let forward = camera.direction.normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+
+        let tan_half_fov = (cg::Deg(camera.fov) / 2.0).tan();
+
+        let corners: Vec<cg::Point3<f32>> = [near, far]
+            .into_iter()
+            .flat_map(|depth| {
+                let half_height = depth * tan_half_fov;
+                let half_width = half_height * camera.aspect_ratio;
+                let center = camera.position + forward * depth;
+
+                [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)]
+                    .map(|(x, y)| center + right * half_width * x + up * half_height * y)
+            })
+            .collect();
+
+        let center = cg::Point3::centroid(&corners);
+        let radius = corners
+            .iter()
+            .map(|corner| corner.distance(center))
+            .fold(0.0, f32::max);
+
+        // rounded up so floating point noise doesn't change the texel size every frame
+        (center, (radius * 16.0).ceil() / 16.0)
+    }
+
+    // orthographic projection looking along the light's direction, covering the sphere around center
+    fn light_view_projection(
+        &self,
+        direction: cg::Vector3<f32>,
+        center: cg::Point3<f32>,
+        radius: f32,
+    ) -> cg::Matrix4<f32> {
+        let direction = direction.normalize();
+
+        // look_at breaks down when looking straight along up
+        let up = if direction.y.abs() > 0.99 {
+            cg::Vector3::unit_z()
+        } else {
+            cg::Vector3::unit_y()
+        };
+
+        let view =
+            cg::Matrix4::look_at_rh(center - direction * (radius + CASTER_DISTANCE), center, up);
+
+        // keep the world lined up with whole texels so shadow edges don't shimmer as the camera moves
+        let texel_size = 2.0 * radius / self.settings.resolution as f32;
+        let origin_light_space = view.transform_point(cg::Point3::origin());
+        let snap = cg::Vector3::new(
+            origin_light_space.x - (origin_light_space.x / texel_size).round() * texel_size,
+            origin_light_space.y - (origin_light_space.y / texel_size).round() * texel_size,
+            0.0,
+        );
+        let view = cg::Matrix4::from_translation(-snap) * view;
+
+        let projection = cg::ortho(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.0,
+            2.0 * radius + CASTER_DISTANCE,
+        );
+
+        OPENGL_TO_WGPU_MATRIX * projection * view
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, lights: &[Light], camera: &Camera) {
+        let sun = lights
+            .iter()
+            .enumerate()
+            .find_map(|(index, light)| match light {
+                Light::Directional { direction, .. } => Some((index, *direction)),
+                _ => None,
+            });
+
+        let mut cascades = [RawCascade::default(); MAX_CASCADES];
+        let light_index = match sun {
+            Some((index, direction)) => {
+                let far = camera.z_far.min(self.settings.max_distance);
+                let mut near = camera.z_near;
+
+                for (i, split_depth) in self.split_depths(near, far).into_iter().enumerate() {
+                    let (center, radius) = Self::frustum_slice_bounds(camera, near, split_depth);
+                    let view_projection = self.light_view_projection(direction, center, radius);
+
+                    cascades[i] = RawCascade {
+                        view_projection: view_projection.into(),
+                        split_depth,
+                        texel_world_size: 2.0 * radius / self.settings.resolution as f32,
+                        ..Default::default()
+                    };
+
+                    queue.write_buffer(
+                        &self.cascade_targets[i].uniform_buffer,
+                        0,
+                        bytemuck::cast_slice(&[cascades[i].view_projection]),
+                    );
+
+                    near = split_depth;
+                }
+
+                index as u32
+            }
+            None => NO_SHADOW_LIGHT,
+        };
+
+        let uniform = ShadowUniform {
+            cascades,
+            camera_forward: camera.direction.normalize().extend(0.0).into(),
+            comparison_bias: self.settings.comparison_bias,
+            texel_size: 1.0 / self.settings.resolution as f32,
+            pcf_radius: self.settings.pcf_radius,
+            light_index,
+            normal_bias: self.settings.normal_bias,
+            cascade_count: self.settings.cascade_count,
+            blend_fraction: self.settings.blend_fraction,
+            debug_cascades: self.settings.debug_cascades as u32,
+        };
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // direction and distance shadows are cast over, for keeping shadow casters outside the view when culling
+    pub fn caster_sweep(&self, lights: &[Light]) -> Option<cg::Vector3<f32>> {
+        lights.iter().find_map(|light| match light {
+            Light::Directional { direction, .. } => {
+                Some(direction.normalize() * (self.settings.max_distance + CASTER_DISTANCE))
+            }
+            _ => None,
+        })
+    }
+
+    // one depth only pass per cascade
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, render_data: &RenderData) {
+        for cascade_target in self.cascade_targets.iter() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow_pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &cascade_target.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            let Some(pipeline) = &self.pipeline else {
+                continue;
+            };
+
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &cascade_target.bind_group, &[]);
+            render_pass.draw_models_geometry(render_data);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use cg::prelude::*;
+use cgmath as cg;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::common::model::ModelVertex;
+
+// borders and seams are held in place by planes along them, weighted much more than the surface so they move last
+const EDGE_CONSTRAINT_WEIGHT: f64 = 10.0;
+// cos of the most a triangle can turn in a single collapse, more and it could end up on its side or facing backwards
+const MAX_TURN_COS: f64 = 0.25;
+
+// sum of squared distances to a set of planes, see https://www.cs.cmu.edu/~garland/Papers/quadrics.pdf
+// symmetric 4x4 matrix, only the upper triangle is stored
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // normal has to be normalized
+    fn from_plane(normal: cg::Vector3<f64>, point: cg::Vector3<f64>, weight: f64) -> Self {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        let d = -normal.dot(point);
+
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+        .scaled(weight)
+    }
+
+    fn scaled(self, weight: f64) -> Self {
+        Self(self.0.map(|value| value * weight))
+    }
+
+    fn error(&self, point: cg::Vector3<f64>) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let (x, y, z) = (point.x, point.y, point.z);
+
+        aa * x * x
+            + bb * y * y
+            + cc * z * z
+            + 2.0 * (ab * x * y + ac * x * z + bc * y * z)
+            + 2.0 * (ad * x + bd * y + cd * z)
+            + dd
+    }
+}
+
+impl std::ops::AddAssign for Quadric {
+    fn add_assign(&mut self, other: Self) {
+        for (value, other) in self.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+    }
+}
+
+// what a position is allowed to collapse onto
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VertexKind {
+    // surrounded by triangles with the same uvs and normals, can go anywhere
+    Manifold,
+    // where the uvs or normals are split into two vertices, both slide along the seam together
+    Seam,
+    // on an open edge e.g where the mesh meets another material, the mesh on the other side would no longer line up if it moved
+    // also corners and anything more tangled than the above
+    Locked,
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+// how the triangles left connect up, rebuilt before every pass of collapses
+struct Topology {
+    // per position
+    kinds: Vec<VertexKind>,
+    // vertices still in use at each position, more than one along seams
+    wedges: Vec<Vec<u32>>,
+    // indices of the triangles using each position
+    triangles: Vec<Vec<usize>>,
+    // undirected position edges
+    border_edges: FxHashSet<(u32, u32)>,
+    seam_edges: FxHashSet<(u32, u32)>,
+}
+
+impl Topology {
+    fn new(indices: &[u32], position_ids: &[u32], position_count: usize) -> Self {
+        let directed_edges = |triangle: &[u32]| {
+            [
+                (triangle[0], triangle[1]),
+                (triangle[1], triangle[2]),
+                (triangle[2], triangle[0]),
+            ]
+        };
+
+        let index_edges = indices
+            .chunks_exact(3)
+            .flat_map(directed_edges)
+            .collect::<FxHashSet<_>>();
+
+        let mut position_edge_counts = FxHashMap::<(u32, u32), u32>::default();
+        for (a, b) in indices.chunks_exact(3).flat_map(directed_edges) {
+            *position_edge_counts
+                .entry((position_ids[a as usize], position_ids[b as usize]))
+                .or_default() += 1;
+        }
+
+        let mut border_edges = FxHashSet::default();
+        let mut seam_edges = FxHashSet::default();
+        let mut non_manifold = vec![false; position_count];
+
+        for (a, b) in indices.chunks_exact(3).flat_map(directed_edges) {
+            let (position_a, position_b) = (position_ids[a as usize], position_ids[b as usize]);
+
+            // more than two triangles share the edge
+            if position_edge_counts[&(position_a, position_b)] > 1 {
+                non_manifold[position_a as usize] = true;
+                non_manifold[position_b as usize] = true;
+            }
+
+            if !position_edge_counts.contains_key(&(position_b, position_a)) {
+                border_edges.insert(edge_key(position_a, position_b));
+            } else if !index_edges.contains(&(b, a)) {
+                // the triangle on the other side uses different vertices at the same positions
+                seam_edges.insert(edge_key(position_a, position_b));
+            }
+        }
+
+        let mut border_edge_counts = vec![0; position_count];
+        for &(a, b) in border_edges.iter() {
+            border_edge_counts[a as usize] += 1;
+            border_edge_counts[b as usize] += 1;
+        }
+
+        let mut seam_edge_counts = vec![0; position_count];
+        for &(a, b) in seam_edges.iter() {
+            seam_edge_counts[a as usize] += 1;
+            seam_edge_counts[b as usize] += 1;
+        }
+
+        let mut wedges = vec![vec![]; position_count];
+        let mut triangles = vec![vec![]; position_count];
+
+        for (triangle_index, triangle) in indices.chunks_exact(3).enumerate() {
+            for &vertex in triangle {
+                let position = position_ids[vertex as usize] as usize;
+
+                if !wedges[position].contains(&vertex) {
+                    wedges[position].push(vertex);
+                }
+                triangles[position].push(triangle_index);
+            }
+        }
+
+        let kinds = (0..position_count)
+            .map(|position| {
+                if non_manifold[position] {
+                    return VertexKind::Locked;
+                }
+
+                match (
+                    border_edge_counts[position],
+                    seam_edge_counts[position],
+                    wedges[position].len(),
+                ) {
+                    (0, 0, 0 | 1) => VertexKind::Manifold,
+                    (0, 2, 2) => VertexKind::Seam,
+                    _ => VertexKind::Locked,
+                }
+            })
+            .collect();
+
+        Self {
+            kinds,
+            wedges,
+            triangles,
+            border_edges,
+            seam_edges,
+        }
+    }
+
+    fn can_collapse(&self, from: u32, to: u32) -> bool {
+        match self.kinds[from as usize] {
+            VertexKind::Manifold => true,
+            VertexKind::Seam => self.seam_edges.contains(&edge_key(from, to)),
+            VertexKind::Locked => false,
+        }
+    }
+}
+
+// reduces the mesh to about target_ratio of its triangles by collapsing edges with the least quadric error first
+// edges are collapsed onto one of their ends so the vertices left keep their uvs, normals and tangents
+// open edges e.g material boundaries and uv or normal seams are kept in place so neighbouring meshes and textures still line up
+pub fn simplify(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    target_ratio: f32,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let positions = vertices
+        .iter()
+        .map(|vertex| cg::Vector3::from(vertex.position).cast::<f64>().unwrap())
+        .collect::<Vec<_>>();
+
+    // vertices that only differ by their uvs or normals share a position
+    let mut position_lookup = FxHashMap::<[u32; 3], u32>::default();
+    let position_ids = vertices
+        .iter()
+        .map(|vertex| {
+            // + 0.0 so -0.0 and 0.0 end up the same
+            let key = vertex.position.map(|component| (component + 0.0).to_bits());
+            let next_id = position_lookup.len() as u32;
+            *position_lookup.entry(key).or_insert(next_id)
+        })
+        .collect::<Vec<u32>>();
+    let position_count = position_lookup.len();
+
+    let position = |vertex: u32| positions[vertex as usize];
+    let position_id = |vertex: u32| position_ids[vertex as usize];
+
+    let is_degenerate = |triangle: &[u32]| {
+        position_id(triangle[0]) == position_id(triangle[1])
+            || position_id(triangle[1]) == position_id(triangle[2])
+            || position_id(triangle[2]) == position_id(triangle[0])
+    };
+
+    let mut indices = indices
+        .chunks_exact(3)
+        .filter(|triangle| !is_degenerate(triangle))
+        .flatten()
+        .copied()
+        .collect::<Vec<u32>>();
+
+    let target_triangle_count =
+        ((indices.len() / 3) as f32 * target_ratio.clamp(0.0, 1.0)).round() as usize;
+
+    let mut quadrics = vec![Quadric::default(); position_count];
+    let topology = Topology::new(&indices, &position_ids, position_count);
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+        let normal = (position(b) - position(a)).cross(position(c) - position(a));
+        let double_area = normal.magnitude();
+
+        if double_area == 0.0 {
+            continue;
+        }
+
+        let normal = normal / double_area;
+        // bigger triangles have more say
+        let quadric = Quadric::from_plane(normal, position(a), double_area / 2.0);
+
+        for vertex in [a, b, c] {
+            quadrics[position_id(vertex) as usize] += quadric;
+        }
+
+        // a plane along the edge facing out of the triangle stops the edge from being pulled sideways
+        for (from, to) in [(a, b), (b, c), (c, a)] {
+            let key = edge_key(position_id(from), position_id(to));
+            if !topology.border_edges.contains(&key) && !topology.seam_edges.contains(&key) {
+                continue;
+            }
+
+            let edge = position(to) - position(from);
+            let edge_normal = edge.cross(normal);
+            if edge_normal.magnitude2() == 0.0 {
+                continue;
+            }
+
+            let constraint = Quadric::from_plane(
+                edge_normal.normalize(),
+                position(from),
+                EDGE_CONSTRAINT_WEIGHT * edge.magnitude2(),
+            );
+            quadrics[position_id(from) as usize] += constraint;
+            quadrics[position_id(to) as usize] += constraint;
+        }
+    }
+
+    let mut topology = Some(topology);
+
+    while indices.len() / 3 > target_triangle_count {
+        let topology = match topology.take() {
+            Some(topology) => topology,
+            None => Topology::new(&indices, &position_ids, position_count),
+        };
+
+        // cheapest direction of every edge that can be collapsed
+        let mut edges = indices
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                [
+                    edge_key(position_id(triangle[0]), position_id(triangle[1])),
+                    edge_key(position_id(triangle[1]), position_id(triangle[2])),
+                    edge_key(position_id(triangle[2]), position_id(triangle[0])),
+                ]
+            })
+            .collect::<Vec<_>>();
+        edges.sort_unstable();
+        edges.dedup();
+
+        // any vertex at the position will do, they all have the same position
+        let position_of = |id: u32| position(topology.wedges[id as usize][0]);
+
+        let mut collapses = edges
+            .into_iter()
+            .flat_map(|(a, b)| {
+                let mut quadric = quadrics[a as usize];
+                quadric += quadrics[b as usize];
+
+                [(a, b), (b, a)]
+                    .into_iter()
+                    .filter(|&(from, to)| topology.can_collapse(from, to))
+                    .map(move |(from, to)| (quadric.error(position_of(to)), from, to))
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+            })
+            .collect::<Vec<_>>();
+        collapses.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut vertex_remap = (0..vertices.len() as u32).collect::<Vec<u32>>();
+        // positions around a collapse this pass, their triangles are out of date until the indices are rewritten
+        let mut touched = vec![false; position_count];
+        let mut triangle_count = indices.len() / 3;
+        let mut collapsed_any = false;
+
+        for (_, from, to) in collapses {
+            if triangle_count <= target_triangle_count {
+                break;
+            }
+
+            if touched[from as usize] || touched[to as usize] {
+                continue;
+            }
+
+            let triangles = &topology.triangles[from as usize];
+            let triangle = |index: usize| &indices[index * 3..index * 3 + 3];
+
+            // each vertex at from moves to the vertex at to on the same side of any seam
+            let targets = topology.wedges[from as usize]
+                .iter()
+                .map(|&wedge| {
+                    triangles.iter().find_map(|&index| {
+                        let triangle = triangle(index);
+                        triangle.contains(&wedge).then(|| {
+                            triangle
+                                .iter()
+                                .copied()
+                                .find(|&vertex| position_id(vertex) == to)
+                        })?
+                    })
+                })
+                .collect::<Option<Vec<u32>>>();
+
+            // a vertex is on a side of the seam that doesn't reach to
+            let Some(targets) = targets else {
+                continue;
+            };
+
+            let to_position = position_of(to);
+            let mut removed_triangles = 0;
+
+            // triangles that turn too far would leave holes and backfaces
+            let flips = triangles.iter().any(|&index| {
+                let triangle = triangle(index);
+                if triangle.iter().any(|&vertex| position_id(vertex) == to) {
+                    return false;
+                }
+
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(position);
+                let moved = |vertex: u32, point: cg::Vector3<f64>| {
+                    if position_id(vertex) == from {
+                        to_position
+                    } else {
+                        point
+                    }
+                };
+                let [moved_a, moved_b, moved_c] = [
+                    moved(triangle[0], a),
+                    moved(triangle[1], b),
+                    moved(triangle[2], c),
+                ];
+
+                let normal = (b - a).cross(c - a);
+                let moved_normal = (moved_b - moved_a).cross(moved_c - moved_a);
+
+                normal.dot(moved_normal)
+                    <= MAX_TURN_COS * normal.magnitude() * moved_normal.magnitude()
+            });
+
+            if flips {
+                continue;
+            }
+
+            for &index in triangles.iter() {
+                if triangle(index)
+                    .iter()
+                    .any(|&vertex| position_id(vertex) == to)
+                {
+                    removed_triangles += 1;
+                }
+
+                for &vertex in triangle(index) {
+                    touched[position_id(vertex) as usize] = true;
+                }
+            }
+
+            for (&wedge, target) in topology.wedges[from as usize].iter().zip(targets) {
+                vertex_remap[wedge as usize] = target;
+            }
+
+            let from_quadric = quadrics[from as usize];
+            quadrics[to as usize] += from_quadric;
+
+            triangle_count -= removed_triangles;
+            collapsed_any = true;
+        }
+
+        // everything left is locked or would flip a triangle
+        if !collapsed_any {
+            break;
+        }
+
+        indices = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                [
+                    vertex_remap[triangle[0] as usize],
+                    vertex_remap[triangle[1] as usize],
+                    vertex_remap[triangle[2] as usize],
+                ]
+            })
+            .filter(|triangle| !is_degenerate(triangle))
+            .flatten()
+            .collect();
+    }
+
+    // only keep the vertices that are still used
+    let mut new_indices = vec![u32::MAX; vertices.len()];
+    let mut simplified_vertices = vec![];
+
+    for index in indices.iter_mut() {
+        if new_indices[*index as usize] == u32::MAX {
+            new_indices[*index as usize] = simplified_vertices.len() as u32;
+            simplified_vertices.push(vertices[*index as usize]);
+        }
+
+        *index = new_indices[*index as usize];
+    }
+
+    (simplified_vertices, indices)
+}
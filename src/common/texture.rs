@@ -1,10 +1,7 @@
-use std::rc::Rc;
-
 use crate::common::resources;
 
 use color_eyre::Result;
 use image::GenericImageView;
-use rustc_hash::FxHashMap;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -12,29 +9,83 @@ pub struct Texture {
     pub sampler: wgpu::Sampler,
 }
 
+// how a texture is filtered when it is drawn smaller than its real size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mipmaps {
+    // single level, for textures that are always drawn at their real size e.g ui
+    None,
+    // full mip chain blended between levels
+    Trilinear,
+    // trilinear plus up to this many anisotropic samples (2, 4, 8 or 16), keeps surfaces at grazing angles sharp
+    Anisotropic(u16),
+}
+
+// how a texture is sampled between and outside its texels, e.g from a glTF sampler
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerSettings {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    // ignored without mipmaps, a single level is always sampled nearest when minified
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+        }
+    }
+}
+
 impl Texture {
     pub fn from_path(
         path: &str,
+        is_normal_map: bool,
+        mipmaps: Mipmaps,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         label: Option<&str>,
     ) -> Result<Self> {
         let data = resources::load_binary(path)?;
-        Self::from_bytes(&data, device, queue, label)
+        Self::from_bytes(&data, is_normal_map, mipmaps, device, queue, label)
     }
 
     pub fn from_bytes(
         bytes: &[u8],
+        is_normal_map: bool,
+        mipmaps: Mipmaps,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         label: Option<&str>,
     ) -> Result<Self> {
         let image = image::load_from_memory(bytes)?;
-        Self::from_image(&image, device, queue, label)
+        Self::from_image(&image, is_normal_map, mipmaps, device, queue, label)
+    }
+
+    // 1x1 texture, stands in for textures a material doesn't have
+    pub fn from_color(
+        color: [u8; 4],
+        is_normal_map: bool,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let image =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+
+        Self::from_image(&image, is_normal_map, Mipmaps::None, device, queue, label)
     }
 
     pub fn from_image(
         image: &image::DynamicImage,
+        is_normal_map: bool,
+        mipmaps: Mipmaps,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         label: Option<&str>,
@@ -49,46 +100,55 @@ impl Texture {
             depth_or_array_layers: 1,
         };
 
+        let levels = match mipmaps {
+            Mipmaps::None => vec![rgba],
+            // normal maps are plain data so are averaged as is
+            Mipmaps::Trilinear | Mipmaps::Anisotropic(_) => {
+                Self::generate_mips(rgba, !is_normal_map)
+            }
+        };
+
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: levels.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            // image stored as sRGB
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            // colors are stored as sRGB, normal maps are plain data so must not be gamma corrected
+            format: if is_normal_map {
+                wgpu::TextureFormat::Rgba8Unorm
+            } else {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            },
             // TEXTURE_BINDING = will be used in shaders, COPY_DST = will be copying data to this
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTextureBase {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
+        for (mip_level, level) in levels.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTextureBase {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                level,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * level.width()),
+                    rows_per_image: Some(level.height()),
+                },
+                wgpu::Extent3d {
+                    width: level.width(),
+                    height: level.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let sampler = Self::create_sampler(device, mipmaps, &SamplerSettings::default());
 
         Ok(Self {
             texture,
@@ -97,11 +157,128 @@ impl Texture {
         })
     }
 
+    // replaces the sampler from_image made, mipmaps has to be what the texture was made with
+    pub fn with_sampler(
+        mut self,
+        device: &wgpu::Device,
+        mipmaps: Mipmaps,
+        settings: &SamplerSettings,
+    ) -> Self {
+        self.sampler = Self::create_sampler(device, mipmaps, settings);
+        self
+    }
+
+    fn create_sampler(
+        device: &wgpu::Device,
+        mipmaps: Mipmaps,
+        settings: &SamplerSettings,
+    ) -> wgpu::Sampler {
+        let linear = wgpu::FilterMode::Linear;
+
+        device.create_sampler(&match mipmaps {
+            Mipmaps::None => wgpu::SamplerDescriptor {
+                address_mode_u: settings.address_mode_u,
+                address_mode_v: settings.address_mode_v,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: settings.mag_filter,
+                min_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            },
+            Mipmaps::Trilinear | Mipmaps::Anisotropic(_) => wgpu::SamplerDescriptor {
+                address_mode_u: settings.address_mode_u,
+                address_mode_v: settings.address_mode_v,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: settings.mag_filter,
+                min_filter: settings.min_filter,
+                mipmap_filter: settings.mipmap_filter,
+                // 1 = off, needs every filter to be linear
+                anisotropy_clamp: match mipmaps {
+                    Mipmaps::Anisotropic(samples)
+                        if settings.mag_filter == linear
+                            && settings.min_filter == linear
+                            && settings.mipmap_filter == linear =>
+                    {
+                        samples.clamp(1, 16)
+                    }
+                    _ => 1,
+                },
+                ..Default::default()
+            },
+        })
+    }
+
+    // every level down to 1x1, each half the size of the one before
+    // srgb colors are averaged in linear space, otherwise distant textures come out too dark
+    fn generate_mips(base: image::RgbaImage, is_srgb: bool) -> Vec<image::RgbaImage> {
+        let (width, height) = base.dimensions();
+        let level_count = 32 - width.max(height).leading_zeros();
+
+        let to_linear = |value: u8| {
+            let value = value as f32 / 255.0;
+            if !is_srgb {
+                value
+            } else if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        let from_linear = |value: f32| {
+            let value = if !is_srgb {
+                value
+            } else if value <= 0.0031308 {
+                value * 12.92
+            } else {
+                1.055 * value.powf(1.0 / 2.4) - 0.055
+            };
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+
+        let mut linear = image::Rgba32FImage::from_fn(width, height, |x, y| {
+            let [r, g, b, a] = base.get_pixel(x, y).0;
+            // alpha is always linear
+            image::Rgba([to_linear(r), to_linear(g), to_linear(b), a as f32 / 255.0])
+        });
+
+        let mut levels = vec![base];
+
+        for level in 1..level_count {
+            linear = image::imageops::resize(
+                &linear,
+                (width >> level).max(1),
+                (height >> level).max(1),
+                image::imageops::FilterType::Triangle,
+            );
+
+            levels.push(image::RgbaImage::from_fn(
+                linear.width(),
+                linear.height(),
+                |x, y| {
+                    let [r, g, b, a] = linear.get_pixel(x, y).0;
+                    image::Rgba([
+                        from_linear(r),
+                        from_linear(g),
+                        from_linear(b),
+                        (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                    ])
+                },
+            ));
+        }
+
+        levels
+    }
+
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    // the scene is rendered with lighting values above 1 then tone mapped down into the surface
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
+    // sample_count has to match the color attachment it is used with
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: Option<&str>,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -114,10 +291,15 @@ impl Texture {
             label,
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            // multisampled depth can't be read with a normal texture binding anyway, and asking for it breaks msaa on the gl backend
+            usage: if sample_count > 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            },
             view_formats: &[],
         });
 
@@ -143,33 +325,196 @@ impl Texture {
             sampler,
         }
     }
-}
 
-pub struct TextureAtlas(FxHashMap<Rc<String>, Rc<Texture>>);
+    // array of square depth textures rendered from a light's point of view, view covers every layer
+    pub fn create_shadow_texture(
+        device: &wgpu::Device,
+        resolution: u32,
+        layers: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: layers,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
 
-impl TextureAtlas {
-    pub fn new() -> Self {
-        Self(FxHashMap::default())
+        // a single layer would otherwise default to a plain 2d view
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        // linear filtering on a comparison sampler blends the results of 4 comparisons, free 2x2 pcf
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
     }
 
-    pub fn get(
-        &mut self,
-        path: Rc<String>,
+    // scene color before tone mapping, same size as the surface
+    pub fn create_hdr_texture(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
-    ) -> Result<Rc<Texture>> {
-        if let Some(texture) = self.0.get(&path) {
-            log::info!("Texture \"{path}\" already loaded, returning reference.");
-            return Ok(texture.clone());
+        config: &wgpu::SurfaceConfiguration,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    // multisampled hdr texture that is rendered to and then resolved into the real target, can't be sampled itself
+    pub fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // never sampled, only here because every texture has one
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
         }
+    }
+
+    // color texture that can be rendered to and copied back to the cpu, used instead of a surface when rendering headless
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
 
-        // todo!("Bind group should be independent of texture, see https://sotrh.github.io/learn-wgpu/intermediate/tutorial11-normals/#normal-mapping");
-        // each material should have a bind group which is created at model load time
-        log::info!("Loading texture \"{path}\".");
-        let texture = Rc::new(Texture::from_path(&path, device, queue, Some(&path))?);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
 
-        self.0.insert(path, texture.clone());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        Ok(texture)
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    // any other kind of texture the gpu fills in itself, sampled linearly and clamped to the edges
+    pub fn from_descriptor(device: &wgpu::Device, descriptor: &wgpu::TextureDescriptor) -> Self {
+        let texture = device.create_texture(descriptor);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
     }
 }
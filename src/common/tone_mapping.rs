@@ -0,0 +1,467 @@
+use color_eyre::Result;
+
+use crate::common::asset::{AssetServer, Handle};
+use crate::common::context::WgpuContext;
+use crate::common::pipeline::{self, Shader, ShaderDefines};
+
+// relative to the assets directory
+const SHADER_PATH: &str = "tone_mapping.wgsl";
+const HISTOGRAM_SHADER_PATH: &str = "luminance_histogram.wgsl";
+
+// must match BIN_COUNT in luminance_histogram.wgsl
+const BIN_COUNT: u64 = 256;
+// build_histogram workgroups cover this many pixels in each direction
+const HISTOGRAM_TILE_SIZE: u32 = 16;
+
+// curve used to squash hdr colors into the 0 to 1 range of the surface
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMappingOperator {
+    // simple, washes out bright colors
+    Reinhard,
+    // film like contrast, the usual choice
+    Aces,
+    // uncharted 2, softer shoulder than aces
+    Filmic,
+}
+
+impl ToneMappingOperator {
+    pub const ALL: [ToneMappingOperator; 3] = [Self::Reinhard, Self::Aces, Self::Filmic];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Reinhard => "Reinhard",
+            Self::Aces => "ACES",
+            Self::Filmic => "Filmic",
+        }
+    }
+
+    // must match the switch in tone_mapping.wgsl
+    fn index(&self) -> u32 {
+        match self {
+            Self::Reinhard => 0,
+            Self::Aces => 1,
+            Self::Filmic => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToneMappingSettings {
+    pub operator: ToneMappingOperator,
+    // measure the scene's brightness every frame and expose for it, like an eye adjusting
+    pub auto_exposure: bool,
+    // in stops i.e each +1 doubles the brightness, used when auto_exposure is off
+    pub exposure: f32,
+    // in stops, added on top of the automatic exposure
+    pub exposure_compensation: f32,
+    // higher = adapts to brightness changes faster
+    pub adaptation_speed: f32,
+    // range of log2 luminance the histogram covers, anything outside is clamped to the ends
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    // only applied when the surface isn't srgb, srgb surfaces are gamma corrected by the hardware
+    pub gamma: f32,
+}
+
+impl Default for ToneMappingSettings {
+    fn default() -> Self {
+        Self {
+            operator: ToneMappingOperator::Aces,
+            auto_exposure: true,
+            exposure: 0.0,
+            exposure_compensation: 0.0,
+            adaptation_speed: 1.5,
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMappingUniform {
+    curve: u32,
+    auto_exposure: u32,
+    manual_exposure: f32,
+    gamma: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct HistogramUniform {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    compensation: f32,
+    pixel_count: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureStorage {
+    average_luminance: f32,
+    exposure: f32,
+}
+
+// maps the hdr texture the scene is rendered into onto the surface
+pub struct ToneMapping {
+    settings: ToneMappingSettings,
+    // gamma is only applied by the shader if the output format doesn't do it already
+    output_is_srgb: bool,
+    uniform_buffer: wgpu::Buffer,
+    histogram_uniform_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    exposure_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    histogram_bind_group_layout: wgpu::BindGroupLayout,
+    histogram_bind_group: wgpu::BindGroup,
+    output_format: wgpu::TextureFormat,
+    pipeline_layout: wgpu::PipelineLayout,
+    histogram_pipeline_layout: wgpu::PipelineLayout,
+    // kept so they stay loaded and the pipelines can be recreated when they're hot reloaded
+    shader: Option<Handle<Shader>>,
+    histogram_shader: Option<Handle<Shader>>,
+    // None until update_pipelines makes it, the output is only cleared without it
+    pipeline: Option<wgpu::RenderPipeline>,
+    // build_histogram then average_histogram, None until update_pipelines makes them, the exposure isn't measured without them
+    histogram_pipelines: Option<(wgpu::ComputePipeline, wgpu::ComputePipeline)>,
+    // false makes update_pipelines recreate the pipelines
+    built: bool,
+}
+
+impl ToneMapping {
+    pub fn new(
+        wgpu: &WgpuContext,
+        output_format: wgpu::TextureFormat,
+        settings: ToneMappingSettings,
+    ) -> Self {
+        let device = &wgpu.device;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("tone_mapping_buffer"),
+            size: std::mem::size_of::<ToneMappingUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let histogram_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("luminance_histogram_settings_buffer"),
+            size: std::mem::size_of::<HistogramUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // the average pass clears it after reading, zeroed buffers start out cleared
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("luminance_histogram_buffer"),
+            size: BIN_COUNT * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let exposure_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("exposure_buffer"),
+            size: std::mem::size_of::<ExposureStorage>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // negative exposure = not measured yet
+        wgpu.queue.write_buffer(
+            &exposure_buffer,
+            0,
+            bytemuck::cast_slice(&[ExposureStorage {
+                average_luminance: 0.0,
+                exposure: -1.0,
+            }]),
+        );
+
+        let bind_group_layout = pipeline::create_tone_mapping_bind_group_layout(device);
+        let histogram_bind_group_layout =
+            pipeline::create_luminance_histogram_bind_group_layout(device);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("tone_mapping_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let histogram_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("luminance_histogram_pipeline_layout"),
+                bind_group_layouts: &[&histogram_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let (bind_group, histogram_bind_group) = Self::create_bind_groups(
+            wgpu,
+            &bind_group_layout,
+            &histogram_bind_group_layout,
+            &uniform_buffer,
+            &histogram_uniform_buffer,
+            &histogram_buffer,
+            &exposure_buffer,
+        );
+
+        Self {
+            settings,
+            output_is_srgb: output_format.is_srgb(),
+            uniform_buffer,
+            histogram_uniform_buffer,
+            histogram_buffer,
+            exposure_buffer,
+            bind_group_layout,
+            bind_group,
+            histogram_bind_group_layout,
+            histogram_bind_group,
+            output_format,
+            pipeline_layout,
+            histogram_pipeline_layout,
+            shader: None,
+            histogram_shader: None,
+            pipeline: None,
+            histogram_pipelines: None,
+            built: false,
+        }
+    }
+
+    // recreates the pipelines if they haven't been made yet or a shader was reloaded, true if they were
+    // a failure is only returned once, they're tried again when the shader is reloaded
+    // one failing doesn't stop the other from being used
+    pub fn update_pipelines(
+        &mut self,
+        asset_server: &mut AssetServer,
+        device: &wgpu::Device,
+    ) -> Result<bool> {
+        if self.built {
+            return Ok(false);
+        }
+        self.built = true;
+
+        let shader = asset_server.load_shader(SHADER_PATH, &ShaderDefines::new(), device);
+        let histogram_shader =
+            asset_server.load_shader(HISTOGRAM_SHADER_PATH, &ShaderDefines::new(), device);
+
+        let pipeline = asset_server.try_get(&shader).and_then(|shader| {
+            pipeline::validated(device, || {
+                pipeline::create_fullscreen_pipeline(
+                    device,
+                    &self.pipeline_layout,
+                    &shader.module,
+                    self.output_format,
+                    Some("tone_mapping_pipeline"),
+                )
+            })
+        });
+
+        let histogram_pipelines = asset_server.try_get(&histogram_shader).and_then(|shader| {
+            pipeline::validated(device, || {
+                let create = |entry_point: &str, label: &str| {
+                    pipeline::create_compute_pipeline(
+                        device,
+                        &self.histogram_pipeline_layout,
+                        &shader.module,
+                        entry_point,
+                        Some(label),
+                    )
+                };
+
+                (
+                    create("build_histogram", "build_histogram_pipeline"),
+                    create("average_histogram", "average_histogram_pipeline"),
+                )
+            })
+        });
+
+        self.shader = Some(shader);
+        self.histogram_shader = Some(histogram_shader);
+
+        let (pipeline, pipeline_error) = match pipeline {
+            Ok(pipeline) => (Some(pipeline), None),
+            Err(error) => (None, Some(error)),
+        };
+        let (histogram_pipelines, histogram_error) = match histogram_pipelines {
+            Ok(histogram_pipelines) => (Some(histogram_pipelines), None),
+            Err(error) => (None, Some(error)),
+        };
+        self.pipeline = pipeline;
+        self.histogram_pipelines = histogram_pipelines;
+
+        match pipeline_error.or(histogram_error) {
+            Some(error) => Err(error),
+            None => Ok(true),
+        }
+    }
+
+    // the pipelines are recreated by the next update_pipelines if either shader was reloaded
+    pub fn reload(&mut self, reloaded_shaders: &[Handle<Shader>]) {
+        if [&self.shader, &self.histogram_shader]
+            .into_iter()
+            .flatten()
+            .any(|handle| reloaded_shaders.contains(handle))
+        {
+            self.built = false;
+        }
+    }
+
+    fn create_bind_groups(
+        wgpu: &WgpuContext,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        histogram_bind_group_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        histogram_uniform_buffer: &wgpu::Buffer,
+        histogram_buffer: &wgpu::Buffer,
+        exposure_buffer: &wgpu::Buffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let bind_group = wgpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tone_mapping_bind_group"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&wgpu.hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let histogram_bind_group = wgpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("luminance_histogram_bind_group"),
+            layout: histogram_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&wgpu.hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        (bind_group, histogram_bind_group)
+    }
+
+    // the hdr texture is recreated when the context is resized so has to be bound again
+    pub fn resize(&mut self, wgpu: &WgpuContext) {
+        (self.bind_group, self.histogram_bind_group) = Self::create_bind_groups(
+            wgpu,
+            &self.bind_group_layout,
+            &self.histogram_bind_group_layout,
+            &self.uniform_buffer,
+            &self.histogram_uniform_buffer,
+            &self.histogram_buffer,
+            &self.exposure_buffer,
+        );
+    }
+
+    pub fn settings(&self) -> &ToneMappingSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: ToneMappingSettings) {
+        self.settings = settings;
+    }
+
+    pub fn update(&self, wgpu: &WgpuContext, delta: instant::Duration) {
+        let uniform = ToneMappingUniform {
+            curve: self.settings.operator.index(),
+            auto_exposure: self.settings.auto_exposure as u32,
+            manual_exposure: self.settings.exposure.exp2(),
+            gamma: if self.output_is_srgb {
+                1.0
+            } else {
+                self.settings.gamma
+            },
+        };
+
+        let histogram_uniform = HistogramUniform {
+            min_log_luminance: self.settings.min_log_luminance,
+            log_luminance_range: (self.settings.max_log_luminance
+                - self.settings.min_log_luminance)
+                .max(f32::EPSILON),
+            // framerate independent exponential smoothing
+            adaptation: 1.0 - (-delta.as_secs_f32() * self.settings.adaptation_speed).exp(),
+            compensation: self.settings.exposure_compensation.exp2(),
+            pixel_count: wgpu.config.width * wgpu.config.height,
+            _padding: [0; 3],
+        };
+
+        wgpu.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        wgpu.queue.write_buffer(
+            &self.histogram_uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[histogram_uniform]),
+        );
+    }
+
+    // measures the hdr texture if auto exposure is on, then tone maps it into output
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        wgpu: &WgpuContext,
+        output: &wgpu::TextureView,
+    ) {
+        let histogram_pipelines = self
+            .histogram_pipelines
+            .as_ref()
+            .filter(|_| self.settings.auto_exposure);
+
+        if let Some((build_histogram_pipeline, average_histogram_pipeline)) = histogram_pipelines {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("luminance_histogram_pass"),
+            });
+
+            compute_pass.set_bind_group(0, &self.histogram_bind_group, &[]);
+
+            compute_pass.set_pipeline(build_histogram_pipeline);
+            compute_pass.dispatch_workgroups(
+                wgpu.config.width.div_ceil(HISTOGRAM_TILE_SIZE),
+                wgpu.config.height.div_ceil(HISTOGRAM_TILE_SIZE),
+                1,
+            );
+
+            compute_pass.set_pipeline(average_histogram_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("tone_mapping_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        if let Some(pipeline) = &self.pipeline {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &self.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,552 @@
+// loads assets through an AssetServer on a headless device and checks they are shared, reported and freed
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use color_eyre::Result;
+
+use shooter_game::common::asset::{AssetServer, LoadState};
+use shooter_game::common::context::HeadlessRenderingContext;
+use shooter_game::common::hot_reload::FileWatcher;
+use shooter_game::common::lod::{self, LodMetric};
+use shooter_game::common::model::Model;
+use shooter_game::common::pipeline::{self, ShaderDefines};
+use shooter_game::common::texture::Texture;
+
+fn asset_path(path: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// an empty directory of its own for each test that writes files
+fn temp_dir(name: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("shooter_game_{name}"));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.canonicalize()?)
+}
+
+fn context() -> Result<HeadlessRenderingContext> {
+    pollster::block_on(HeadlessRenderingContext::new(
+        winit::dpi::PhysicalSize::new(1, 1),
+        1,
+        true,
+    ))
+}
+
+#[test]
+fn same_file_is_loaded_once() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+
+    let texture =
+        asset_server.load_texture(&asset_path("assets/default.png"), false, &context.wgpu);
+    // a different path to the same file
+    let same_texture = asset_server.load_texture(
+        &asset_path("assets/../assets/default.png"),
+        false,
+        &context.wgpu,
+    );
+
+    assert_eq!(texture, same_texture);
+    assert_eq!(asset_server.load_state(&texture), LoadState::Loaded);
+    assert_eq!(asset_server.assets::<Texture>().len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn normal_map_of_the_same_file_is_its_own_texture() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let dir = temp_dir("normal_map_reload")?;
+    let path = dir.join("texture.png");
+    std::fs::copy(asset_path("assets/default.png"), &path)?;
+
+    let color = asset_server.load_texture(&path.to_string_lossy(), false, &context.wgpu);
+    let normal_map = asset_server.load_texture(&path.to_string_lossy(), true, &context.wgpu);
+    assert_ne!(color, normal_map);
+
+    let format = |asset_server: &AssetServer, handle| {
+        asset_server
+            .get(handle)
+            .map(|texture: Rc<Texture>| texture.texture.format())
+    };
+    let formats = [
+        Some(wgpu::TextureFormat::Rgba8UnormSrgb),
+        Some(wgpu::TextureFormat::Rgba8Unorm),
+    ];
+    assert_eq!(
+        [&color, &normal_map].map(|handle| format(&asset_server, handle)),
+        formats
+    );
+
+    // each is loaded again the way it was first loaded
+    let reloaded = asset_server.reload(&[path], &material_bind_group_layout, &context.wgpu);
+    assert_eq!(reloaded.textures.len(), 2);
+    assert!(reloaded.textures.contains(&color) && reloaded.textures.contains(&normal_map));
+    assert_eq!(
+        [&color, &normal_map].map(|handle| format(&asset_server, handle)),
+        formats
+    );
+
+    Ok(())
+}
+
+// the model and its lods are kept in the asset server, shared with anything else loading them and reloaded with the file
+#[test]
+fn lod_group_is_loaded_through_the_asset_server() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    // the materials are only colors so no texture has to be found
+    let dir = temp_dir("lod_group_reload")?;
+    let path = dir.join("colored_cubes.obj");
+    std::fs::copy(asset_path("tests/obj/colored_cubes.obj"), &path)?;
+    std::fs::copy(
+        asset_path("tests/obj/colored_cubes.mtl"),
+        dir.join("colored_cubes.mtl"),
+    )?;
+
+    let lod_group = lod::load_lod_group(
+        &path.to_string_lossy(),
+        LodMetric::Distance,
+        &[10.0],
+        &[0.5],
+        &mut asset_server,
+        &material_bind_group_layout,
+        &context.wgpu,
+    )?;
+    assert_eq!(lod_group.lod_metric, LodMetric::Distance);
+    assert_eq!(lod_group.lods.len(), 1);
+    assert_eq!(lod_group.lods[0].threshold, 10.0);
+
+    let model = asset_server.load_model(
+        &path.to_string_lossy(),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    assert_eq!(lod_group.handle.as_ref(), Some(&model));
+    assert_eq!(asset_server.assets::<Model>().len(), 2);
+
+    let reloaded = asset_server.reload(
+        std::slice::from_ref(&path),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    assert_eq!(reloaded.models.len(), 2);
+    assert!(reloaded.models.contains(&model));
+    assert!(reloaded
+        .models
+        .contains(lod_group.lods[0].handle.as_ref().unwrap()));
+
+    // each generated lod needs a threshold
+    assert!(lod::load_lod_group(
+        &path.to_string_lossy(),
+        LodMetric::ScreenSize,
+        &[0.5, 0.25],
+        &[0.5],
+        &mut asset_server,
+        &material_bind_group_layout,
+        &context.wgpu,
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn missing_file_fails_without_panicking() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let model = asset_server.load_model(
+        &asset_path("assets/missing.obj"),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    let shader = asset_server.load_shader(
+        &asset_path("assets/missing.wgsl"),
+        &ShaderDefines::new(),
+        &context.wgpu.device,
+    );
+
+    assert!(matches!(
+        asset_server.load_state(&model),
+        LoadState::Failed(_)
+    ));
+    assert!(asset_server.get(&model).is_none());
+    assert!(matches!(
+        asset_server.load_state(&shader),
+        LoadState::Failed(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn invalid_shader_fails_without_panicking() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+
+    let shader = asset_server.load_shader(
+        &asset_path("tests/obj/quad.obj"),
+        &ShaderDefines::new(),
+        &context.wgpu.device,
+    );
+
+    assert!(matches!(
+        asset_server.load_state(&shader),
+        LoadState::Failed(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn unused_assets_are_unloaded() -> Result<()> {
+    // texture paths in the mtl file are relative to the assets directory, same as the binaries
+    std::env::set_current_dir(asset_path("assets"))?;
+
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let model = asset_server.load_model(
+        &asset_path("assets/cube.obj"),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    let shader = asset_server.load_shader(
+        &asset_path("assets/shader.wgsl"),
+        &ShaderDefines::new(),
+        &context.wgpu.device,
+    );
+    assert_eq!(asset_server.load_state(&shader), LoadState::Loaded);
+
+    // held by the model's materials
+    assert_eq!(asset_server.unload_unused(), 0);
+    let texture_count = asset_server.assets::<Texture>().len();
+    assert!(texture_count > 0);
+
+    // the model outlives its handle while something else is still using it
+    let model_in_use = asset_server.get(&model);
+    drop(model);
+    assert_eq!(asset_server.unload_unused(), 0);
+
+    drop(model_in_use);
+    assert_eq!(asset_server.unload_unused(), 1 + texture_count);
+    assert!(asset_server.assets::<Model>().is_empty());
+    assert!(asset_server.assets::<Texture>().is_empty());
+
+    // the shader's handle is still alive
+    assert_eq!(asset_server.assets::<pipeline::Shader>().len(), 1);
+    drop(shader);
+    assert_eq!(asset_server.unload_unused(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn changed_shader_is_reloaded_and_broken_one_is_not() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let dir = temp_dir("shader_reload")?;
+    let path = dir.join("shader.wgsl");
+    std::fs::copy(asset_path("assets/shader.wgsl"), &path)?;
+    // included by shader.wgsl
+    std::fs::copy(asset_path("assets/mesh.wgsl"), dir.join("mesh.wgsl"))?;
+
+    let shader = asset_server.load_shader(
+        &path.to_string_lossy(),
+        &ShaderDefines::new(),
+        &context.wgpu.device,
+    );
+    let old_shader = asset_server.get(&shader).unwrap();
+
+    std::fs::write(&path, "not wgsl")?;
+    let reloaded = asset_server.reload(
+        std::slice::from_ref(&path),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    assert!(reloaded.is_empty());
+    assert!(Rc::ptr_eq(&asset_server.get(&shader).unwrap(), &old_shader));
+    assert!(matches!(
+        asset_server.load_state(&shader),
+        LoadState::ReloadFailed(_)
+    ));
+
+    std::fs::copy(asset_path("assets/shader.wgsl"), &path)?;
+    let reloaded = asset_server.reload(&[path], &material_bind_group_layout, &context.wgpu);
+    assert_eq!(reloaded.shaders, vec![shader.clone()]);
+    assert!(!Rc::ptr_eq(
+        &asset_server.get(&shader).unwrap(),
+        &old_shader
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn failed_shader_is_reloaded_when_an_include_is_fixed() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let dir = temp_dir("failed_include_reload")?;
+    std::fs::create_dir(dir.join("lighting"))?;
+    for file in ["main.wgsl", "common.wgsl"] {
+        std::fs::copy(asset_path(&format!("tests/shaders/{file}")), dir.join(file))?;
+    }
+
+    // the include is missing
+    let shader = asset_server.load_shader(
+        &dir.join("main.wgsl").to_string_lossy(),
+        &ShaderDefines::new(),
+        &context.wgpu.device,
+    );
+    assert!(matches!(
+        asset_server.load_state(&shader),
+        LoadState::Failed(_)
+    ));
+
+    // then it's there but broken
+    let lighting_path = dir.join("lighting/lighting.wgsl");
+    std::fs::write(
+        &lighting_path,
+        "fn light(normal: vec3<f32>) -> f32 { return 1u; }",
+    )?;
+    let reloaded = asset_server.reload(
+        std::slice::from_ref(&lighting_path),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    assert!(reloaded.is_empty());
+    assert!(matches!(
+        asset_server.load_state(&shader),
+        LoadState::Failed(error) if error.contains("lighting.wgsl")
+    ));
+
+    std::fs::copy(
+        asset_path("tests/shaders/lighting/lighting.wgsl"),
+        &lighting_path,
+    )?;
+    let reloaded =
+        asset_server.reload(&[lighting_path], &material_bind_group_layout, &context.wgpu);
+    assert_eq!(reloaded.shaders, vec![shader.clone()]);
+    assert_eq!(asset_server.load_state(&shader), LoadState::Loaded);
+
+    Ok(())
+}
+
+#[test]
+fn model_is_reloaded_when_its_mtl_file_changes() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let dir = temp_dir("mtl_reload")?;
+    for file in ["colored_cubes.obj", "colored_cubes.mtl"] {
+        std::fs::copy(asset_path(&format!("tests/obj/{file}")), dir.join(file))?;
+    }
+
+    let model = asset_server.load_model(
+        &dir.join("colored_cubes.obj").to_string_lossy(),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    let diffuse = |asset_server: &AssetServer| {
+        let model = asset_server.get(&model).unwrap();
+        let red = model
+            .materials
+            .iter()
+            .find(|material| material.name.as_str() == "Red")
+            .unwrap();
+        red.uniform.diffuse
+    };
+    assert_eq!(diffuse(&asset_server), [0.8, 0.1, 0.1]);
+
+    let mtl_path = dir.join("colored_cubes.mtl");
+    let mtl = std::fs::read_to_string(&mtl_path)?;
+    std::fs::write(&mtl_path, mtl.replace("Kd 0.8 0.1 0.1", "Kd 0.1 0.8 0.1"))?;
+
+    let reloaded = asset_server.reload(&[mtl_path], &material_bind_group_layout, &context.wgpu);
+    assert_eq!(reloaded.models, vec![model.clone()]);
+    assert_eq!(diffuse(&asset_server), [0.1, 0.8, 0.1]);
+
+    Ok(())
+}
+
+#[test]
+fn loading_again_keeps_the_reload_error() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let dir = temp_dir("load_after_failed_reload")?;
+    let path = dir.join("texture.png");
+    std::fs::copy(asset_path("assets/default.png"), &path)?;
+
+    let texture = asset_server.load_texture(&path.to_string_lossy(), false, &context.wgpu);
+    std::fs::write(&path, "not a png")?;
+    asset_server.reload(
+        std::slice::from_ref(&path),
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    assert!(matches!(
+        asset_server.load_state(&texture),
+        LoadState::ReloadFailed(_)
+    ));
+
+    let same_texture = asset_server.load_texture(&path.to_string_lossy(), false, &context.wgpu);
+    assert_eq!(same_texture, texture);
+    assert!(matches!(
+        asset_server.load_state(&same_texture),
+        LoadState::ReloadFailed(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn file_watcher_notices_changed_created_and_removed_files() -> Result<()> {
+    let dir = temp_dir("file_watcher")?;
+    let changed = dir.join("changed.txt");
+    let unchanged = dir.join("unchanged.txt");
+    std::fs::write(&changed, "before")?;
+    std::fs::write(&unchanged, "before")?;
+
+    let mut file_watcher = FileWatcher::new(&dir);
+
+    std::fs::create_dir(dir.join("nested"))?;
+    let created = dir.join("nested/created.txt");
+    std::fs::write(&created, "created")?;
+    // modified times aren't always precise to less than a second
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    std::fs::write(&changed, "after")?;
+
+    let mut changed_paths = file_watcher.poll();
+    changed_paths.sort();
+    assert_eq!(changed_paths, vec![changed, created]);
+
+    std::thread::sleep(std::time::Duration::from_millis(600));
+    assert!(file_watcher.poll().is_empty());
+
+    std::fs::remove_file(&unchanged)?;
+    std::thread::sleep(std::time::Duration::from_millis(600));
+    assert_eq!(file_watcher.poll(), vec![unchanged]);
+
+    Ok(())
+}
+
+// the old texture is kept but it's reported so the removal doesn't go unnoticed
+#[test]
+fn removed_file_fails_to_reload() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let dir = temp_dir("removed_file")?;
+    let path = dir.join("texture.png");
+    std::fs::copy(asset_path("assets/default.png"), &path)?;
+
+    let texture = asset_server.load_texture(&path.to_string_lossy(), false, &context.wgpu);
+    let old_texture = asset_server.get(&texture).unwrap();
+    let mut file_watcher = FileWatcher::new(&dir);
+
+    std::fs::remove_file(&path)?;
+    std::thread::sleep(std::time::Duration::from_millis(600));
+    let changed_paths = file_watcher.poll();
+    assert_eq!(changed_paths, vec![path]);
+
+    let reloaded = asset_server.reload(&changed_paths, &material_bind_group_layout, &context.wgpu);
+    assert!(reloaded.is_empty());
+    assert!(Rc::ptr_eq(
+        &asset_server.get(&texture).unwrap(),
+        &old_texture
+    ));
+    assert!(matches!(
+        asset_server.load_state(&texture),
+        LoadState::ReloadFailed(_)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn shader_permutations_are_compiled_once() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let path = asset_path("tests/shaders/main.wgsl");
+
+    let shader = asset_server.load_shader(&path, &ShaderDefines::new(), &context.wgpu.device);
+    let same_shader = asset_server.load_shader(&path, &ShaderDefines::new(), &context.wgpu.device);
+    let flipped = asset_server.load_shader(
+        &path,
+        &ShaderDefines::new().with("FLIP"),
+        &context.wgpu.device,
+    );
+    let same_flipped = asset_server.load_shader(
+        &path,
+        &ShaderDefines::new().with("FLIP"),
+        &context.wgpu.device,
+    );
+
+    assert_eq!(asset_server.load_state(&shader), LoadState::Loaded);
+    assert_eq!(asset_server.load_state(&flipped), LoadState::Loaded);
+    assert_eq!(shader, same_shader);
+    assert_eq!(flipped, same_flipped);
+    assert_ne!(shader, flipped);
+    assert_eq!(asset_server.assets::<pipeline::Shader>().len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn shader_is_reloaded_when_an_include_changes() -> Result<()> {
+    let context = context()?;
+    let mut asset_server = AssetServer::new();
+    let material_bind_group_layout =
+        pipeline::create_material_bind_group_layout(&context.wgpu.device);
+
+    let dir = temp_dir("include_reload")?;
+    std::fs::create_dir(dir.join("lighting"))?;
+    for file in ["main.wgsl", "common.wgsl", "lighting/lighting.wgsl"] {
+        std::fs::copy(asset_path(&format!("tests/shaders/{file}")), dir.join(file))?;
+    }
+
+    let shader = asset_server.load_shader(
+        &dir.join("main.wgsl").to_string_lossy(),
+        &ShaderDefines::new(),
+        &context.wgpu.device,
+    );
+    assert_eq!(asset_server.load_state(&shader), LoadState::Loaded);
+
+    let reloaded = asset_server.reload(
+        &[dir.join("lighting/lighting.wgsl")],
+        &material_bind_group_layout,
+        &context.wgpu,
+    );
+    assert_eq!(reloaded.shaders, vec![shader]);
+
+    Ok(())
+}
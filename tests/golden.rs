@@ -0,0 +1,649 @@
+// renders fixed scenes offscreen and compares them against the reference images in tests/golden
+// run with UPDATE_GOLDEN=1 to overwrite the references after an intentional visual change
+
+use std::path::{Path, PathBuf};
+
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use wgpu::util::DeviceExt;
+
+use shooter_game::common::context::HeadlessRenderingContext;
+use shooter_game::common::model::DrawModels;
+use shooter_game::common::*;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+// max difference allowed in any channel before a pixel counts as mismatched
+const CHANNEL_TOLERANCE: u8 = 8;
+// fraction of pixels allowed to mismatch, rasterizers disagree slightly on triangle edges
+const MAX_MISMATCHED_FRACTION: f32 = 0.002;
+
+fn manifest_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn camera(position: (f32, f32, f32), target: (f32, f32, f32)) -> camera::Camera {
+    let position = cg::Point3::from(position);
+
+    camera::Camera {
+        position,
+        direction: cg::Point3::from(target) - position,
+        pitch: 0.0,
+        yaw: 0.0,
+        up: cg::Vector3::unit_y(),
+        aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        fov: 45.0,
+        z_near: 0.1,
+        z_far: 100.0,
+    }
+}
+
+fn lights() -> Vec<light::Light> {
+    vec![
+        light::Light::Directional {
+            direction: cg::Vector3::new(-0.4, -1.0, -0.6),
+            color: cg::Vector3::new(1.0, 1.0, 1.0),
+            intensity: 0.8,
+        },
+        light::Light::Point {
+            position: cg::Point3::new(2.0, 3.0, 2.0),
+            color: cg::Vector3::new(1.0, 0.5, 0.2),
+            intensity: 10.0,
+            range: 10.0,
+        },
+    ]
+}
+
+// fixed exposure so the references don't depend on how the auto exposure adapts
+fn manual_exposure() -> tone_mapping::ToneMappingSettings {
+    tone_mapping::ToneMappingSettings {
+        auto_exposure: false,
+        ..Default::default()
+    }
+}
+
+struct SceneSettings {
+    sample_count: u32,
+    tone_mapping_settings: tone_mapping::ToneMappingSettings,
+    // runs every default post processing effect, even the ones that are off by default
+    all_post_processing: bool,
+    // given to the last model
+    instances: Option<Vec<instance::ModelInstance>>,
+    gpu_culling: bool,
+    // (triangle ratio, screen size threshold) of each lod simplified from each model, none loads the models without lods
+    generated_lods: Vec<(f32, f32)>,
+    debug_view_settings: debug_view::DebugViewSettings,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        Self {
+            sample_count: 1,
+            tone_mapping_settings: manual_exposure(),
+            all_post_processing: false,
+            instances: None,
+            gpu_culling: false,
+            generated_lods: vec![],
+            debug_view_settings: debug_view::DebugViewSettings::default(),
+        }
+    }
+}
+
+fn render_scene(model_paths: &[&str], camera: &camera::Camera) -> Result<image::RgbaImage> {
+    render_scene_with(model_paths, camera, SceneSettings::default())
+}
+
+// a grid of cubes that reaches past the edges of the view so some of them are culled
+fn cube_grid() -> Vec<instance::ModelInstance> {
+    (-6..=6)
+        .flat_map(|x| {
+            (-6..=6).map(move |z| instance::ModelInstance {
+                position: cg::Vector3::new(x as f32 * 3.0, 0.0, z as f32 * 3.0),
+                rotation: cg::Quaternion::from_angle_y(cg::Deg((x * z) as f32 * 10.0)),
+            })
+        })
+        .collect()
+}
+
+fn render_scene_with(
+    model_paths: &[&str],
+    camera: &camera::Camera,
+    settings: SceneSettings,
+) -> Result<image::RgbaImage> {
+    let SceneSettings {
+        sample_count,
+        tone_mapping_settings,
+        all_post_processing,
+        instances,
+        gpu_culling,
+        generated_lods,
+        debug_view_settings,
+    } = settings;
+
+    // model and texture paths are relative to the assets directory, same as the binaries
+    std::env::set_current_dir(manifest_path("assets"))?;
+
+    let mut context = pollster::block_on(HeadlessRenderingContext::new(
+        winit::dpi::PhysicalSize::new(WIDTH, HEIGHT),
+        sample_count,
+        true,
+    ))?;
+
+    // allocates its ping pong textures from the context
+    let mut post_process = post_process::PostProcessStack::new(&mut context.wgpu)?;
+
+    let device = &context.wgpu.device;
+
+    let material_bind_group_layout = pipeline::create_material_bind_group_layout(device);
+    let camera_bind_group_layout = pipeline::create_camera_bind_group_layout(device);
+    let lights_bind_group_layout = pipeline::create_lights_bind_group_layout(device);
+    let shadow_bind_group_layout = pipeline::create_shadow_bind_group_layout(device);
+
+    let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("camera_buffer"),
+        contents: bytemuck::cast_slice(&[camera::CameraUniform::from(camera)]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+
+    let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("camera_bind_group"),
+        layout: &camera_bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: camera_buffer.as_entire_binding(),
+        }],
+    });
+
+    let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("render_pipeline_layout"),
+        bind_group_layouts: &[
+            &material_bind_group_layout,
+            &camera_bind_group_layout,
+            &lights_bind_group_layout,
+            &shadow_bind_group_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+
+    // loaded the same way as the binaries do, through the preprocessor and validation
+    let mut asset_server = asset::AssetServer::new();
+    let shader_handle =
+        asset_server.load_shader("shader.wgsl", &pipeline::ShaderDefines::new(), device);
+    let shader = asset_server
+        .get(&shader_handle)
+        .ok_or_else(|| eyre!("{:?}", asset_server.load_state(&shader_handle)))?;
+
+    let render_pipeline = pipeline::scene_pipeline_builder(context.wgpu.sample_count).build(
+        device,
+        &render_pipeline_layout,
+        &shader.module,
+        Some("instanced_render_pipeline"),
+    );
+    let mut render_data = render::RenderData::new(device, &lights_bind_group_layout);
+    render_data.lights = lights();
+    render_data.update_lights_buffer(&context.wgpu);
+
+    for path in model_paths {
+        let model = if generated_lods.is_empty() {
+            model::Model::load(
+                path.to_string(),
+                &mut asset_server,
+                &material_bind_group_layout,
+                &context.wgpu,
+            )?
+            .into()
+        } else {
+            let (ratios, thresholds): (Vec<f32>, Vec<f32>) = generated_lods.iter().copied().unzip();
+
+            lod::load_lod_group(
+                path,
+                lod::LodMetric::ScreenSize,
+                &thresholds,
+                &ratios,
+                &mut asset_server,
+                &material_bind_group_layout,
+                &context.wgpu,
+            )?
+        };
+        render_data.models.push(model);
+    }
+
+    if let Some(model) = render_data.models.last_mut() {
+        model.instances = instances;
+    }
+
+    let gpu_culling = if gpu_culling {
+        let mut gpu_culling = culling::GpuCulling::new(&context.wgpu)?;
+        gpu_culling.update_pipelines(&mut asset_server, device)?;
+        Some(gpu_culling)
+    } else {
+        None
+    };
+
+    let mut shadow_map = shadow::ShadowMap::new(device, shadow::ShadowSettings::default());
+    shadow_map.update_pipeline(&mut asset_server, device)?;
+    shadow_map.update(&context.wgpu.queue, &render_data.lights, camera);
+
+    // anything culled by mistake would be missing from the image
+    let frustum = camera.frustum();
+    let shadow_sweep = shadow_map.caster_sweep(&render_data.lights);
+    if let Some(gpu_culling) = &gpu_culling {
+        gpu_culling.update(&context.wgpu, &mut render_data, &frustum, shadow_sweep);
+    }
+    render_data.cull(&frustum, shadow_sweep);
+    render_data.select_lods(camera, &lod::LodSettings::default());
+    render_data.update_instance_buffers(&context.wgpu);
+
+    let mut tone_mapping = tone_mapping::ToneMapping::new(
+        &context.wgpu,
+        HeadlessRenderingContext::COLOR_FORMAT,
+        tone_mapping_settings,
+    );
+    tone_mapping.update_pipelines(&mut asset_server, device)?;
+    // a long frame so auto exposure settles straight away
+    tone_mapping.update(&context.wgpu, std::time::Duration::from_secs(60));
+
+    if all_post_processing {
+        post_process.add_default_effects(&context.wgpu)?;
+
+        let mut settings = post_process.settings().clone();
+        for effect in settings.effects.iter_mut() {
+            effect.enabled = true;
+        }
+        post_process.set_settings(settings);
+    }
+    post_process.update(&context.wgpu);
+
+    let mut debug_views = debug_view::DebugViews::new(&context.wgpu);
+    debug_views.set_settings(debug_view_settings);
+    debug_views.update(
+        &mut asset_server,
+        &mut pipeline::PipelineCache::new(),
+        &context.wgpu,
+    )?;
+
+    let wgpu = &context.wgpu;
+    let output = &context.color_texture.view;
+    let post_process_target = post_process.target_resource();
+
+    // added out of order on purpose, the graph has to sort them by what they read and write
+    let mut graph = render::RenderGraph::new();
+
+    graph.add_pass(
+        "tone_mapping",
+        &[render::HDR],
+        &[post_process_target],
+        |encoder| {
+            tone_mapping.render(encoder, wgpu, post_process.target(wgpu, output));
+            Ok(())
+        },
+    );
+
+    graph.add_pass(
+        "main",
+        &[render::SHADOW_MAP, render::INSTANCES],
+        &[render::HDR, render::DEPTH],
+        |encoder| {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("render_pass"),
+                color_attachments: &[Some(wgpu.color_attachment(
+                    &wgpu.hdr_texture.view,
+                    wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(if debug_views.replaces_scene() {
+                            wgpu::Color::BLACK
+                        } else {
+                            wgpu::Color {
+                                r: 0.1,
+                                g: 0.2,
+                                b: 0.3,
+                                a: 1.0,
+                            }
+                        }),
+                        store: true,
+                    },
+                ))],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &wgpu.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            if !debug_views.replaces_scene() {
+                render_pass.set_pipeline(&render_pipeline);
+                render_pass.set_bind_group(1, &camera_bind_group, &[]);
+                render_pass.set_bind_group(3, shadow_map.bind_group(), &[]);
+                render_pass.draw_models(&render_data);
+            }
+            debug_views.draw(&mut render_pass, &render_data, &camera_bind_group);
+
+            Ok(())
+        },
+    );
+
+    graph.add_pass(
+        "post_process",
+        &[post_process_target],
+        &[render::SURFACE],
+        |encoder| {
+            post_process.render(encoder, wgpu, output);
+            Ok(())
+        },
+    );
+
+    graph.add_pass(
+        "shadow",
+        &[render::INSTANCES],
+        &[render::SHADOW_MAP],
+        |encoder| {
+            shadow_map.render(encoder, &render_data);
+            Ok(())
+        },
+    );
+
+    if let Some(gpu_culling) = &gpu_culling {
+        graph.add_pass("culling", &[], &[render::INSTANCES], |encoder| {
+            gpu_culling.cull(encoder, &render_data);
+            Ok(())
+        });
+    }
+
+    let command_buffer = graph.execute(wgpu)?;
+    wgpu.queue.submit(std::iter::once(command_buffer));
+
+    context.read_frame()
+}
+
+// red where the images differ beyond the tolerance, dimmed actual image everywhere else
+fn diff_image(actual: &image::RgbaImage, expected: &image::RgbaImage) -> (image::RgbaImage, u32) {
+    let mut mismatched = 0;
+
+    let diff = image::RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let actual_pixel = actual.get_pixel(x, y);
+        let expected_pixel = expected.get_pixel(x, y);
+
+        let matches = actual_pixel
+            .0
+            .iter()
+            .zip(expected_pixel.0.iter())
+            .all(|(a, e)| a.abs_diff(*e) <= CHANNEL_TOLERANCE);
+
+        if matches {
+            let [r, g, b, _] = actual_pixel.0;
+            image::Rgba([r / 4, g / 4, b / 4, 255])
+        } else {
+            mismatched += 1;
+            image::Rgba([255, 0, 0, 255])
+        }
+    });
+
+    (diff, mismatched)
+}
+
+fn assert_matches_golden(name: &str, actual: &image::RgbaImage) -> Result<()> {
+    let golden_path = manifest_path(&format!("tests/golden/{name}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&golden_path)?;
+        return Ok(());
+    }
+
+    let expected = image::open(&golden_path)
+        .map_err(|err| {
+            eyre!(
+                "Could not open reference image \"{}\" ({err}), run with UPDATE_GOLDEN=1 to create it",
+                golden_path.display()
+            )
+        })?
+        .to_rgba8();
+
+    let output_directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    let actual_path = output_directory.join(format!("{name}-actual.png"));
+    let diff_path = output_directory.join(format!("{name}-diff.png"));
+
+    if actual.dimensions() != expected.dimensions() {
+        std::fs::create_dir_all(&output_directory)?;
+        actual.save(&actual_path)?;
+
+        return Err(eyre!(
+            "Rendered \"{name}\" at {:?} but reference is {:?}, actual image written to \"{}\"",
+            actual.dimensions(),
+            expected.dimensions(),
+            actual_path.display()
+        ));
+    }
+
+    let (diff, mismatched) = diff_image(actual, &expected);
+    let max_mismatched = (MAX_MISMATCHED_FRACTION * (WIDTH * HEIGHT) as f32) as u32;
+
+    if mismatched > max_mismatched {
+        std::fs::create_dir_all(&output_directory)?;
+        actual.save(&actual_path)?;
+        diff.save(&diff_path)?;
+
+        return Err(eyre!(
+            "Rendered \"{name}\" differs from reference in {mismatched} pixels (max {max_mismatched}), see \"{}\" and \"{}\"",
+            actual_path.display(),
+            diff_path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cube() -> Result<()> {
+    let image = render_scene(&["cube.obj"], &camera((3.0, 2.5, 4.0), (0.0, 0.0, 0.0)))?;
+    assert_matches_golden("cube", &image)
+}
+
+#[test]
+fn cube_msaa() -> Result<()> {
+    let image = render_scene_with(
+        &["cube.obj"],
+        &camera((3.0, 2.5, 4.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            sample_count: 4,
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("cube_msaa", &image)
+}
+
+#[test]
+fn map_auto_exposure() -> Result<()> {
+    let image = render_scene_with(
+        &["map.obj"],
+        &camera((9.0, 7.0, 9.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            tone_mapping_settings: tone_mapping::ToneMappingSettings::default(),
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("map_auto_exposure", &image)
+}
+
+#[test]
+fn map_post_process() -> Result<()> {
+    let image = render_scene_with(
+        &["map.obj"],
+        &camera((9.0, 7.0, 9.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            all_post_processing: true,
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("map_post_process", &image)
+}
+
+#[test]
+fn map() -> Result<()> {
+    let image = render_scene(&["map.obj"], &camera((9.0, 7.0, 9.0), (0.0, 0.0, 0.0)))?;
+    assert_matches_golden("map", &image)
+}
+
+#[test]
+fn map_with_cube() -> Result<()> {
+    let image = render_scene(
+        &["map.obj", "cube.obj"],
+        &camera((5.0, 3.0, 6.0), (0.0, 0.0, 0.0)),
+    )?;
+    assert_matches_golden("map_with_cube", &image)
+}
+
+#[test]
+fn gltf_scene() -> Result<()> {
+    let image = render_scene(
+        &["gltf-scene.glb"],
+        &camera((0.0, 3.0, 5.0), (0.0, 0.5, 0.0)),
+    )?;
+    assert_matches_golden("gltf_scene", &image)
+}
+
+// the checker is on the second uv set and repeats with nearest filtering, the right quad is mirrored and still faces the camera
+#[test]
+fn gltf_texture_coords_and_mirroring() -> Result<()> {
+    let path = manifest_path("tests/gltf/mirrored_quads.gltf");
+    let image = render_scene(
+        &[&path.to_string_lossy()],
+        &camera((0.0, 0.0, 2.5), (0.0, 0.0, 0.0)),
+    )?;
+    assert_matches_golden("gltf_texture_coords_and_mirroring", &image)
+}
+
+#[test]
+fn cube_instances() -> Result<()> {
+    let image = render_scene_with(
+        &["cube.obj"],
+        &camera((8.0, 6.0, 10.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            instances: Some(cube_grid()),
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("cube_instances", &image)
+}
+
+// compared against the same image as the cpu culled instances
+#[test]
+fn cube_instances_gpu_culling() -> Result<()> {
+    let image = render_scene_with(
+        &["cube.obj"],
+        &camera((8.0, 6.0, 10.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            instances: Some(cube_grid()),
+            gpu_culling: true,
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("cube_instances", &image)
+}
+
+// the far cubes are drawn with lods simplified from the cube, some of them crossfading
+#[test]
+fn cube_lods() -> Result<()> {
+    let image = render_scene_with(
+        &["cube.obj"],
+        &camera((8.0, 6.0, 10.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            instances: Some(cube_grid()),
+            generated_lods: vec![(0.25, 0.5), (0.05, 0.25)],
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("cube_lods", &image)
+}
+
+// normals and texture coordinates are generated, the default texture is box projected onto it
+#[test]
+fn obj_positions_only() -> Result<()> {
+    let path = manifest_path("tests/obj/cube_positions_only.obj");
+    let image = render_scene(
+        &[&path.to_string_lossy()],
+        &camera((3.0, 2.5, 4.0), (0.0, 0.0, 0.0)),
+    )?;
+    assert_matches_golden("obj_positions_only", &image)
+}
+
+#[test]
+fn cube_wireframe() -> Result<()> {
+    let image = render_scene_with(
+        &["cube.obj"],
+        &camera((3.0, 2.5, 4.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            debug_view_settings: debug_view::DebugViewSettings {
+                view: debug_view::DebugView::Lit,
+                wireframe: true,
+            },
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("cube_wireframe", &image)
+}
+
+#[test]
+fn map_normals() -> Result<()> {
+    let image = render_scene_with(
+        &["map.obj"],
+        &camera((9.0, 7.0, 9.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            debug_view_settings: debug_view::DebugViewSettings {
+                view: debug_view::DebugView::Normals,
+                wireframe: false,
+            },
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("map_normals", &image)
+}
+
+// each mesh of both models gets its own color, including the instanced cubes
+#[test]
+fn map_with_cube_mesh_colors() -> Result<()> {
+    let image = render_scene_with(
+        &["map.obj", "cube.obj"],
+        &camera((5.0, 3.0, 6.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            debug_view_settings: debug_view::DebugViewSettings {
+                view: debug_view::DebugView::MeshColors,
+                wireframe: false,
+            },
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("map_with_cube_mesh_colors", &image)
+}
+
+#[test]
+fn cube_instances_overdraw() -> Result<()> {
+    let image = render_scene_with(
+        &["cube.obj"],
+        &camera((8.0, 6.0, 10.0), (0.0, 0.0, 0.0)),
+        SceneSettings {
+            instances: Some(cube_grid()),
+            debug_view_settings: debug_view::DebugViewSettings {
+                view: debug_view::DebugView::Overdraw,
+                wireframe: false,
+            },
+            ..Default::default()
+        },
+    )?;
+    assert_matches_golden("cube_instances_overdraw", &image)
+}
+
+// the materials only have mtl colors, the blue one is dissolved so the red one shows through its dithering
+#[test]
+fn obj_material_colors() -> Result<()> {
+    let path = manifest_path("tests/obj/colored_cubes.obj");
+    let image = render_scene(
+        &[&path.to_string_lossy()],
+        &camera((2.5, 2.0, 4.0), (0.3, 0.0, 0.5)),
+    )?;
+    assert_matches_golden("obj_material_colors", &image)
+}
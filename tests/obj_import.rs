@@ -0,0 +1,220 @@
+// loads the obj files in tests/obj and checks what the importer fills in for any missing attributes
+
+use std::path::Path;
+
+use cg::prelude::*;
+use cgmath as cg;
+use color_eyre::Result;
+
+use shooter_game::common::import::{
+    self, ImportSettings, ImportWarning, NormalGeneration, TextureCoordGeneration,
+};
+use shooter_game::common::model::MeshData;
+
+const EPSILON: f32 = 1e-5;
+
+fn load(name: &str, settings: ImportSettings) -> Result<(MeshData, Vec<ImportWarning>)> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/obj")
+        .join(name);
+
+    let mut obj_file = import::load_obj(&path.to_string_lossy(), &settings)?;
+    assert_eq!(obj_file.meshes.len(), 1);
+
+    Ok((obj_file.meshes.remove(0), obj_file.warnings))
+}
+
+// each triangle's normal from its positions, the winding is counter clockwise
+fn face_normals(mesh: &MeshData) -> Vec<cg::Vector3<f32>> {
+    mesh.indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let [a, b, c] =
+                [0, 1, 2].map(|i| cg::Vector3::from(mesh.vertices[triangle[i] as usize].position));
+            (b - a).cross(c - a).normalize()
+        })
+        .collect()
+}
+
+fn assert_close(a: [f32; 3], b: cg::Vector3<f32>) {
+    assert!(
+        (cg::Vector3::from(a) - b).magnitude() < EPSILON,
+        "{:?} != {:?}",
+        a,
+        b
+    );
+}
+
+#[test]
+fn complete_mesh_has_no_warnings() -> Result<()> {
+    let (mesh, warnings) = load("quad.obj", ImportSettings::default())?;
+
+    assert!(warnings.is_empty());
+    assert_eq!(mesh.vertices.len(), 4);
+    assert_eq!(mesh.indices.len(), 6);
+    assert_eq!(mesh.vertices[2].texture_coords, [1.0, 1.0]);
+
+    Ok(())
+}
+
+#[test]
+fn missing_normals_are_smoothed() -> Result<()> {
+    let (mesh, warnings) = load("quad_no_normals.obj", ImportSettings::default())?;
+
+    assert_eq!(
+        warnings,
+        vec![ImportWarning::MissingNormals {
+            mesh: "Quad".to_string(),
+            generated: NormalGeneration::Smooth,
+        }]
+    );
+    assert_eq!(mesh.vertices.len(), 4);
+    for vertex in mesh.vertices.iter() {
+        assert_close(vertex.normal, cg::Vector3::unit_z());
+    }
+    // the texture coordinates from the file are kept
+    assert_eq!(mesh.vertices[2].texture_coords, [1.0, 1.0]);
+
+    Ok(())
+}
+
+#[test]
+fn smooth_normals_are_shared_between_faces() -> Result<()> {
+    let (mesh, warnings) = load(
+        "cube_positions_only.obj",
+        ImportSettings {
+            normals: NormalGeneration::Smooth,
+            texture_coords: TextureCoordGeneration::Zero,
+        },
+    )?;
+
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(mesh.vertices.len(), 8);
+    assert_eq!(mesh.indices.len(), 36);
+    // every corner of the cube points out of it, between the faces it is on
+    for vertex in mesh.vertices.iter() {
+        let normal = cg::Vector3::from(vertex.normal);
+        assert!((normal.magnitude() - 1.0).abs() < EPSILON);
+        for (axis, position) in vertex.position.into_iter().enumerate() {
+            assert!(normal[axis] * position > 0.0);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn flat_normals_split_the_vertices() -> Result<()> {
+    let (mesh, _) = load(
+        "cube_positions_only.obj",
+        ImportSettings {
+            normals: NormalGeneration::Flat,
+            texture_coords: TextureCoordGeneration::Zero,
+        },
+    )?;
+
+    // each face gets its own 4 corners
+    assert_eq!(mesh.vertices.len(), 24);
+    assert_eq!(mesh.indices.len(), 36);
+    for (triangle, face_normal) in mesh.indices.chunks_exact(3).zip(face_normals(&mesh)) {
+        for &index in triangle {
+            assert_close(mesh.vertices[index as usize].normal, face_normal);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn missing_texture_coords_are_projected() -> Result<()> {
+    let (mesh, warnings) = load("quad_no_texture_coords.obj", ImportSettings::default())?;
+
+    assert_eq!(
+        warnings,
+        vec![ImportWarning::MissingTextureCoords {
+            mesh: "Quad".to_string(),
+            generated: TextureCoordGeneration::BoxProjection,
+        }]
+    );
+    assert_eq!(mesh.vertices.len(), 4);
+    // facing z so projected onto xy, v is flipped so the texture is upright
+    for vertex in mesh.vertices.iter() {
+        let [x, y, _] = vertex.position;
+        assert_eq!(vertex.texture_coords, [x, -y]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn missing_texture_coords_can_be_zero() -> Result<()> {
+    let (mesh, warnings) = load(
+        "quad_no_texture_coords.obj",
+        ImportSettings {
+            texture_coords: TextureCoordGeneration::Zero,
+            ..Default::default()
+        },
+    )?;
+
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(mesh.vertices.len(), 4);
+    for vertex in mesh.vertices.iter() {
+        assert_eq!(vertex.texture_coords, [0.0, 0.0]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn positions_only_mesh_is_complete() -> Result<()> {
+    let (mesh, warnings) = load("cube_positions_only.obj", ImportSettings::default())?;
+
+    assert_eq!(
+        warnings,
+        vec![
+            ImportWarning::MissingNormals {
+                mesh: "Cube".to_string(),
+                generated: NormalGeneration::Smooth,
+            },
+            ImportWarning::MissingTextureCoords {
+                mesh: "Cube".to_string(),
+                generated: TextureCoordGeneration::BoxProjection,
+            },
+        ]
+    );
+    assert_eq!(mesh.indices.len(), 36);
+    // each face is projected onto the plane it faces
+    for (triangle, face_normal) in mesh.indices.chunks_exact(3).zip(face_normals(&mesh)) {
+        for &index in triangle {
+            let [x, y, z] = mesh.vertices[index as usize].position;
+            let expected = if face_normal.x.abs() > 0.5 {
+                [z, -y]
+            } else if face_normal.y.abs() > 0.5 {
+                [x, z]
+            } else {
+                [x, -y]
+            };
+            assert_eq!(mesh.vertices[index as usize].texture_coords, expected);
+        }
+    }
+    for vertex in mesh.vertices.iter() {
+        assert!((cg::Vector3::from(vertex.normal).magnitude() - 1.0).abs() < EPSILON);
+        assert!((cg::Vector3::from(vertex.tangent).magnitude() - 1.0).abs() < EPSILON);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn repeated_vertices_are_welded() -> Result<()> {
+    let (mesh, warnings) = load("quad_repeated_vertices.obj", ImportSettings::default())?;
+
+    assert!(warnings.is_empty());
+    // the two copies of the diagonal's ends are merged, same as quad.obj
+    assert_eq!(mesh.vertices.len(), 4);
+    assert_eq!(mesh.indices.len(), 6);
+    assert_eq!(mesh.indices[0], mesh.indices[3]);
+    assert_eq!(mesh.indices[2], mesh.indices[4]);
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+// checks optimizing a mesh only changes the order it is drawn in
+
+use shooter_game::common::model::ModelVertex;
+use shooter_game::common::optimize;
+
+const GRID_SIZE: u32 = 12;
+
+// a flat grid of quads with the triangles in a scrambled order, so there is something to reorder
+fn scrambled_grid() -> (Vec<ModelVertex>, Vec<u32>) {
+    let vertices = (0..GRID_SIZE * GRID_SIZE)
+        .map(|i| {
+            let [x, z] = [i % GRID_SIZE, i / GRID_SIZE].map(|coordinate| coordinate as f32);
+
+            ModelVertex {
+                position: [x, 0.0, z],
+                texture_coords: [x, z],
+                normal: [0.0, 1.0, 0.0],
+                tangent: [1.0, 0.0, 0.0],
+                bitangent: [0.0, 0.0, 1.0],
+            }
+        })
+        .collect::<Vec<ModelVertex>>();
+
+    let mut triangles = vec![];
+    for z in 0..GRID_SIZE - 1 {
+        for x in 0..GRID_SIZE - 1 {
+            let corner = z * GRID_SIZE + x;
+            triangles.push([corner, corner + GRID_SIZE, corner + 1]);
+            triangles.push([corner + 1, corner + GRID_SIZE, corner + GRID_SIZE + 1]);
+        }
+    }
+
+    // every 7th triangle, 7 doesn't divide the count so each one is visited once
+    let count = triangles.len();
+    assert_ne!(count % 7, 0);
+    let indices = (0..count)
+        .flat_map(|i| triangles[i * 7 % count])
+        .collect::<Vec<u32>>();
+
+    (vertices, indices)
+}
+
+// each triangle as its vertices, starting from the smallest so the same triangle compares equal however it was rotated
+fn triangle_set(vertices: &[ModelVertex], indices: &[u32]) -> Vec<[[u32; 14]; 3]> {
+    let mut triangles = indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let corners = [0, 1, 2].map(|i| bytemuck::cast(vertices[triangle[i] as usize]));
+            let first = (0..3).min_by_key(|&i| corners[i]).unwrap();
+
+            [0, 1, 2].map(|i| corners[(first + i) % 3])
+        })
+        .collect::<Vec<[[u32; 14]; 3]>>();
+    triangles.sort();
+
+    triangles
+}
+
+#[test]
+fn optimize_keeps_the_same_triangles() {
+    let (vertices, indices) = scrambled_grid();
+
+    let (optimized_vertices, optimized_indices, stats) = optimize::optimize(&vertices, &indices);
+
+    assert_eq!(
+        triangle_set(&optimized_vertices, &optimized_indices),
+        triangle_set(&vertices, &indices)
+    );
+    assert_eq!(stats.vertices_after, vertices.len());
+    assert!(stats.acmr_after < stats.acmr_before, "{stats:?}");
+}
+
+#[test]
+fn optimize_welds_duplicate_vertices() {
+    let (vertices, indices) = scrambled_grid();
+
+    // every triangle gets its own copies of its corners
+    let split_vertices = indices
+        .iter()
+        .map(|&index| vertices[index as usize])
+        .collect::<Vec<ModelVertex>>();
+    let split_indices = (0..indices.len() as u32).collect::<Vec<u32>>();
+
+    let (optimized_vertices, optimized_indices, stats) =
+        optimize::optimize(&split_vertices, &split_indices);
+
+    assert_eq!(stats.vertices_before, indices.len());
+    assert_eq!(optimized_vertices.len(), vertices.len());
+    assert_eq!(
+        triangle_set(&optimized_vertices, &optimized_indices),
+        triangle_set(&vertices, &indices)
+    );
+}
@@ -0,0 +1,68 @@
+// builds pipelines through a PipelineCache on a headless device and checks identical state is shared
+
+use color_eyre::Result;
+
+use shooter_game::common::context::HeadlessRenderingContext;
+use shooter_game::common::pipeline::{PipelineBuilder, PipelineCache, Shader, ShaderDefines};
+use shooter_game::common::texture::Texture;
+
+// position and normal, see tests/shaders/common.wgsl
+const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+    wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+const VERTEX_BUFFER: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+    array_stride: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+    step_mode: wgpu::VertexStepMode::Vertex,
+    attributes: &ATTRIBUTES,
+};
+
+fn builder() -> PipelineBuilder {
+    PipelineBuilder::new()
+        .vertex_buffers(&[VERTEX_BUFFER])
+        .color_target(Texture::HDR_FORMAT, None)
+        .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+}
+
+#[test]
+fn identical_state_shares_a_pipeline() -> Result<()> {
+    let context = pollster::block_on(HeadlessRenderingContext::new(
+        winit::dpi::PhysicalSize::new(1, 1),
+        1,
+        true,
+    ))?;
+    let device = &context.wgpu.device;
+
+    let shader = Shader::from_path("tests/shaders/main.wgsl", &ShaderDefines::new(), device)?;
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+
+    let mut pipeline_cache = PipelineCache::new();
+    let mut get = |builder: PipelineBuilder| {
+        pipeline_cache.get(&builder, device, &layout, &shader.module, "test_pipeline")
+    };
+
+    let opaque = get(builder())?;
+    let same_opaque = get(builder())?;
+    let transparent = get(PipelineBuilder::new()
+        .vertex_buffers(&[VERTEX_BUFFER])
+        .color_target(Texture::HDR_FORMAT, Some(wgpu::BlendState::ALPHA_BLENDING))
+        .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::Less))?;
+    let double_sided = get(builder().cull_mode(None))?;
+    let missing_entry_point = get(builder().entry_points("missing", Some("fs_main")));
+
+    assert!(std::rc::Rc::ptr_eq(&opaque, &same_opaque));
+    assert!(!std::rc::Rc::ptr_eq(&opaque, &transparent));
+    assert!(!std::rc::Rc::ptr_eq(&opaque, &double_sided));
+    // fails instead of panicking and isn't cached
+    assert!(missing_entry_point.is_err());
+    assert_eq!(pipeline_cache.len(), 3);
+
+    drop(double_sided);
+    assert_eq!(pipeline_cache.remove_unused(), 1);
+    assert_eq!(pipeline_cache.len(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+// runs render graphs of passes that only note down that they ran, and checks the order they ran in
+
+use std::cell::RefCell;
+
+use color_eyre::Result;
+
+use shooter_game::common::context::HeadlessRenderingContext;
+use shooter_game::common::render::{self, RenderGraph, Resource};
+
+const BLOOM: Resource = Resource::External("bloom");
+
+fn context() -> Result<HeadlessRenderingContext> {
+    pollster::block_on(HeadlessRenderingContext::new(
+        winit::dpi::PhysicalSize::new(1, 1),
+        1,
+        true,
+    ))
+}
+
+// (name, reads, writes) of each pass in the order they're added, the names of the passes in the order they ran
+fn run(
+    context: &HeadlessRenderingContext,
+    passes: &[(&'static str, &[Resource], &[Resource])],
+) -> (Result<()>, Vec<&'static str>) {
+    let ran = RefCell::new(vec![]);
+
+    let mut render_graph = RenderGraph::new();
+    for &(name, reads, writes) in passes {
+        let ran = &ran;
+        render_graph.add_pass(name, reads, writes, move |_| {
+            ran.borrow_mut().push(name);
+            Ok(())
+        });
+    }
+
+    let result = render_graph.execute(&context.wgpu).map(|_| ());
+
+    (result, ran.into_inner())
+}
+
+#[test]
+fn read_runs_after_write() -> Result<()> {
+    let context = context()?;
+
+    let (result, ran) = run(
+        &context,
+        &[
+            ("scene", &[], &[render::HDR]),
+            ("tone_mapping", &[render::HDR], &[render::SURFACE]),
+        ],
+    );
+
+    result?;
+    assert_eq!(ran, vec!["scene", "tone_mapping"]);
+
+    Ok(())
+}
+
+#[test]
+fn reader_added_before_writer_is_moved_after_it() -> Result<()> {
+    let context = context()?;
+
+    let (result, ran) = run(
+        &context,
+        &[
+            ("tone_mapping", &[render::HDR], &[render::SURFACE]),
+            ("bloom", &[BLOOM], &[render::HDR]),
+            ("scene", &[], &[BLOOM]),
+        ],
+    );
+
+    result?;
+    assert_eq!(ran, vec!["scene", "bloom", "tone_mapping"]);
+
+    Ok(())
+}
+
+#[test]
+fn writers_of_the_same_resource_keep_their_order() -> Result<()> {
+    let context = context()?;
+
+    let (result, ran) = run(
+        &context,
+        &[
+            ("scene", &[], &[render::HDR, render::DEPTH]),
+            ("debug_view", &[render::DEPTH], &[render::HDR]),
+            ("gui", &[], &[render::SURFACE]),
+            ("tone_mapping", &[render::HDR], &[render::SURFACE]),
+        ],
+    );
+
+    result?;
+    // gui and tone_mapping only share the surface they both write, so they keep the order they were added in
+    assert_eq!(ran, vec!["scene", "debug_view", "gui", "tone_mapping"]);
+
+    Ok(())
+}
+
+#[test]
+fn cycle_is_an_error_naming_the_passes() -> Result<()> {
+    let context = context()?;
+
+    let (result, ran) = run(
+        &context,
+        &[
+            ("shadows", &[], &[render::SHADOW_MAP]),
+            ("scene", &[render::DEPTH], &[render::HDR]),
+            ("resolve", &[render::HDR], &[render::DEPTH]),
+        ],
+    );
+
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("\"scene\""), "{error}");
+    assert!(error.contains("\"resolve\""), "{error}");
+    assert!(!error.contains("\"shadows\""), "{error}");
+    // nothing is recorded, not even the passes that could be ordered
+    assert!(ran.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn failing_pass_is_named() -> Result<()> {
+    let context = context()?;
+
+    let mut render_graph = RenderGraph::new();
+    render_graph.add_pass("broken", &[], &[render::HDR], |_| {
+        Err(color_eyre::eyre::eyre!("out of memory"))
+    });
+
+    let error = render_graph.execute(&context.wgpu).unwrap_err().to_string();
+    assert!(error.contains("\"broken\""), "{error}");
+    assert!(error.contains("out of memory"), "{error}");
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+// checks mistakes in wgsl are reported with where they are instead of panicking inside wgpu
+
+use shooter_game::common::debug_view;
+use shooter_game::common::pipeline::{self, Shader, ShaderDefines};
+
+#[test]
+fn bundled_shaders_are_valid() {
+    // the post processing effects are only complete once they're put after the prelude
+    let paths = [
+        "assets/shader.wgsl",
+        "assets/shadow.wgsl",
+        "assets/culling.wgsl",
+        "assets/tone_mapping.wgsl",
+        "assets/luminance_histogram.wgsl",
+    ];
+
+    for path in paths {
+        let shader = pipeline::preprocess(path, &ShaderDefines::new()).unwrap();
+        if let Err(error) = shader.validate() {
+            panic!("{error}");
+        }
+    }
+}
+
+#[test]
+fn mesh_shaders_share_their_inputs() {
+    for path in [
+        "assets/shader.wgsl",
+        "assets/shadow.wgsl",
+        "assets/debug_view.wgsl",
+    ] {
+        let shader = pipeline::preprocess(path, &ShaderDefines::new()).unwrap();
+
+        assert!(
+            shader.files.iter().any(|file| file.ends_with("mesh.wgsl")),
+            "{path} doesn't include mesh.wgsl"
+        );
+        assert_eq!(shader.source.matches("struct VertexInput").count(), 1);
+        assert_eq!(shader.source.matches("struct InstanceInput").count(), 1);
+    }
+}
+
+#[test]
+fn debug_view_permutations_are_valid() {
+    let defines = debug_view::DebugView::ALL
+        .iter()
+        .filter_map(|view| view.define())
+        .chain(["WIREFRAME", "BARYCENTRIC_WIREFRAME"]);
+
+    for define in defines {
+        let shader =
+            pipeline::preprocess("assets/debug_view.wgsl", &ShaderDefines::new().with(define))
+                .unwrap();
+
+        if let Err(error) = shader.validate() {
+            panic!("{define}: {error}");
+        }
+    }
+}
+
+#[test]
+fn parse_error_has_location() {
+    let source = "fn main() {\n    let x = 1.0\n}\n";
+
+    let error = Shader::validate("broken.wgsl", source).unwrap_err();
+    let location = error.location.as_ref().unwrap();
+
+    assert_eq!(location.line, 3);
+    assert_eq!(location.column, 1);
+    assert_eq!(location.source_line, "}");
+    assert!(error.to_string().starts_with("broken.wgsl:3:1: "));
+}
+
+#[test]
+fn validation_error_without_location_has_path() {
+    // parses fine, but a vertex shader has to output a position, naga doesn't say where entry point errors are
+    let source =
+        "@vertex\nfn vs_main() -> @location(0) vec4<f32> {\n    return vec4<f32>(0.0);\n}\n";
+
+    let error = Shader::validate("no_position.wgsl", source).unwrap_err();
+
+    assert!(error.location.is_none());
+    assert!(error.message.contains("vs_main"), "{error}");
+    assert!(error.to_string().starts_with("no_position.wgsl: "));
+}
+
+#[test]
+fn error_is_underlined() {
+    let source = "fn main() {\n\tlet x: f32 = 1u;\n}\n";
+
+    let error = Shader::validate("mismatch.wgsl", source).unwrap_err();
+    let location = error.location.as_ref().unwrap();
+    let lines = error
+        .to_string()
+        .lines()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    assert_eq!(location.line, 2);
+    assert_eq!(lines[1], "\tlet x: f32 = 1u;");
+    // the tab is kept so the underline starts under the mistake
+    let underline_start = location.column as usize - 1;
+    assert_eq!(
+        &lines[2][..underline_start],
+        &format!("\t{}", " ".repeat(underline_start - 1))
+    );
+    assert!(lines[2][underline_start..].chars().all(|c| c == '^'));
+}
+
+#[test]
+fn includes_are_inlined_once() {
+    let shader = pipeline::preprocess("tests/shaders/main.wgsl", &ShaderDefines::new()).unwrap();
+
+    // main.wgsl and lighting.wgsl both include common.wgsl
+    assert_eq!(shader.source.matches("struct VertexInput").count(), 1);
+    assert_eq!(shader.files.len(), 3);
+    assert!(!shader.source.contains('#'));
+    if let Err(error) = shader.validate() {
+        panic!("{error}");
+    }
+}
+
+#[test]
+fn defines_pick_branches() {
+    let shader = pipeline::preprocess("tests/shaders/main.wgsl", &ShaderDefines::new()).unwrap();
+    let flipped = pipeline::preprocess(
+        "tests/shaders/main.wgsl",
+        &ShaderDefines::new().with("FLIP"),
+    )
+    .unwrap();
+
+    assert!(!shader.source.contains("-model.position"));
+    assert!(flipped.source.contains("-model.position"));
+    // defined in main.wgsl before lighting.wgsl is included
+    assert!(shader.source.contains("max(dot(normal"));
+}
+
+#[test]
+fn lines_map_back_to_their_file() {
+    let shader = pipeline::preprocess("tests/shaders/main.wgsl", &ShaderDefines::new()).unwrap();
+
+    let line = shader
+        .source
+        .lines()
+        .position(|line| line.contains("max(dot(normal"))
+        .unwrap() as u32
+        + 1;
+
+    assert_eq!(
+        shader.origin(line),
+        Some(("tests/shaders/lighting/lighting.wgsl", 5))
+    );
+}
+
+#[test]
+fn error_in_include_points_at_include() {
+    let shader = pipeline::preprocess("tests/shaders/broken.wgsl", &ShaderDefines::new()).unwrap();
+
+    let error = shader.validate().unwrap_err();
+
+    assert_eq!(error.path, "tests/shaders/broken_include.wgsl");
+    assert_eq!(error.location.unwrap().line, 3);
+}
+
+#[test]
+fn unterminated_ifdef_fails() {
+    let error =
+        pipeline::preprocess("tests/shaders/unterminated.wgsl", &ShaderDefines::new()).unwrap_err();
+
+    assert!(
+        error.to_string().contains("#ifdef without #endif"),
+        "{error}"
+    );
+}
+
+#[test]
+fn permutation_names_ignore_define_order() {
+    let defines = ShaderDefines::from_iter(["SHADOWS", "FLIP"]);
+
+    assert_eq!(
+        defines.permutation_name("main.wgsl"),
+        "main.wgsl#FLIP,SHADOWS"
+    );
+    assert_eq!(defines, ShaderDefines::new().with("FLIP").with("SHADOWS"));
+    assert_eq!(
+        ShaderDefines::new().permutation_name("main.wgsl"),
+        "main.wgsl"
+    );
+}
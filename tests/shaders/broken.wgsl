@@ -0,0 +1,3 @@
+#include "broken_include.wgsl"
+
+fn main() {}
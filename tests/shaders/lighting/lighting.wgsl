@@ -0,0 +1,11 @@
+#include "../common.wgsl"
+
+#ifdef LIT
+fn light(normal: vec3<f32>) -> f32 {
+    return max(dot(normal, vec3<f32>(0.0, 1.0, 0.0)), 0.0);
+}
+#else
+fn light(normal: vec3<f32>) -> f32 {
+    return 1.0;
+}
+#endif
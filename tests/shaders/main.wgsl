@@ -0,0 +1,25 @@
+#include "common.wgsl"
+#define LIT
+#include "lighting/lighting.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) brightness: f32,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+#ifdef FLIP
+    out.clip_position = vec4<f32>(-model.position, 1.0);
+#else
+    out.clip_position = vec4<f32>(model.position, 1.0);
+#endif
+    out.brightness = light(model.normal);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(vec3<f32>(in.brightness), 1.0);
+}
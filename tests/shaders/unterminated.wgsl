@@ -0,0 +1,2 @@
+#ifdef FLIP
+fn main() {}
@@ -0,0 +1,245 @@
+// simplifies small generated grids and checks what simplify::simplify promises to keep
+
+use cgmath as cg;
+
+use shooter_game::common::model::ModelVertex;
+use shooter_game::common::simplify;
+
+// vertices along each side of the grids
+const GRID_SIZE: u32 = 17;
+const EPSILON: f32 = 1e-5;
+
+fn vertex(x: f32, z: f32, texture_coords: [f32; 2]) -> ModelVertex {
+    ModelVertex {
+        position: [x, height(x, z), z],
+        texture_coords,
+        normal: [0.0, 1.0, 0.0],
+        tangent: [1.0, 0.0, 0.0],
+        bitangent: [0.0, 0.0, 1.0],
+    }
+}
+
+// gentle hills so the collapses have different costs, never steep enough for a triangle to face down
+fn height(x: f32, z: f32) -> f32 {
+    0.3 * (x * 0.7).sin() * (z * 0.5).cos()
+}
+
+// quads from column first_column up to but not including last_column, facing up
+fn grid_triangles(first_column: u32, last_column: u32) -> Vec<u32> {
+    let mut indices = vec![];
+    for z in 0..GRID_SIZE - 1 {
+        for x in first_column..last_column {
+            let corner = z * GRID_SIZE + x;
+            indices.extend([corner, corner + GRID_SIZE, corner + 1]);
+            indices.extend([corner + 1, corner + GRID_SIZE, corner + GRID_SIZE + 1]);
+        }
+    }
+
+    indices
+}
+
+fn grid() -> (Vec<ModelVertex>, Vec<u32>) {
+    let vertices = (0..GRID_SIZE * GRID_SIZE)
+        .map(|i| {
+            let [x, z] = [i % GRID_SIZE, i / GRID_SIZE].map(|coordinate| coordinate as f32);
+            vertex(x, z, [x, z])
+        })
+        .collect();
+
+    (vertices, grid_triangles(0, GRID_SIZE - 1))
+}
+
+fn triangle_normals(vertices: &[ModelVertex], indices: &[u32]) -> Vec<cg::Vector3<f32>> {
+    indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let [a, b, c] =
+                [0, 1, 2].map(|i| cg::Vector3::from(vertices[triangle[i] as usize].position));
+            (b - a).cross(c - a)
+        })
+        .collect()
+}
+
+// seen from above, the hills don't change it
+fn projected_area(vertices: &[ModelVertex], indices: &[u32]) -> f32 {
+    triangle_normals(vertices, indices)
+        .iter()
+        .map(|normal| normal.y / 2.0)
+        .sum()
+}
+
+fn on_border(vertex: &ModelVertex) -> bool {
+    let [x, _, z] = vertex.position;
+    let last = (GRID_SIZE - 1) as f32;
+
+    x == 0.0 || z == 0.0 || x == last || z == last
+}
+
+#[test]
+fn triangle_count_is_near_target_ratio() {
+    let (vertices, indices) = grid();
+    let triangle_count = indices.len() / 3;
+
+    for target_ratio in [0.75, 0.5, 0.25] {
+        let (_, simplified_indices) = simplify::simplify(&vertices, &indices, target_ratio);
+
+        let ratio = (simplified_indices.len() / 3) as f32 / triangle_count as f32;
+        assert!(
+            (ratio - target_ratio).abs() < 0.05,
+            "{target_ratio} gave {ratio}"
+        );
+    }
+}
+
+#[test]
+fn vertices_are_kept_not_moved() {
+    let (vertices, indices) = grid();
+
+    let (simplified_vertices, _) = simplify::simplify(&vertices, &indices, 0.25);
+
+    // edges collapse onto one of their ends
+    for vertex in simplified_vertices.iter() {
+        assert!(
+            vertices
+                .iter()
+                .any(|original| original.position == vertex.position
+                    && original.texture_coords == vertex.texture_coords),
+            "{vertex:?}"
+        );
+    }
+}
+
+#[test]
+fn border_keeps_its_shape() {
+    let (vertices, indices) = grid();
+
+    let (simplified_vertices, simplified_indices) = simplify::simplify(&vertices, &indices, 0.25);
+
+    // a vertex leaving the border or a corner being cut off would leave a gap
+    let border_vertices = simplified_vertices
+        .iter()
+        .filter(|vertex| on_border(vertex))
+        .count();
+    assert!(border_vertices >= 4);
+    let last = (GRID_SIZE - 1) as f32;
+    for corner in [[0.0, 0.0], [last, 0.0], [0.0, last], [last, last]] {
+        assert!(simplified_vertices
+            .iter()
+            .any(|vertex| [vertex.position[0], vertex.position[2]] == corner));
+    }
+    assert!(
+        (projected_area(&simplified_vertices, &simplified_indices) - last * last).abs() < EPSILON
+    );
+}
+
+#[test]
+fn material_boundary_still_lines_up() {
+    let (vertices, _) = grid();
+    let middle = GRID_SIZE / 2;
+
+    // two meshes sharing the column of vertices at x = middle, like two materials of the same model
+    let halves = [
+        grid_triangles(0, middle),
+        grid_triangles(middle, GRID_SIZE - 1),
+    ]
+    .map(|indices| simplify::simplify(&vertices, &indices, 0.5));
+
+    let boundary = halves.each_ref().map(|(vertices, _)| {
+        let mut boundary = vertices
+            .iter()
+            .filter(|vertex| vertex.position[0] == middle as f32)
+            .map(|vertex| vertex.position.map(f32::to_bits))
+            .collect::<Vec<_>>();
+        boundary.sort();
+        boundary
+    });
+
+    // any vertex only one side kept would leave a crack between the meshes
+    assert!(boundary[0].len() >= 2);
+    assert_eq!(boundary[0], boundary[1]);
+    let full_area = projected_area(&vertices, &grid_triangles(0, GRID_SIZE - 1));
+    let simplified_area = halves
+        .iter()
+        .map(|(vertices, indices)| projected_area(vertices, indices))
+        .sum::<f32>();
+    assert!((simplified_area - full_area).abs() < EPSILON);
+}
+
+#[test]
+fn uv_seam_stays_split() {
+    let (mut vertices, _) = grid();
+    let middle = GRID_SIZE / 2;
+
+    // the right half gets its own copies of the middle column with u starting again from 0, like a cylinder's seam
+    let mut right_half = grid_triangles(middle, GRID_SIZE - 1);
+    for z in 0..GRID_SIZE {
+        let seam_vertex = vertices.len() as u32;
+        vertices.push(vertex(middle as f32, z as f32, [0.0, z as f32]));
+
+        for index in right_half.iter_mut() {
+            if *index == z * GRID_SIZE + middle {
+                *index = seam_vertex;
+            }
+        }
+    }
+    for vertex in vertices[..(GRID_SIZE * GRID_SIZE) as usize].iter_mut() {
+        if vertex.position[0] > middle as f32 {
+            vertex.texture_coords[0] -= middle as f32;
+        }
+    }
+    let indices = [grid_triangles(0, middle), right_half].concat();
+
+    let (simplified_vertices, simplified_indices) = simplify::simplify(&vertices, &indices, 0.5);
+
+    let seam = simplified_vertices
+        .iter()
+        .filter(|vertex| vertex.position[0] == middle as f32)
+        .collect::<Vec<_>>();
+    assert!(seam.len() >= 4);
+    // the vertices on the seam are still in pairs, one for each side
+    for vertex in seam.iter() {
+        let [_, _, z] = vertex.position;
+        let expected_other_u = if vertex.texture_coords[0] == 0.0 {
+            middle as f32
+        } else {
+            0.0
+        };
+        assert!(seam
+            .iter()
+            .any(|other| other.position[2] == z && other.texture_coords[0] == expected_other_u));
+    }
+    // and each triangle only uses the ones from its own side
+    for triangle in simplified_indices.chunks_exact(3) {
+        let triangle = [0, 1, 2].map(|i| simplified_vertices[triangle[i] as usize]);
+        let centre_x = triangle
+            .iter()
+            .map(|vertex| vertex.position[0])
+            .sum::<f32>()
+            / 3.0;
+
+        for vertex in triangle {
+            let x = vertex.position[0];
+            let expected_u = if centre_x > middle as f32 {
+                x - middle as f32
+            } else {
+                x
+            };
+            assert_eq!(vertex.texture_coords[0], expected_u, "{triangle:?}");
+        }
+    }
+}
+
+#[test]
+fn no_triangles_flip() {
+    let (vertices, indices) = grid();
+
+    for target_ratio in [0.5, 0.1] {
+        let (simplified_vertices, simplified_indices) =
+            simplify::simplify(&vertices, &indices, target_ratio);
+
+        // every triangle of the grid faces up, one facing down was turned over
+        for normal in triangle_normals(&simplified_vertices, &simplified_indices) {
+            assert!(normal.y > 0.0, "{normal:?}");
+        }
+    }
+}